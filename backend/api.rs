@@ -1,27 +1,47 @@
-use std::{env, time::Instant};
+use std::{sync::Arc, time::Instant};
 
-use crate::graph::mongo_graph_builder::MongoGraphBuilder;
-use crate::graph::{location::Location, path::Path, tfl_graph::TflGraph};
+use crate::config::Config;
+use crate::graph::disruptions::LiveDisruptions;
+use crate::graph::graph_source::GraphSource;
+use crate::graph::live_graph::{GraphStatus, LiveGraph};
+use crate::graph::{journey::Journey, location::Location, path::Path};
+use crate::national_rail::push_port_consumer::PushPortConsumer;
+use crate::tfl::client::{Client, TFLClient};
+use crate::tfl::fixture_client::ReplayClient;
+use crate::tfl::live_status::LiveStatusPoller;
+use crate::util::time::parse_start;
+use crate::ServeArgs;
 use anyhow::Result;
-use chrono::NaiveTime;
+use chrono::NaiveDateTime;
 use rocket::fairing::{Fairing, Info, Kind};
-use rocket::http::Header;
-use rocket::{get, routes, serde::json::Json, State};
+use rocket::http::{Header, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::{get, post, routes, serde::json::Json, State};
 use rocket::{Request, Response};
-use tokio::sync::RwLock;
 
 #[get("/")]
 pub fn wake_up() -> &'static str {
     "awake"
 }
 
-#[get("/traveltime/<loc_string>/<time_str>")]
+/// `time_str` is HH:MM, or "now". `date` is YYYY-MM-DD and defaults to today,
+/// so that planned closures on that day apply. Live delays, cancellations and
+/// line status only apply to queries for today.
+#[get("/traveltime/<loc_string>/<time_str>?<date>")]
 pub async fn get_travel_time(
     loc_string: String,
     time_str: String,
-    graph: &State<RwLock<TflGraph>>,
+    date: Option<String>,
+    graph: &State<Arc<LiveGraph>>,
+    disruptions: &State<Arc<LiveDisruptions>>,
 ) -> Json<Vec<Path>> {
-    let start_time = NaiveTime::parse_from_str(&time_str, "%H:%M").unwrap();
+    let start = match parse_start(&time_str, date.as_deref()) {
+        Ok(start) => start,
+        Err(e) => {
+            println!("{}", e);
+            return Json(vec![]);
+        }
+    };
 
     println!("{}", loc_string);
     let locs: Result<Vec<_>> = loc_string
@@ -34,9 +54,12 @@ pub async fn get_travel_time(
 
     let result = match locs {
         Ok(coords_list) => graph
+            .current()
+            .await
+            .graph
             .write()
             .await
-            .travel_times_from_locs(coords_list, start_time),
+            .travel_times_from_locs(coords_list, start, &*disruptions.for_start(start).await),
         Err(e) => {
             println!("{}", e);
             vec![]
@@ -46,27 +69,190 @@ pub async fn get_travel_time(
     Json(result)
 }
 
-pub async fn rocket() -> Result<()> {
-    let port = env::var("PORT")
-        .unwrap_or_else(|_| "3001".to_string())
-        .parse::<usize>()?;
+/// The quickest journey from `from` to `to`, each given as `lat,lon`, with
+/// its legs. `at` and `date` are as for `/traveltime`. Not found if there is
+/// no way there.
+#[get("/journey?<from>&<to>&<at>&<date>")]
+pub async fn get_journey(
+    from: String,
+    to: String,
+    at: String,
+    date: Option<String>,
+    graph: &State<Arc<LiveGraph>>,
+    disruptions: &State<Arc<LiveDisruptions>>,
+) -> Option<Json<Journey>> {
+    let (from, to, start) = parse_journey(&from, &to, &at, date.as_deref())?;
+
+    graph
+        .current()
+        .await
+        .graph
+        .write()
+        .await
+        .journey(from, to, start, &*disruptions.for_start(start).await)
+        .map(Json)
+}
+
+/// Up to `count` (3 by default) different ways from `from` to `to`, by
+/// arrival: the quickest journey, ones with fewer changes or other lines, and
+/// later departures. Parameters are otherwise as for `/journey`.
+#[allow(clippy::too_many_arguments)]
+#[get("/journeys?<from>&<to>&<at>&<date>&<count>")]
+pub async fn get_journeys(
+    from: String,
+    to: String,
+    at: String,
+    date: Option<String>,
+    count: Option<usize>,
+    graph: &State<Arc<LiveGraph>>,
+    disruptions: &State<Arc<LiveDisruptions>>,
+    config: &State<Config>,
+) -> Option<Json<Vec<Journey>>> {
+    let (from, to, start) = parse_journey(&from, &to, &at, date.as_deref())?;
+    let settings = &config.alternatives;
+    let count = count.unwrap_or(3).clamp(1, settings.max_journeys);
+
+    let journeys = graph.current().await.graph.write().await.alternatives(
+        from,
+        to,
+        start,
+        &*disruptions.for_start(start).await,
+        count,
+        settings,
+    );
+    Some(Json(journeys))
+}
+
+/// The journeys from `from` to `to` that none other beats on arrival, fare
+/// and changes at once, with their pay as you go fares, by arrival.
+/// Parameters are as for `/journey`.
+#[get("/journey/pareto?<from>&<to>&<at>&<date>")]
+pub async fn get_pareto_journeys(
+    from: String,
+    to: String,
+    at: String,
+    date: Option<String>,
+    graph: &State<Arc<LiveGraph>>,
+    disruptions: &State<Arc<LiveDisruptions>>,
+    config: &State<Config>,
+) -> Option<Json<Vec<Journey>>> {
+    let (from, to, start) = parse_journey(&from, &to, &at, date.as_deref())?;
+
+    let journeys = graph.current().await.graph.write().await.pareto_journeys(
+        from,
+        to,
+        start,
+        &*disruptions.for_start(start).await,
+        &config.fares,
+    );
+    Some(Json(journeys))
+}
+
+fn parse_journey(
+    from: &str,
+    to: &str,
+    at: &str,
+    date: Option<&str>,
+) -> Option<(Location, Location, NaiveDateTime)> {
+    let start = match parse_start(at, date) {
+        Ok(start) => start,
+        Err(e) => {
+            println!("{}", e);
+            return None;
+        }
+    };
+    match (Location::try_parse_loc(from), Location::try_parse_loc(to)) {
+        (Some(from), Some(to)) => Some((from, to, start)),
+        _ => {
+            println!("Locations could not be parsed: {} and {}", from, to);
+            None
+        }
+    }
+}
+
+#[get("/graph/status")]
+pub async fn graph_status(graph: &State<Arc<LiveGraph>>) -> Json<GraphStatus> {
+    Json(graph.status().await)
+}
+
+/// Rebuilds the graph from its source in the background and swaps it in once done.
+#[post("/admin/reload")]
+pub async fn reload_graph(
+    _admin: Admin,
+    graph: &State<Arc<LiveGraph>>,
+    source: &State<GraphSource>,
+    config: &State<Config>,
+) -> (Status, Json<GraphStatus>) {
+    if !graph.try_start_build().await {
+        return (Status::Conflict, Json(graph.status().await));
+    }
 
-    println!("PORT: {:#?}", port);
+    let live = graph.inner().clone();
+    let source = source.inner().clone();
+    let routing = config.routing.clone();
+    tokio::spawn(async move {
+        println!("Rebuilding graph from {}", source);
+        let now = Instant::now();
+        // Built in its own task so that a panic still ends the build, or
+        // no other could be started.
+        let result = match tokio::spawn(async move { source.build_graph(&routing).await }).await {
+            Ok(result) => result,
+            Err(e) => Err(anyhow::anyhow!("The build panicked: {}", e)),
+        };
+        match &result {
+            Ok(_) => println!("Done rebuilding graph in {}ms", now.elapsed().as_millis()),
+            Err(e) => println!("Failed to rebuild graph: {}", e),
+        }
+        live.finish_build(result).await;
+    });
+
+    (Status::Accepted, Json(graph.status().await))
+}
 
-    let graph_builder = MongoGraphBuilder::from_env_var().await?;
+pub async fn rocket(config: Config, source: GraphSource, args: ServeArgs) -> Result<()> {
+    println!("PORT: {:#?}", config.server.port);
 
-    println!("Building graph");
+    println!("Building graph from {}", source);
     let now = Instant::now();
-    let graph = RwLock::new(graph_builder.build_graph().await?);
+    let graph = Arc::new(LiveGraph::new(source.build_graph(&config.routing).await?));
     println!("Done building graph in {}ms", now.elapsed().as_millis());
 
-    let config = rocket::Config::figment()
-        .merge(("port", port))
-        .merge(("address", "0.0.0.0"));
+    let disruptions = Arc::new(LiveDisruptions::default());
+    let mut consumer = PushPortConsumer::new(graph.clone(), disruptions.clone(), &config.push_port);
+    if let Some(dir) = args.push_port_replay {
+        consumer.replay(&dir).await?;
+    }
+    if args.push_port {
+        tokio::spawn(consumer.run(config.push_port.clone()));
+    }
+
+    if let Some(dir) = args.tfl_live_replay {
+        spawn_live_status(ReplayClient::new(dir), &config, disruptions.clone())?;
+    } else if args.tfl_live {
+        spawn_live_status(TFLClient::new(&config.tfl)?, &config, disruptions.clone())?;
+    }
+
+    let rocket_config = rocket::Config::figment()
+        .merge(("port", config.server.port))
+        .merge(("address", config.server.address.clone()));
 
-    let _rocket = rocket::custom(config)
-        .mount("/", routes![wake_up, get_travel_time])
+    let _rocket = rocket::custom(rocket_config)
+        .mount(
+            "/",
+            routes![
+                wake_up,
+                get_travel_time,
+                get_journey,
+                get_journeys,
+                get_pareto_journeys,
+                graph_status,
+                reload_graph
+            ],
+        )
         .manage(graph)
+        .manage(disruptions)
+        .manage(source)
+        .manage(config)
         .attach(Cors)
         .ignite()
         .await?
@@ -76,6 +262,46 @@ pub async fn rocket() -> Result<()> {
     Ok(())
 }
 
+/// The TfL client's futures aren't `Send`, so the poller gets a thread and
+/// runtime of its own.
+fn spawn_live_status<C: Client + Send + 'static>(
+    client: C,
+    config: &Config,
+    disruptions: Arc<LiveDisruptions>,
+) -> Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let poller = LiveStatusPoller::new(client, config.tfl_live.clone());
+    std::thread::spawn(move || runtime.block_on(poller.run(disruptions)));
+    Ok(())
+}
+
+/// Request guard for admin endpoints. Requires the `X-Admin-Token` header to
+/// match `server.admin_token`. If that isn't set, admin endpoints are disabled.
+pub struct Admin;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let expected = match request
+            .rocket()
+            .state::<Config>()
+            .and_then(|c| c.server.admin_token.as_ref())
+        {
+            Some(token) if !token.is_empty() => token,
+            _ => return Outcome::Error((Status::NotFound, "Admin endpoints are disabled")),
+        };
+
+        match request.headers().get_one("X-Admin-Token") {
+            Some(token) if token == expected.as_str() => Outcome::Success(Admin),
+            _ => Outcome::Error((Status::Unauthorized, "Invalid admin token")),
+        }
+    }
+}
+
 pub struct Cors;
 
 #[rocket::async_trait]
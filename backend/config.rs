@@ -0,0 +1,683 @@
+use std::{env, path::PathBuf, str::FromStr};
+
+use anyhow::{bail, Context, Result};
+use figment::{
+    providers::{Env, Format, Serialized, Toml},
+    Figment,
+};
+use rusoto_core::Region;
+use serde::{Deserialize, Serialize};
+
+use crate::tfl::model::stops_response::TransportMode;
+
+/// Settings for every part of the app.
+///
+/// Values are layered: built-in defaults, then `TravelTime.toml` (or the file
+/// named by `TRAVEL_TIME_CONFIG`), then `TRAVEL_TIME_` environment variables
+/// with `__` separating sections, e.g. `TRAVEL_TIME_ROUTING__WALKING_RADIUS_METRES`.
+/// The older `MONGO_URI`, `TFL_CLIENT_URI`, `PORT` and `ADMIN_TOKEN` variables
+/// are still honoured.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Config {
+    pub storage: StorageConfig,
+    pub mongo: MongoConfig,
+    pub tfl: TflConfig,
+    pub naptan: NaptanConfig,
+    pub transxchange: TransXChangeConfig,
+    pub national_rail: NationalRailConfig,
+    pub push_port: PushPortConfig,
+    pub tfl_live: TflLiveConfig,
+    pub routing: RoutingConfig,
+    pub alternatives: AlternativesConfig,
+    pub fares: FareConfig,
+    pub validation: ValidationConfig,
+    pub diff: DiffConfig,
+    pub server: ServerConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageConfig {
+    /// Where ingested data is stored and graphs are built from.
+    pub backend: StorageBackend,
+    /// Root directory of the `files` backend.
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// The MongoDB at `mongo.local_uri`.
+    Mongo,
+    /// JSON files under `storage.path`. Needs no database.
+    Files,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MongoConfig {
+    /// The MongoDB that data is ingested into and graphs are built from.
+    pub local_uri: String,
+    /// The hosted MongoDB that is served from.
+    pub atlas_uri: Option<String>,
+    pub app_name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TflConfig {
+    pub base_url: String,
+    pub app_key: Option<String>,
+    /// TfL allows 500 requests a minute per app key.
+    pub requests_per_minute: u32,
+    /// How often a request is retried after a 429, 5xx or timeout.
+    pub max_retries: u32,
+    /// The longest wait before a retry, whether backing off or as asked by a
+    /// 429's `Retry-After`. Requests asked to wait longer fail.
+    pub max_retry_wait_seconds: u64,
+    pub timeout_seconds: u64,
+    /// Modes whose planned closures are loaded.
+    pub closure_modes: Vec<TransportMode>,
+    /// How many days ahead planned closures are loaded for.
+    pub closure_days: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NaptanConfig {
+    /// The unzipped CSV download of NaPTAN.
+    pub path: PathBuf,
+    /// Only stops whose codes start with one of these are loaded.
+    pub atco_prefixes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransXChangeConfig {
+    /// A directory or zip of TransXChange files, e.g. TfL's bus timetables.
+    pub path: PathBuf,
+    /// Services of other modes are skipped.
+    pub modes: Vec<String>,
+    /// How many days from today trips are loaded for.
+    pub calendar_days: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NationalRailConfig {
+    pub s3_bucket: String,
+    pub s3_region: String,
+    pub s3_prefix: String,
+    /// Suffix of the timetable file to pick from the bucket.
+    pub s3_timetable_suffix: String,
+    /// Where the timetable downloaded from S3 is saved.
+    pub download_path: PathBuf,
+    /// The timetable that is loaded into Mongo, plain or gzipped XML.
+    pub timetable_path: PathBuf,
+    /// Suffix of the Darwin reference data file to pick from the bucket.
+    pub s3_reference_suffix: String,
+    /// Where the Darwin reference data is downloaded to and read from.
+    pub reference_path: PathBuf,
+    /// NaPTAN's `RailReferences.csv`, from the NaPTAN CSV download.
+    pub rail_references_path: PathBuf,
+    /// Where the TIPLOCs that don't match a stop point are reported.
+    pub unmatched_report_path: PathBuf,
+    /// An ATOC CIF timetable bundle (zip), as an alternative to the Darwin
+    /// timetable.
+    pub cif_path: PathBuf,
+    /// How many days from today the CIF timetable is loaded for.
+    pub calendar_days: u32,
+}
+
+/// The Darwin Push Port STOMP feed of live national rail running.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PushPortConfig {
+    pub host: String,
+    pub port: u16,
+    pub topic: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// How often the delays served to queries are refreshed.
+    pub publish_interval_seconds: u64,
+}
+
+/// Live TfL line status and arrival predictions, applied to "now" queries.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TflLiveConfig {
+    /// Modes whose line status is polled.
+    pub status_modes: Vec<TransportMode>,
+    /// Stations whose arrival predictions are polled, e.g. busy origins.
+    pub arrival_stop_ids: Vec<String>,
+    pub poll_interval_seconds: u64,
+    /// Polled status is dropped if it hasn't been refreshed for this long.
+    pub ttl_seconds: u64,
+    /// How far past the time of polling the status is applied.
+    pub horizon_minutes: u16,
+    pub minor_delay_minutes: u16,
+    pub severe_delay_minutes: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutingConfig {
+    /// Stations closer than this are connected by walking edges.
+    pub walking_radius_metres: f64,
+    pub walking_speed_metres_per_minute: f64,
+    /// Route between stations rather than their platforms, by moving the
+    /// connections of a platform onto the station NaPTAN places it in.
+    /// Otherwise stops are linked to their station by walking edges.
+    pub collapse_platforms: bool,
+    /// Rail replacement buses are slower than the trains they replace.
+    pub replacement_bus_speed_metres_per_minute: f64,
+    pub replacement_bus_interval_minutes: u16,
+    /// How the departures along timetabled edges are held in memory.
+    pub departure_storage: DepartureStorage,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DepartureStorage {
+    /// The minutes until the next departure from every minute of the day.
+    /// About 2.8 KB an edge, and the quickest to look up.
+    Table,
+    /// Just the departures, searched when looked up.
+    #[default]
+    Sorted,
+}
+
+/// How `/journeys` picks alternatives to the quickest journey.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlternativesConfig {
+    /// Journeys that board their first line at the same time have to differ
+    /// in at least this share of the stops they ride between.
+    pub dissimilarity: f64,
+    /// Added to every boarding when looking for a journey with fewer changes.
+    pub change_penalty_minutes: u16,
+    /// The most journeys returned at once.
+    pub max_journeys: usize,
+}
+
+/// Pay as you go fares in London, in pence, and how far `/journey/pareto`
+/// looks for cheaper journeys or ones with fewer changes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FareConfig {
+    /// A bus or tram fare, which covers any other buses and trams boarded
+    /// within `hopper_minutes`.
+    pub bus_pence: u32,
+    pub hopper_minutes: u16,
+    /// The most a day of only buses and trams costs.
+    pub bus_cap_pence: u32,
+    /// Tube, DLR, Overground, Elizabeth line and rail fares by the zones
+    /// travelled through.
+    pub zone_fares: Vec<ZoneFare>,
+    /// The most a day of travel within the zones costs, buses included.
+    pub zone_caps: Vec<ZoneCap>,
+    /// How much later than the quickest journey others may arrive.
+    pub max_extra_minutes: u16,
+    pub max_changes: u8,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZoneFare {
+    pub low_zone: u8,
+    pub high_zone: u8,
+    pub peak: u32,
+    pub off_peak: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZoneCap {
+    pub low_zone: u8,
+    pub high_zone: u8,
+    pub pence: u32,
+}
+
+/// Thresholds of the `validate` command.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidationConfig {
+    /// Connections faster than this between their stops are reported.
+    pub max_speed_metres_per_minute: f64,
+    /// Stops closer than this that aren't part of the same station are
+    /// reported as duplicates.
+    pub duplicate_stop_metres: f64,
+    /// Where the JSON report is written.
+    pub report_path: PathBuf,
+}
+
+/// What the `diff` command reports.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiffConfig {
+    /// Stations whose travel times to everywhere else are compared.
+    pub probe_stop_ids: Vec<String>,
+    /// Edges whose duration changes by at least this are reported.
+    pub duration_change_minutes: f64,
+    /// Edges whose number of departures changes by at least this fraction
+    /// are reported.
+    pub frequency_change_ratio: f64,
+    /// Travel times from a probe that shift by at least this are reported.
+    pub travel_time_change_minutes: u16,
+    /// Where the JSON report is written.
+    pub report_path: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerConfig {
+    pub address: String,
+    pub port: u16,
+    /// Token required by admin endpoints. They are disabled if unset.
+    pub admin_token: Option<String>,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: StorageBackend::Mongo,
+            path: "./data/store".into(),
+        }
+    }
+}
+
+impl Default for MongoConfig {
+    fn default() -> Self {
+        Self {
+            local_uri: "mongodb://localhost:27017".into(),
+            atlas_uri: None,
+            app_name: "travel-time".into(),
+        }
+    }
+}
+
+impl Default for TflConfig {
+    fn default() -> Self {
+        Self {
+            base_url: "https://api.tfl.gov.uk/".into(),
+            app_key: None,
+            requests_per_minute: 500,
+            max_retries: 5,
+            max_retry_wait_seconds: 60,
+            timeout_seconds: 30,
+            closure_modes: vec![
+                TransportMode::Tube,
+                TransportMode::Dlr,
+                TransportMode::Overground,
+                TransportMode::ElizabethLine,
+                TransportMode::Tram,
+            ],
+            closure_days: 28,
+        }
+    }
+}
+
+impl Default for NaptanConfig {
+    fn default() -> Self {
+        Self {
+            path: "./data/naptan".into(),
+            atco_prefixes: ["490", "910", "930", "940"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        }
+    }
+}
+
+impl Default for TransXChangeConfig {
+    fn default() -> Self {
+        Self {
+            path: "./data/transxchange".into(),
+            modes: vec!["bus".into()],
+            calendar_days: 7,
+        }
+    }
+}
+
+impl Default for NationalRailConfig {
+    fn default() -> Self {
+        Self {
+            s3_bucket: "darwin.xmltimetable".into(),
+            s3_region: "eu-west-1".into(),
+            s3_prefix: "PPTimetable/".into(),
+            s3_timetable_suffix: "_v8.xml.gz".into(),
+            download_path: "./data/timetable.xml.gz".into(),
+            timetable_path: "./data/timetable.xml.gz".into(),
+            s3_reference_suffix: "_ref_v3.xml.gz".into(),
+            reference_path: "./data/reference.xml.gz".into(),
+            rail_references_path: "./data/RailReferences.csv".into(),
+            unmatched_report_path: "./data/unmatched_tiplocs.csv".into(),
+            cif_path: "./data/ttis.zip".into(),
+            calendar_days: 28,
+        }
+    }
+}
+
+impl Default for PushPortConfig {
+    fn default() -> Self {
+        Self {
+            host: "darwin-dist-44ae45.nationalrail.co.uk".into(),
+            port: 61613,
+            topic: "/topic/darwin.pushport-v16".into(),
+            username: None,
+            password: None,
+            publish_interval_seconds: 10,
+        }
+    }
+}
+
+impl Default for TflLiveConfig {
+    fn default() -> Self {
+        Self {
+            status_modes: vec![
+                TransportMode::Tube,
+                TransportMode::Dlr,
+                TransportMode::Overground,
+                TransportMode::ElizabethLine,
+                TransportMode::Tram,
+            ],
+            arrival_stop_ids: vec![],
+            poll_interval_seconds: 60,
+            ttl_seconds: 300,
+            horizon_minutes: 120,
+            minor_delay_minutes: 5,
+            severe_delay_minutes: 15,
+        }
+    }
+}
+
+impl Default for RoutingConfig {
+    fn default() -> Self {
+        Self {
+            walking_radius_metres: 1000.,
+            walking_speed_metres_per_minute: 80.,
+            collapse_platforms: true,
+            replacement_bus_speed_metres_per_minute: 200.,
+            replacement_bus_interval_minutes: 10,
+            departure_storage: DepartureStorage::default(),
+        }
+    }
+}
+
+impl Default for AlternativesConfig {
+    fn default() -> Self {
+        Self {
+            dissimilarity: 0.3,
+            change_penalty_minutes: 10,
+            max_journeys: 5,
+        }
+    }
+}
+
+impl Default for FareConfig {
+    fn default() -> Self {
+        // Adult pay as you go fares from March 2024.
+        let zone_fares = [
+            (1, 1, 280, 270),
+            (1, 2, 340, 280),
+            (1, 3, 370, 300),
+            (1, 4, 450, 320),
+            (1, 5, 520, 340),
+            (1, 6, 560, 360),
+            (2, 2, 190, 180),
+            (2, 3, 190, 180),
+            (2, 4, 280, 190),
+            (2, 5, 350, 200),
+            (2, 6, 370, 200),
+            (3, 3, 190, 180),
+            (3, 4, 190, 180),
+            (3, 5, 280, 190),
+            (3, 6, 350, 200),
+            (4, 4, 190, 180),
+            (4, 5, 190, 180),
+            (4, 6, 280, 190),
+            (5, 5, 190, 180),
+            (5, 6, 190, 180),
+            (6, 6, 190, 180),
+        ]
+        .into_iter()
+        .map(|(low_zone, high_zone, peak, off_peak)| ZoneFare {
+            low_zone,
+            high_zone,
+            peak,
+            off_peak,
+        })
+        .collect();
+        let zone_caps = [
+            (1, 2, 850),
+            (1, 3, 1000),
+            (1, 4, 1230),
+            (1, 5, 1460),
+            (1, 6, 1560),
+        ]
+        .into_iter()
+        .map(|(low_zone, high_zone, pence)| ZoneCap {
+            low_zone,
+            high_zone,
+            pence,
+        })
+        .collect();
+        Self {
+            bus_pence: 175,
+            hopper_minutes: 60,
+            bus_cap_pence: 525,
+            zone_fares,
+            zone_caps,
+            max_extra_minutes: 30,
+            max_changes: 4,
+        }
+    }
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            // About 250 km/h, faster than any train in the network.
+            max_speed_metres_per_minute: 4200.,
+            duplicate_stop_metres: 3.,
+            report_path: "./data/validation.json".into(),
+        }
+    }
+}
+
+impl Default for DiffConfig {
+    fn default() -> Self {
+        Self {
+            // Oxford Circus, King's Cross St. Pancras, Waterloo and Stratford.
+            probe_stop_ids: ["940GZZLUOXC", "940GZZLUKSX", "940GZZLUWLO", "940GZZLUSTD"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            duration_change_minutes: 1.,
+            frequency_change_ratio: 0.25,
+            travel_time_change_minutes: 5,
+            report_path: "./data/diff.json".into(),
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            address: "0.0.0.0".into(),
+            port: 3001,
+            admin_token: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Result<Self> {
+        Self::from_figment(Self::figment())
+    }
+
+    pub fn figment() -> Figment {
+        let path = env::var("TRAVEL_TIME_CONFIG").unwrap_or_else(|_| "TravelTime.toml".into());
+        Figment::from(Serialized::defaults(Config::default()))
+            .merge(Toml::file(path))
+            .merge(
+                Env::raw()
+                    .only(&["MONGO_URI"])
+                    .map(|_| "mongo.atlas_uri".into()),
+            )
+            .merge(
+                Env::raw()
+                    .only(&["TFL_CLIENT_URI"])
+                    .map(|_| "tfl.app_key".into()),
+            )
+            .merge(Env::raw().only(&["PORT"]).map(|_| "server.port".into()))
+            .merge(
+                Env::raw()
+                    .only(&["ADMIN_TOKEN"])
+                    .map(|_| "server.admin_token".into()),
+            )
+            .merge(
+                Env::prefixed("TRAVEL_TIME_")
+                    .ignore(&["CONFIG"])
+                    .split("__"),
+            )
+    }
+
+    pub fn from_figment(figment: Figment) -> Result<Self> {
+        let config: Config = figment
+            .extract()
+            .map_err(|e| anyhow::anyhow!("Invalid configuration: {}", e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        for (key, uri) in [
+            ("mongo.local_uri", Some(&self.mongo.local_uri)),
+            ("mongo.atlas_uri", self.mongo.atlas_uri.as_ref()),
+        ] {
+            if let Some(uri) = uri {
+                if !uri.starts_with("mongodb://") && !uri.starts_with("mongodb+srv://") {
+                    bail!("Invalid configuration: {} must be a mongodb:// URI", key);
+                }
+            }
+        }
+
+        url::Url::parse(&self.tfl.base_url)
+            .context("Invalid configuration: tfl.base_url is not a valid URL")?;
+        if self.tfl.requests_per_minute == 0
+            || self.tfl.timeout_seconds == 0
+            || self.tfl.max_retry_wait_seconds == 0
+        {
+            bail!("Invalid configuration: tfl.requests_per_minute, tfl.timeout_seconds and tfl.max_retry_wait_seconds must be positive");
+        }
+        if self.tfl_live.poll_interval_seconds == 0
+            || self.tfl_live.ttl_seconds < self.tfl_live.poll_interval_seconds
+        {
+            bail!("Invalid configuration: tfl_live.ttl_seconds must be at least tfl_live.poll_interval_seconds, which must be positive");
+        }
+        self.national_rail.region()?;
+
+        if !is_positive(self.routing.walking_radius_metres) {
+            bail!("Invalid configuration: routing.walking_radius_metres must be positive");
+        }
+        if !is_positive(self.routing.walking_speed_metres_per_minute) {
+            bail!(
+                "Invalid configuration: routing.walking_speed_metres_per_minute must be positive"
+            );
+        }
+        if !is_positive(self.routing.replacement_bus_speed_metres_per_minute)
+            || self.routing.replacement_bus_interval_minutes == 0
+        {
+            bail!("Invalid configuration: routing.replacement_bus_speed_metres_per_minute and routing.replacement_bus_interval_minutes must be positive");
+        }
+        if !(0. ..=1.).contains(&self.alternatives.dissimilarity)
+            || self.alternatives.max_journeys == 0
+        {
+            bail!("Invalid configuration: alternatives.dissimilarity must be between 0 and 1 and alternatives.max_journeys positive");
+        }
+        if self
+            .fares
+            .zone_fares
+            .iter()
+            .map(|fare| (fare.low_zone, fare.high_zone))
+            .chain(
+                self.fares
+                    .zone_caps
+                    .iter()
+                    .map(|cap| (cap.low_zone, cap.high_zone)),
+            )
+            .any(|(low, high)| low == 0 || low > high)
+        {
+            bail!("Invalid configuration: fares.zone_fares and fares.zone_caps need a low_zone of at least 1 and no higher than their high_zone");
+        }
+        if !is_positive(self.validation.max_speed_metres_per_minute)
+            || !is_positive(self.validation.duplicate_stop_metres)
+        {
+            bail!("Invalid configuration: validation.max_speed_metres_per_minute and validation.duplicate_stop_metres must be positive");
+        }
+
+        Ok(())
+    }
+}
+
+fn is_positive(value: f64) -> bool {
+    value.is_finite() && value > 0.
+}
+
+impl MongoConfig {
+    pub fn atlas_uri(&self) -> Result<&str> {
+        match &self.atlas_uri {
+            Some(uri) => Ok(uri),
+            None => {
+                bail!("mongo.atlas_uri is not set. Set it in TravelTime.toml or with MONGO_URI.")
+            }
+        }
+    }
+}
+
+impl TflConfig {
+    pub fn app_key(&self) -> Result<&str> {
+        match &self.app_key {
+            Some(key) => Ok(key),
+            None => {
+                bail!("tfl.app_key is not set. Set it in TravelTime.toml or with TFL_CLIENT_URI.")
+            }
+        }
+    }
+}
+
+impl NationalRailConfig {
+    pub fn region(&self) -> Result<Region> {
+        Region::from_str(&self.s3_region).with_context(|| {
+            format!(
+                "Invalid configuration: national_rail.s3_region {} is not an AWS region",
+                self.s3_region
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_toml(toml: &str) -> Result<Config> {
+        Config::from_figment(
+            Figment::from(Serialized::defaults(Config::default())).merge(Toml::string(toml)),
+        )
+    }
+
+    #[test]
+    fn test_toml_overrides_defaults() {
+        let config = from_toml(
+            r#"
+            [routing]
+            walking_radius_metres = 500.0
+
+            [server]
+            port = 8000
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.routing.walking_radius_metres, 500.);
+        assert_eq!(config.routing.walking_speed_metres_per_minute, 80.);
+        assert_eq!(config.server.port, 8000);
+        assert!(config.tfl.app_key().is_err());
+    }
+
+    #[test]
+    fn test_invalid_values_are_rejected() {
+        assert!(from_toml("[routing]\nwalking_speed_metres_per_minute = 0.0").is_err());
+        assert!(from_toml("[mongo]\nlocal_uri = \"localhost\"").is_err());
+        assert!(from_toml("[national_rail]\ns3_region = \"narnia\"").is_err());
+        assert!(from_toml("[server]\nport = \"eighty\"").is_err());
+        assert!(from_toml("[storage]\nbackend = \"sqlite\"").is_err());
+        assert!(from_toml("[tfl_live]\nttl_seconds = 10").is_err());
+    }
+}
@@ -1,25 +1,35 @@
 use anyhow::Result;
 use futures::TryStreamExt;
-use mongodb::Client;
+use serde::{de::DeserializeOwned, Serialize};
 
-use crate::tfl::model::{direct_connection::DirectConnection, stops_response::StopPoint};
+use crate::{
+    national_rail::crosswalk::TiplocMapping,
+    tfl::model::{
+        direct_connection::DirectConnection, planned_closure::PlannedClosure,
+        stops_response::StopPoint, trip::Trip,
+    },
+};
 
-use super::mongo_repo::MongoRepository;
+use super::{mongo_doc::MongoDoc, storage::Storage};
 
-pub async fn copy_collections(from_client: &Client, to_client: &Client) -> Result<()> {
-    let from: MongoRepository<DirectConnection> = MongoRepository::new(from_client);
-    let to: MongoRepository<DirectConnection> = MongoRepository::new(to_client);
-    to.collection.drop(None).await?;
-
-    let all = from.get_all().await?.try_collect::<Vec<_>>().await?;
-    to.collection.insert_many(all, None).await?;
-
-    let from: MongoRepository<StopPoint> = MongoRepository::new(from_client);
-    let to: MongoRepository<StopPoint> = MongoRepository::new(to_client);
-    to.collection.drop(None).await?;
+pub async fn copy_collections(from: &Storage, to: &Storage) -> Result<()> {
+    copy_collection::<DirectConnection>(from, to).await?;
+    copy_collection::<StopPoint>(from, to).await?;
+    copy_collection::<PlannedClosure>(from, to).await?;
+    copy_collection::<TiplocMapping>(from, to).await?;
+    copy_collection::<Trip>(from, to).await?;
+    Ok(())
+}
 
-    let all = from.get_all().await?.try_collect::<Vec<_>>().await?;
-    to.collection.insert_many(all, None).await?;
+async fn copy_collection<T>(from: &Storage, to: &Storage) -> Result<()>
+where
+    T: Serialize + DeserializeOwned + MongoDoc + Unpin + Send + Sync + 'static,
+{
+    let from_repo = from.repo::<T>();
+    let to_repo = to.repo::<T>();
+    to_repo.clear().await?;
 
+    let all = from_repo.get_all().await?.try_collect::<Vec<_>>().await?;
+    to_repo.insert_many(&all).await?;
     Ok(())
 }
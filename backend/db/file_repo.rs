@@ -0,0 +1,239 @@
+use std::{
+    io::ErrorKind,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures::{stream, stream::BoxStream, StreamExt, TryStreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use tokio::fs;
+
+use super::{mongo_doc::MongoDoc, repository::Repository};
+
+/// Stores every document as a JSON file named after its ID, in
+/// `<root>/<database>/<collection>/`.
+pub struct FileRepository<T> {
+    dir: PathBuf,
+    doc_type: PhantomData<fn() -> T>,
+}
+
+impl<T: MongoDoc> FileRepository<T> {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            dir: root
+                .as_ref()
+                .join(T::database_name())
+                .join(T::collection_name()),
+            doc_type: PhantomData,
+        }
+    }
+}
+
+impl<T> FileRepository<T>
+where
+    T: Serialize + DeserializeOwned + MongoDoc + Send + Sync + 'static,
+{
+    fn path_for(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", encode_file_name(id)))
+    }
+
+    async fn read(path: PathBuf) -> Result<Option<T>> {
+        match fs::read(&path).await {
+            Ok(bytes) => {
+                Ok(Some(serde_json::from_slice(&bytes).with_context(|| {
+                    format!("Could not parse {}", path.display())
+                })?))
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn write(&self, doc: &T) -> Result<()> {
+        fs::create_dir_all(&self.dir).await?;
+        let path = self.path_for(&doc.id());
+        // Write to a temporary file first so that readers never see half a document.
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(doc)?).await?;
+        fs::rename(&tmp_path, &path).await?;
+        Ok(())
+    }
+
+    async fn file_paths(&self) -> Result<Vec<PathBuf>> {
+        let mut entries = match fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut paths = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+}
+
+#[async_trait]
+impl<T> Repository<T> for FileRepository<T>
+where
+    T: Serialize + DeserializeOwned + MongoDoc + Send + Sync + 'static,
+{
+    async fn get_by_id(&self, id: &str) -> Result<Option<T>> {
+        Self::read(self.path_for(id)).await
+    }
+
+    async fn get_by_ids(&self, ids: &[String]) -> Result<Vec<T>> {
+        let mut docs = vec![];
+        for id in ids {
+            if let Some(doc) = self.get_by_id(id).await? {
+                docs.push(doc);
+            }
+        }
+        Ok(docs)
+    }
+
+    async fn get_all(&self) -> Result<BoxStream<'static, Result<T>>> {
+        let paths = self.file_paths().await?;
+        Ok(stream::iter(paths)
+            .then(Self::read)
+            .try_filter_map(|doc| async move { Ok(doc) })
+            .boxed())
+    }
+
+    async fn count(&self) -> Result<u64> {
+        Ok(self.file_paths().await?.len() as u64)
+    }
+
+    async fn insert(&self, doc: &T) -> Result<()> {
+        if fs::try_exists(self.path_for(&doc.id())).await? {
+            anyhow::bail!("Duplicate key {} in {}", doc.id(), T::collection_name());
+        }
+        self.write(doc).await
+    }
+
+    async fn insert_many(&self, docs: &[T]) -> Result<()> {
+        for doc in docs {
+            self.insert(doc).await?;
+        }
+        Ok(())
+    }
+
+    async fn insert_or_replace(&self, doc: &T) -> Result<()> {
+        self.write(doc).await
+    }
+
+    // Not atomic like Mongo's, but files are only for a single local process.
+    async fn add_to_sets(&self, doc: &T, fields: &[&str]) -> Result<()> {
+        let existing = match self.get_by_id(&doc.id()).await? {
+            Some(existing) => existing,
+            None => return self.write(doc).await,
+        };
+        let mut merged = serde_json::to_value(existing)?;
+        let added = serde_json::to_value(doc)?;
+        for field in fields {
+            let values = match added.get(field) {
+                Some(Value::Array(values)) => values,
+                _ => continue,
+            };
+            match merged.get_mut(field) {
+                Some(Value::Array(existing)) => {
+                    for value in values {
+                        if !existing.contains(value) {
+                            existing.push(value.clone());
+                        }
+                    }
+                }
+                _ => merged[field] = Value::Array(values.clone()),
+            }
+        }
+        self.write(&serde_json::from_value(merged)?).await
+    }
+
+    async fn clear(&self) -> Result<()> {
+        match fs::remove_dir_all(&self.dir).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Makes an ID safe to use as a file name by percent-encoding anything
+/// that isn't alphanumeric, `-` or `_`.
+fn encode_file_name(id: &str) -> String {
+    let mut name = String::with_capacity(id.len());
+    for byte in id.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' {
+            name.push(byte as char);
+        } else {
+            name.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tfl::model::direct_connection::DirectConnection;
+
+    use super::*;
+
+    fn connection(origin: &str, destination: &str) -> DirectConnection {
+        let mut con = DirectConnection {
+            origin: origin.into(),
+            destination: destination.into(),
+            duration_minutes: 2.,
+            ..Default::default()
+        };
+        con.set_id();
+        con
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = FileRepository::<DirectConnection>::new(root.path());
+
+        assert_eq!(repo.count().await.unwrap(), 0);
+        repo.insert(&connection("A", "B")).await.unwrap();
+        repo.insert(&connection("B", "C/D")).await.unwrap();
+        assert!(repo.insert(&connection("A", "B")).await.is_err());
+
+        let mut replacement = connection("A", "B");
+        replacement.duration_minutes = 3.;
+        repo.insert_or_replace(&replacement).await.unwrap();
+
+        let con = repo.get_by_id("A-B").await.unwrap().unwrap();
+        assert_eq!(con.duration_minutes, 3.);
+        assert_eq!(
+            repo.get_all()
+                .await
+                .unwrap()
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+
+        let mut more = connection("A", "B");
+        more.duration_minutes = 4.;
+        more.line_ids = vec!["x".into(), "y".into()];
+        repo.add_to_sets(&more, &["line_ids"]).await.unwrap();
+        more.line_ids = vec!["y".into(), "z".into()];
+        repo.add_to_sets(&more, &["line_ids"]).await.unwrap();
+        let con = repo.get_by_id("A-B").await.unwrap().unwrap();
+        assert_eq!(con.duration_minutes, 3.);
+        assert_eq!(con.line_ids, ["x", "y", "z"]);
+
+        repo.clear().await.unwrap();
+        assert_eq!(repo.count().await.unwrap(), 0);
+    }
+}
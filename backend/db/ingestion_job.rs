@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::tfl::model::time_table_request::TimetableRequest;
+
+use super::mongo_doc::MongoDoc;
+
+/// One timetable request of a TfL ingestion, and how it went. These are
+/// kept so that a rerun only fetches the timetables that are still missing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimetableJob {
+    #[serde(rename = "_id", alias = "id")]
+    pub id: String,
+    pub line_id: String,
+    pub mode: String,
+    pub origin: String,
+    pub destination: String,
+    pub status: JobStatus,
+    pub attempts: u32,
+    pub error: Option<String>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JobStatus {
+    /// Not attempted yet.
+    Pending,
+    Done,
+    Failed,
+}
+
+impl TimetableJob {
+    pub fn new(line_id: &str, mode: &str, origin: &str, destination: &str) -> Self {
+        Self {
+            id: format!("{}:{}-{}", line_id, origin, destination),
+            line_id: line_id.into(),
+            mode: mode.into(),
+            origin: origin.into(),
+            destination: destination.into(),
+            status: JobStatus::Pending,
+            attempts: 0,
+            error: None,
+            updated_at: None,
+        }
+    }
+
+    pub fn request(&self) -> TimetableRequest {
+        TimetableRequest::new(
+            self.line_id.as_str(),
+            self.origin.as_str(),
+            self.destination.as_str(),
+        )
+    }
+
+    pub fn record<T>(&mut self, result: &Result<T>) {
+        self.attempts += 1;
+        self.updated_at = Some(Utc::now());
+        match result {
+            Ok(_) => {
+                self.status = JobStatus::Done;
+                self.error = None;
+            }
+            Err(e) => {
+                self.status = JobStatus::Failed;
+                self.error = Some(format!("{:#}", e));
+            }
+        }
+    }
+}
+
+impl MongoDoc for TimetableJob {
+    fn database_name() -> &'static str {
+        "tfl"
+    }
+
+    fn collection_name() -> &'static str {
+        "timetableJobs"
+    }
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Coverage {
+    pub done: usize,
+    pub failed: usize,
+    pub pending: usize,
+}
+
+impl Coverage {
+    fn add(&mut self, status: JobStatus) {
+        match status {
+            JobStatus::Done => self.done += 1,
+            JobStatus::Failed => self.failed += 1,
+            JobStatus::Pending => self.pending += 1,
+        }
+    }
+
+    fn total(&self) -> usize {
+        self.done + self.failed + self.pending
+    }
+}
+
+/// How many timetables were loaded, by mode and then by line.
+pub fn coverage<'a, I>(jobs: I) -> BTreeMap<String, BTreeMap<String, Coverage>>
+where
+    I: IntoIterator<Item = &'a TimetableJob>,
+{
+    let mut by_mode: BTreeMap<String, BTreeMap<String, Coverage>> = BTreeMap::new();
+    for job in jobs {
+        by_mode
+            .entry(job.mode.clone())
+            .or_default()
+            .entry(job.line_id.clone())
+            .or_default()
+            .add(job.status);
+    }
+    by_mode
+}
+
+pub fn print_coverage(coverage: &BTreeMap<String, BTreeMap<String, Coverage>>) {
+    println!("Timetable coverage:");
+    for (mode, lines) in coverage {
+        let done: usize = lines.values().map(|c| c.done).sum();
+        let total: usize = lines.values().map(|c| c.total()).sum();
+        println!("  {}: {}/{} loaded", mode, done, total);
+        for (line, c) in lines {
+            println!(
+                "    {}: {}/{} loaded, {} failed, {} pending",
+                line,
+                c.done,
+                c.total(),
+                c.failed,
+                c.pending
+            );
+        }
+    }
+}
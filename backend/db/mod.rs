@@ -1,5 +1,9 @@
 pub mod atlas_loader;
 pub mod data_fixer;
+pub mod file_repo;
+pub mod ingestion_job;
 pub mod mongo_doc;
 pub mod mongo_repo;
+pub mod repository;
+pub mod storage;
 pub mod tfl_loader;
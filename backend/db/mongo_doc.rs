@@ -1,3 +1,5 @@
+/// A document that can be kept in a `Repository`. The database and
+/// collection names are used by every storage backend, not just Mongo.
 pub trait MongoDoc {
     fn database_name() -> &'static str;
     fn collection_name() -> &'static str;
@@ -1,19 +1,20 @@
 use anyhow::Result;
+use async_trait::async_trait;
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
 use mongodb::{
-    bson::doc,
-    options::{FindOneOptions, InsertOneOptions, ReplaceOptions},
-    Client, Collection, Cursor,
+    bson::{self, doc, Document},
+    options::{FindOneOptions, InsertOneOptions, ReplaceOptions, UpdateOptions},
+    Client, Collection,
 };
 
 use serde::{de::DeserializeOwned, Serialize};
 
-use super::mongo_doc::MongoDoc;
+use super::{mongo_doc::MongoDoc, repository::Repository};
 
 pub struct MongoRepository<T> {
-    pub collection: Collection<T>,
+    collection: Collection<T>,
 }
 
-// TODO: DEREF so that all collection methods are available.
 impl<T> MongoRepository<T>
 where
     T: Serialize + DeserializeOwned + MongoDoc + Unpin + Send + Sync,
@@ -24,29 +25,80 @@ where
             .collection(T::collection_name());
         Self { collection }
     }
+}
 
-    pub async fn get_by_id<S: Into<String>>(&self, id: S) -> Result<Option<T>> {
-        let filter = doc! {"_id": id.into()};
+#[async_trait]
+impl<T> Repository<T> for MongoRepository<T>
+where
+    T: Serialize + DeserializeOwned + MongoDoc + Unpin + Send + Sync + 'static,
+{
+    async fn get_by_id(&self, id: &str) -> Result<Option<T>> {
+        let filter = doc! {"_id": id};
         let options = FindOneOptions::default();
         Ok(self.collection.find_one(filter, options).await?)
     }
 
-    pub async fn insert(&self, doc: &T) -> Result<()> {
+    async fn get_by_ids(&self, ids: &[String]) -> Result<Vec<T>> {
+        let filter = doc! {"_id": {"$in": ids}};
+        let cursor = self.collection.find(filter, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn get_all(&self) -> Result<BoxStream<'static, Result<T>>> {
+        let filter = doc! {};
+        let cursor = self.collection.find(filter, None).await?;
+        Ok(cursor.map_err(anyhow::Error::from).boxed())
+    }
+
+    async fn count(&self) -> Result<u64> {
+        Ok(self.collection.count_documents(None, None).await?)
+    }
+
+    async fn insert(&self, doc: &T) -> Result<()> {
         self.collection
             .insert_one(doc, InsertOneOptions::default())
             .await?;
         Ok(())
     }
 
-    pub async fn insert_or_replace(&self, doc: &T) -> Result<()> {
+    async fn insert_many(&self, docs: &[T]) -> Result<()> {
+        if !docs.is_empty() {
+            self.collection.insert_many(docs, None).await?;
+        }
+        Ok(())
+    }
+
+    async fn insert_or_replace(&self, doc: &T) -> Result<()> {
         let filter = doc! {"_id": doc.id()};
         let options = ReplaceOptions::builder().upsert(true).build();
         self.collection.replace_one(filter, doc, options).await?;
         Ok(())
     }
 
-    pub async fn get_all(&self) -> Result<Cursor<T>> {
-        let filter = doc! {};
-        Ok(self.collection.find(filter, None).await?)
+    async fn add_to_sets(&self, doc: &T, fields: &[&str]) -> Result<()> {
+        let mut on_insert = bson::to_document(doc)?;
+        on_insert.remove("_id");
+        let mut added = Document::new();
+        for field in fields {
+            if let Some(values) = on_insert.remove(field) {
+                added.insert(*field, doc! {"$each": values});
+            }
+        }
+        let mut update = Document::new();
+        if !on_insert.is_empty() {
+            update.insert("$setOnInsert", on_insert);
+        }
+        if !added.is_empty() {
+            update.insert("$addToSet", added);
+        }
+        let filter = doc! {"_id": doc.id()};
+        let options = UpdateOptions::builder().upsert(true).build();
+        self.collection.update_one(filter, update, options).await?;
+        Ok(())
+    }
+
+    async fn clear(&self) -> Result<()> {
+        self.collection.drop(None).await?;
+        Ok(())
     }
 }
@@ -0,0 +1,25 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+
+/// Storage operations for one collection of documents.
+///
+/// Implemented for MongoDB and for plain JSON files on disk, so that
+/// everything except the hosted deployment can run without a database.
+#[async_trait]
+pub trait Repository<T>: Send + Sync {
+    async fn get_by_id(&self, id: &str) -> Result<Option<T>>;
+    async fn get_by_ids(&self, ids: &[String]) -> Result<Vec<T>>;
+    async fn get_all(&self) -> Result<BoxStream<'static, Result<T>>>;
+    async fn count(&self) -> Result<u64>;
+
+    async fn insert(&self, doc: &T) -> Result<()>;
+    async fn insert_many(&self, docs: &[T]) -> Result<()>;
+    async fn insert_or_replace(&self, doc: &T) -> Result<()>;
+    /// Inserts `doc`, or adds the elements of its array `fields` that the
+    /// stored document with the same ID is missing. Its other fields are
+    /// kept as they were.
+    async fn add_to_sets(&self, doc: &T, fields: &[&str]) -> Result<()>;
+    /// Removes all documents.
+    async fn clear(&self) -> Result<()>;
+}
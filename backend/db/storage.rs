@@ -0,0 +1,68 @@
+use std::{fmt::Display, path::PathBuf};
+
+use anyhow::Result;
+use mongodb::options::ClientOptions;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::config::{Config, StorageBackend};
+
+use super::{
+    file_repo::FileRepository, mongo_doc::MongoDoc, mongo_repo::MongoRepository,
+    repository::Repository,
+};
+
+/// The place documents are stored in.
+#[derive(Clone)]
+pub enum Storage {
+    Mongo(mongodb::Client),
+    /// JSON files under this directory, for running without a database.
+    Files(PathBuf),
+}
+
+impl Storage {
+    pub async fn mongo(uri: &str, app_name: &str) -> Result<Self> {
+        // Parse a connection string into an options struct.
+        let mut client_options = ClientOptions::parse(uri).await?;
+        client_options.app_name = Some(app_name.to_string());
+
+        // Get a handle to the deployment.
+        Ok(Storage::Mongo(mongodb::Client::with_options(
+            client_options,
+        )?))
+    }
+
+    /// The storage that data is ingested into and graphs are built from.
+    pub async fn local(config: &Config) -> Result<Self> {
+        match config.storage.backend {
+            StorageBackend::Mongo => {
+                Self::mongo(&config.mongo.local_uri, &config.mongo.app_name).await
+            }
+            StorageBackend::Files => Ok(Storage::Files(config.storage.path.clone())),
+        }
+    }
+
+    /// The hosted MongoDB that is served from.
+    pub async fn atlas(config: &Config) -> Result<Self> {
+        Self::mongo(config.mongo.atlas_uri()?, &config.mongo.app_name).await
+    }
+
+    pub fn repo<T>(&self) -> Box<dyn Repository<T>>
+    where
+        T: Serialize + DeserializeOwned + MongoDoc + Unpin + Send + Sync + 'static,
+    {
+        match self {
+            Storage::Mongo(client) => Box::new(MongoRepository::<T>::new(client)),
+            Storage::Files(root) => Box::new(FileRepository::<T>::new(root)),
+        }
+    }
+}
+
+impl Display for Storage {
+    // Deliberately leaves out the Mongo URI, which contains credentials.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Storage::Mongo(_) => write!(f, "MongoDB"),
+            Storage::Files(root) => write!(f, "files in {}", root.display()),
+        }
+    }
+}
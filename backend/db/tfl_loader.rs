@@ -1,34 +1,42 @@
 use std::collections::HashMap;
 
-use crate::tfl::{
-    client::Client,
-    model::{
-        direct_connection::DirectConnection,
-        line_request::LinesByModeRequest,
-        line_response::RouteEndpoints,
-        stops_request::StopsByModeRequest,
-        stops_response::{StopPoint, TransportMode},
-        time_table_request::TimetableRequest,
-        time_table_response::TimetableResult,
+use crate::{
+    tfl::{
+        client::Client,
+        endpoint::Endpoint,
+        model::{
+            direct_connection::DirectConnection,
+            line_request::{LineStatusBetweenRequest, LinesByModeRequest},
+            line_response::RouteEndpoints,
+            planned_closure::PlannedClosure,
+            stops_request::StopsByModeRequest,
+            stops_response::{StopPoint, TransportMode},
+            time_table_response::{Departure, Interval, TimetableResult},
+            trip::{StopTime, Trip},
+        },
     },
+    util::string_util,
 };
 use anyhow::Result;
-use chrono::{Duration, NaiveTime};
+use chrono::{Duration, NaiveDate, NaiveTime};
 use futures::{future::join_all, stream, StreamExt, TryStreamExt};
-use mongodb::bson::doc;
 
-use super::{mongo_doc::MongoDoc, mongo_repo::MongoRepository};
+use super::{
+    ingestion_job::{coverage, print_coverage, JobStatus, TimetableJob},
+    mongo_doc::MongoDoc,
+    storage::Storage,
+};
 
 pub struct Loader<'a, C: Client> {
     tfl_client: &'a mut C,
-    mongo_client: &'a mongodb::Client,
+    storage: &'a Storage,
 }
 
 impl<'a, C: Client> Loader<'a, C> {
-    pub fn new(tfl_client: &'a mut C, mongo_client: &'a mongodb::Client) -> Self {
+    pub fn new(tfl_client: &'a mut C, storage: &'a Storage) -> Self {
         Self {
             tfl_client,
-            mongo_client,
+            storage,
         }
     }
 
@@ -68,10 +76,10 @@ impl<'a, C: Client> Loader<'a, C> {
             println!("Found {} matching stops", total);
         }
 
-        let mongo_repo = MongoRepository::<StopPoint>::new(self.mongo_client);
-        let existing_doc_count = mongo_repo.collection.count_documents(None, None).await?;
+        let repo = self.storage.repo::<StopPoint>();
+        let existing_doc_count = repo.count().await?;
 
-        // Go through page by page and transfer to Mongo.
+        // Go through page by page and transfer to storage.
         while let Some(stop_points) = response.stop_points {
             if stop_points.is_empty() {
                 // Once the pages are empty, we're done.
@@ -85,7 +93,7 @@ impl<'a, C: Client> Loader<'a, C> {
             let results = join_all(
                 stop_points
                     .iter()
-                    .map(|stop_point| mongo_repo.insert_or_replace(stop_point)),
+                    .map(|stop_point| repo.insert_or_replace(stop_point)),
             )
             .await;
 
@@ -97,43 +105,74 @@ impl<'a, C: Client> Loader<'a, C> {
             }
             request.page += 1;
         }
-        let new_doc_count = mongo_repo.collection.count_documents(None, None).await?;
+        let new_doc_count = repo.count().await?;
 
         println!(
-            "Inserted {} documents into Mongo.",
+            "Inserted {} documents into storage.",
             new_doc_count - existing_doc_count
         );
 
         Ok(())
     }
 
+    /// Replaces the stored planned closures with those TfL lists for lines
+    /// of `modes` between `from` and `to`.
+    pub async fn load_closures(
+        &self,
+        modes: Vec<TransportMode>,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<()> {
+        let request = LineStatusBetweenRequest::new(modes, from, to);
+        let lines = self.tfl_client.query(&request).await?;
+
+        let closures = lines
+            .iter()
+            .flat_map(|line| {
+                line.line_statuses
+                    .iter()
+                    .flat_map(|status| PlannedClosure::from_line_status(&line.id, status))
+            })
+            .map(|closure| (closure.id(), closure))
+            .collect::<HashMap<_, _>>();
+        let closures: Vec<_> = closures.into_values().collect();
+
+        let repo = self.storage.repo::<PlannedClosure>();
+        repo.clear().await?;
+        repo.insert_many(&closures).await?;
+        println!(
+            "Saved {} planned closures between {} and {}.",
+            closures.len(),
+            from,
+            to
+        );
+        Ok(())
+    }
+
     pub async fn load_routes(&mut self) -> Result<()> {
         let all_modes: Vec<TransportMode> = Loader::<'_, C>::stop_point_modes();
         let request = LinesByModeRequest::new(all_modes);
         let mut lines = self.tfl_client.query(&request).await?;
-        let mongo_repo = MongoRepository::<RouteEndpoints>::new(self.mongo_client);
+        let repo = self.storage.repo::<RouteEndpoints>();
 
         for line in &mut lines {
             for sec in &mut line.route_sections {
-                let existing_val = mongo_repo.get_by_id(sec.id()).await?;
-
-                // If a value exists in Mongo, we start with that one.
-                if existing_val.is_some() {
-                    mongo_repo
-                        .collection
-                        .update_one(
-                            doc! {"_id": sec.id()},
-                            doc! {"$addToSet": {"lineIds": line.id.clone()}},
-                            None,
-                        )
-                        .await?;
+                let existing_val = repo.get_by_id(&sec.id()).await?;
+
+                // If a value already exists, we start with that one.
+                if let Some(mut existing) = existing_val {
+                    let line_ids = existing.line_ids.get_or_insert(Vec::new());
+                    if !line_ids.contains(&line.id) {
+                        line_ids.push(line.id.clone());
+                        repo.insert_or_replace(&existing).await?;
+                    }
                     break;
                 }
 
                 // Else we insert a new one.
                 sec.line_ids.get_or_insert(Vec::new()).push(line.id.clone());
                 sec.set_id();
-                mongo_repo.insert(sec).await?;
+                repo.insert(sec).await?;
             }
         }
 
@@ -143,13 +182,13 @@ impl<'a, C: Client> Loader<'a, C> {
     pub async fn load_segments(&mut self) -> Result<()> {
         let request = LinesByModeRequest::new(Loader::<'a, C>::stop_point_modes());
         let lines = self.tfl_client.query(&request).await?;
-        let mongo_repo = MongoRepository::<RouteEndpoints>::new(self.mongo_client);
+        let repo = self.storage.repo::<RouteEndpoints>();
 
         let results = join_all(
             lines
                 .iter()
                 .flat_map(|l| l.route_sections.iter())
-                .map(|sec| mongo_repo.insert_or_replace(sec)),
+                .map(|sec| repo.insert_or_replace(sec)),
         )
         .await;
 
@@ -160,37 +199,75 @@ impl<'a, C: Client> Loader<'a, C> {
         Ok(())
     }
 
-    pub async fn load_timetables(&mut self) -> Result<()> {
-        let routes_repo = MongoRepository::<RouteEndpoints>::new(self.mongo_client);
+    /// Loads the timetables of every route, except for lines of `skip_modes`.
+    pub async fn load_timetables(&mut self, skip_modes: &[&str]) -> Result<()> {
+        let routes_repo = self.storage.repo::<RouteEndpoints>();
         let cursor = routes_repo.get_all().await?;
-
         let routes = cursor.try_collect::<Vec<_>>().await?;
-        let requests = routes
-            .iter()
-            .flat_map(|r| {
-                r.line_ids
-                    .as_ref()
-                    .unwrap()
-                    .iter()
-                    .flat_map(|line_id| {
-                        vec![TimetableRequest::new(
-                            line_id,
-                            r.originator.clone(),
-                            r.destination.clone(),
-                        )]
-                    })
-                    .collect::<Vec<_>>()
-            })
+        let line_modes = self.line_modes().await?;
+
+        // Timetables that earlier runs already loaded are skipped.
+        let jobs_repo = self.storage.repo::<TimetableJob>();
+        let mut jobs = jobs_repo
+            .get_all()
+            .await?
+            .map_ok(|job| (job.id(), job))
+            .try_collect::<HashMap<_, _>>()
+            .await?;
+
+        for r in &routes {
+            for line_id in r.line_ids.as_deref().unwrap_or_default() {
+                let mode = line_modes
+                    .get(line_id)
+                    .map(String::as_str)
+                    .unwrap_or("unknown");
+                let job = TimetableJob::new(line_id, mode, &r.originator, &r.destination);
+                jobs.entry(job.id()).or_insert(job);
+            }
+        }
+        jobs.retain(|_, job| !skip_modes.contains(&job.mode.as_str()));
+
+        let todo = jobs
+            .values()
+            .filter(|job| job.status != JobStatus::Done)
+            .cloned()
             .collect::<Vec<_>>();
+        println!(
+            "{} of {} timetables already loaded, fetching {}.",
+            jobs.len() - todo.len(),
+            jobs.len(),
+            todo.len()
+        );
 
-        println!("Starting...");
-        stream::iter(requests.iter())
-            .map(|r| self.tfl_client.query(r))
+        // The client rate limits and retries, so anything that still fails is
+        // recorded in the job log and retried on the next run.
+        let this = &*self;
+        let jobs_repo = jobs_repo.as_ref();
+        let finished = stream::iter(todo)
+            .map(|job| async move {
+                let result = this.tfl_client.query(&job.request()).await;
+                (job, result)
+            })
             .buffer_unordered(5)
-            .and_then(|result| self.save_direct_connections(result))
-            .collect::<Vec<_>>()
-            .await;
-        println!("Done.");
+            .then(|(mut job, result)| async move {
+                let result = match result {
+                    Ok(timetable) => this.save_direct_connections(timetable).await,
+                    Err(e) => Err(e),
+                };
+                job.record(&result);
+                jobs_repo.insert_or_replace(&job).await?;
+                Ok::<_, anyhow::Error>(job)
+            })
+            .try_collect::<Vec<_>>()
+            .await?;
+
+        for job in finished {
+            if let Some(error) = &job.error {
+                println!("Failed to load {}: {}", job.request().endpoint(), error);
+            }
+            jobs.insert(job.id(), job);
+        }
+        print_coverage(&coverage(jobs.values()));
 
         //let a = vec!["a", "b", "c"];
         //a.iter().map(|l| l.len()).filter(|x| < 2).collect();
@@ -209,20 +286,36 @@ impl<'a, C: Client> Loader<'a, C> {
         Ok(())
     }
 
+    /// The mode of every line, e.g. `district` -> `tube`.
+    async fn line_modes(&self) -> Result<HashMap<String, String>> {
+        let request = LinesByModeRequest::new(Loader::<'a, C>::stop_point_modes());
+        let lines = self.tfl_client.query(&request).await?;
+        lines
+            .iter()
+            .map(|line| {
+                Ok((
+                    line.id.clone(),
+                    string_util::enum_to_string(line.mode_name)?,
+                ))
+            })
+            .collect()
+    }
+
     async fn save_direct_connections(&self, timetable: TimetableResult) -> Result<()> {
         println!("saving {:#?}", timetable.line_name);
         let mut interval_id_to_journeys = HashMap::new();
-        let direct_connection_repo = MongoRepository::<DirectConnection>::new(self.mongo_client);
+        let direct_connection_repo = self.storage.repo::<DirectConnection>();
+        let trip_repo = self.storage.repo::<Trip>();
+        let mut trips = vec![];
 
         let origin = timetable.timetable.departure_stop_id;
 
         for route in &timetable.timetable.routes {
-            let schedule = route.schedules.first();
-            if schedule.is_none() {
+            let Some(schedule) = route.schedules.first() else {
                 continue;
-            }
+            };
 
-            for journey in &schedule.unwrap().known_journeys {
+            for journey in &schedule.known_journeys {
                 interval_id_to_journeys
                     .entry(journey.interval_id.to_string())
                     .or_insert_with(Vec::new)
@@ -249,7 +342,7 @@ impl<'a, C: Client> Loader<'a, C> {
                         .map(|j| {
                             let hr_int = j.hour.parse::<u32>().unwrap() % 24;
                             let min_int = j.minute.parse::<u32>().unwrap();
-                            NaiveTime::from_hms(hr_int, min_int, 0)
+                            NaiveTime::from_hms_opt(hr_int, min_int, 0).unwrap()
                                 + Duration::minutes(total_time_travelled as i64)
                         })
                         .collect::<Vec<NaiveTime>>();
@@ -264,38 +357,232 @@ impl<'a, C: Client> Loader<'a, C> {
                         destination: destination.clone(),
                         duration_minutes: minutes_between_stations,
                         departure_times,
+                        dated_departures: vec![],
+                        line_ids: timetable.line_id.iter().cloned().collect(),
+                        service_ids: vec![],
                     };
-                    direct_connection.set_id();
                     current_stop = destination.clone();
 
-                    if direct_connection_repo
-                        .get_by_id(direct_connection.id())
-                        .await?
-                        .is_none()
-                    {
-                        direct_connection_repo.insert(&direct_connection).await?;
-                        continue;
-                    }
-
-                    // Otherwise, we already have this pair in Mongo and simply add to it.
-                    let departure_time_strings = direct_connection
-                        .departure_times
-                        .iter()
-                        .map(|t| t.to_string())
-                        .collect::<Vec<String>>();
-
-                    direct_connection_repo
-                        .collection
-                        .update_one(
-                            doc! {"_id": direct_connection.id()},
-                            doc! {"$addToSet": {"departure_times": {"$each": departure_time_strings }}},
-                            None,
-                        )
+                    // If we already have this pair stored, this adds to it.
+                    direct_connection
+                        .merge_insert(direct_connection_repo.as_ref())
                         .await?;
                 }
+
+                trips.extend(journeys.iter().map(|j| {
+                    Self::trip(
+                        timetable.line_id.as_deref(),
+                        &schedule.name,
+                        &origin,
+                        &interval.intervals,
+                        j,
+                    )
+                }));
             }
         }
 
+        for trip in &trips {
+            trip_repo.insert_or_replace(trip).await?;
+        }
+
         Ok(())
     }
+
+    // The vehicle leaving `origin` at the time of `departure` and calling at
+    // the stops of `intervals`. TfL timetables aren't dated, so neither is the
+    // trip, but its id names the `schedule` so that weekday and weekend trips
+    // at the same time stay apart.
+    fn trip(
+        line_id: Option<&str>,
+        schedule: &str,
+        origin: &str,
+        intervals: &[Interval],
+        departure: &Departure,
+    ) -> Trip {
+        let hour = departure.hour.parse::<u32>().unwrap();
+        let minute = departure.minute.parse::<u32>().unwrap();
+        // Hours past midnight carry on from 24, which keeps the trip in order.
+        let start = hour * 60 + minute;
+        let stop_time = |stop_id: &str, minutes: u32| StopTime {
+            stop_id: stop_id.to_string(),
+            arrival_minutes: minutes,
+            departure_minutes: minutes,
+            picks_up: true,
+            sets_down: true,
+        };
+        let mut stop_times = vec![stop_time(origin, start)];
+        stop_times.extend(
+            intervals
+                .iter()
+                .map(|section| stop_time(&section.stop_id, start + section.time_to_arrival as u32)),
+        );
+        let last_stop = stop_times.last().unwrap().stop_id.clone();
+        Trip {
+            id: format!(
+                "{}:{}:{}:{}:{:02}{:02}",
+                line_id.unwrap_or_default(),
+                schedule,
+                origin,
+                last_stop,
+                hour,
+                minute
+            ),
+            line_id: line_id.map(String::from),
+            dates: vec![],
+            stop_times,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{db::ingestion_job::Coverage, tfl::fixture_client::ReplayClient};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_load_from_fixtures() {
+        let root = tempfile::tempdir().unwrap();
+        let storage = Storage::Files(root.path().to_path_buf());
+        let mut client = ReplayClient::checked_in();
+        let mut loader = Loader::new(&mut client, &storage);
+
+        loader.load_stops().await.unwrap();
+        assert_eq!(storage.repo::<StopPoint>().count().await.unwrap(), 3);
+
+        loader.load_routes().await.unwrap();
+        let routes = storage
+            .repo::<RouteEndpoints>()
+            .get_all()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(routes.len(), 2);
+        assert!(routes
+            .iter()
+            .all(|r| r.line_ids == Some(vec!["district".to_string()])));
+
+        loader.load_timetables(&[]).await.unwrap();
+        let connection = storage
+            .repo::<DirectConnection>()
+            .get_by_id("940GZZLUHSD-940GZZLURVP")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(connection.duration_minutes, 2.);
+        assert_eq!(
+            connection.departure_times,
+            ["10:00", "10:10", "10:20"]
+                .iter()
+                .map(|t| NaiveTime::parse_from_str(t, "%H:%M").unwrap())
+                .collect::<Vec<_>>()
+        );
+
+        // The second leg departs once the first is travelled.
+        let connection = storage
+            .repo::<DirectConnection>()
+            .get_by_id("940GZZLURVP-940GZZLUSFB")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            connection.departure_times[0],
+            NaiveTime::from_hms_opt(10, 2, 0).unwrap()
+        );
+
+        // Each departure is a trip calling at every stop of its route.
+        let trip = storage
+            .repo::<Trip>()
+            .get_by_id("district:Monday - Friday:940GZZLUHSD:940GZZLUSFB:1010")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            trip.stop_times
+                .iter()
+                .map(|s| (s.stop_id.as_str(), s.departure_minutes))
+                .collect::<Vec<_>>(),
+            [
+                ("940GZZLUHSD", 610),
+                ("940GZZLURVP", 612),
+                ("940GZZLUSFB", 614)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timetables_resume_from_job_log() {
+        let root = tempfile::tempdir().unwrap();
+        let storage = Storage::Files(root.path().to_path_buf());
+        let mut client = ReplayClient::checked_in();
+        Loader::new(&mut client, &storage)
+            .load_routes()
+            .await
+            .unwrap();
+        let jobs = storage.repo::<TimetableJob>();
+
+        // Every timetable fails when the fixtures are missing.
+        let empty = tempfile::tempdir().unwrap();
+        let routes = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/tfl/Line/Mode");
+        copy_dir(&routes, &empty.path().join("Line/Mode"));
+        let mut offline = ReplayClient::new(empty.path());
+        Loader::new(&mut offline, &storage)
+            .load_timetables(&[])
+            .await
+            .unwrap();
+        let failed = jobs
+            .get_all()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(failed.len(), 2);
+        assert!(failed
+            .iter()
+            .all(|j| j.status == JobStatus::Failed && j.attempts == 1 && j.error.is_some()));
+
+        // A rerun retries them, and the one after that has nothing left to do.
+        for _ in 0..2 {
+            Loader::new(&mut client, &storage)
+                .load_timetables(&[])
+                .await
+                .unwrap();
+        }
+        let done = jobs
+            .get_all()
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert!(done
+            .iter()
+            .all(|j| j.status == JobStatus::Done && j.attempts == 2 && j.mode == "tube"));
+        assert_eq!(
+            coverage(&done)["tube"]["district"],
+            Coverage {
+                done: 2,
+                failed: 0,
+                pending: 0
+            }
+        );
+    }
+
+    fn copy_dir(from: &Path, to: &Path) {
+        std::fs::create_dir_all(to).unwrap();
+        for entry in std::fs::read_dir(from).unwrap() {
+            let path = entry.unwrap().path();
+            let target = to.join(path.file_name().unwrap());
+            if path.is_dir() {
+                copy_dir(&path, &target);
+            } else {
+                std::fs::copy(&path, &target).unwrap();
+            }
+        }
+    }
 }
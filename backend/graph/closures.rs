@@ -0,0 +1,35 @@
+use chrono::{DateTime, Utc};
+
+use crate::{db::mongo_doc::MongoDoc, tfl::model::planned_closure::PlannedClosure};
+
+use super::connection::Connection;
+
+/// The planned closures in effect at the start of a query.
+pub struct ActiveClosures<'a> {
+    closures: Vec<&'a PlannedClosure>,
+}
+
+impl<'a> ActiveClosures<'a> {
+    pub fn at(closures: &'a [PlannedClosure], at: DateTime<Utc>) -> Self {
+        Self {
+            closures: closures.iter().filter(|c| c.is_active(at)).collect(),
+        }
+    }
+
+    /// Whether the search may travel along `connection`. Edges are closed
+    /// if every line along them is, and replacement buses only run during
+    /// their closure.
+    pub fn allows(&self, origin: &str, destination: &str, connection: &Connection) -> bool {
+        if let Some(closure_id) = &connection.replacement_for {
+            return self.closures.iter().any(|c| &c.id() == closure_id);
+        }
+        if self.closures.is_empty() || connection.line_ids.is_empty() {
+            return true;
+        }
+        !connection.line_ids.iter().all(|line| {
+            self.closures
+                .iter()
+                .any(|c| c.closes(line, origin, destination))
+        })
+    }
+}
@@ -1,66 +1,335 @@
-use chrono::NaiveTime;
+use std::collections::HashMap;
 
-use crate::tfl::model::direct_connection::DirectConnection;
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+
+use crate::{
+    config::DepartureStorage, tfl::model::direct_connection::DirectConnection, util::time::Seconds,
+};
+
+use super::disruptions::DepartureChange;
 
 #[derive(Debug, Clone)]
 pub struct Connection {
-    pub duration_minutes: u16,
-    // An array where the index is the minute of the day
-    // and the value is the number of minutes until the
-    // next train arrives.
+    pub duration: Seconds,
     pub departure_times: DepartureTime,
+    // The TfL lines running along this edge, so that live line
+    // status can be applied to it.
+    pub line_ids: Vec<String>,
+    // The national rail services running along this edge, sorted.
+    pub service_ids: Vec<String>,
+    // Set on rail replacement buses, which only run during this closure.
+    pub replacement_for: Option<String>,
+    // The trips along this edge, sorted by trip, so that a rider can stay on
+    // the vehicle they boarded.
+    pub trips: Vec<TripDeparture>,
+    // The dates departures run on, sorted, by the minute of the day they
+    // leave at. Departures at other minutes run every day.
+    pub dates: HashMap<u16, Vec<NaiveDate>>,
+}
+
+/// A trip leaving along an edge, in minutes after midnight of the day the
+/// trip starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TripDeparture {
+    pub trip: u32,
+    pub departure: u32,
+    pub arrival: u32,
 }
 
 #[derive(Debug, Clone)]
 pub enum DepartureTime {
     Instantaneous,
+    // An array where the index is the minute of the day
+    // and the value is the number of minutes until the
+    // next train arrives.
     Timetable(Box<[u16; 24 * 60]>),
+    // The minutes of the day trains leave at, sorted. A fraction of the size
+    // of a timetable for all but the most frequent services, but found by
+    // binary search.
+    Sorted(Box<[u16]>),
 }
 
-impl Connection {
-    pub fn from_direct_connection(con: &DirectConnection) -> Self {
-        let mut departure_times_arr = [0; 24 * 60];
-        let midnight = NaiveTime::from_hms(0, 0, 0);
-        let mut start = 0_usize;
-        // Depart times are pre-sorted in Mongo.
-        for depart_time in &con.departure_times {
-            let end = (*depart_time - midnight).num_minutes() as usize;
-            let mut mins_until_depart = end - start;
-            departure_times_arr
-                .iter_mut()
-                .take(end)
-                .skip(start)
-                .for_each(|entry| {
-                    *entry = mins_until_depart as u16;
-                    mins_until_depart -= 1;
-                });
-            /*
-            for i in start..end {
-                departure_times_arr[i] = mins_until_depart as u16;
-                mins_until_depart -= 1;
+impl DepartureTime {
+    /// Departures stored as `storage`. They are usually sorted already, but
+    /// needn't be, nor free of duplicates.
+    pub fn new(departures: &[NaiveTime], storage: DepartureStorage) -> Self {
+        let midnight = NaiveTime::from_hms_opt(0, 0, 0).unwrap();
+        let mut minutes: Vec<usize> = departures
+            .iter()
+            .map(|depart_time| (*depart_time - midnight).num_minutes() as usize)
+            .collect();
+        minutes.sort_unstable();
+        minutes.dedup();
+        let minutes = minutes.into_iter();
+        match storage {
+            DepartureStorage::Table => {
+                let mut departure_times_arr = [0; 24 * 60];
+                let mut start = 0_usize;
+                for end in minutes {
+                    let mut mins_until_depart = end - start;
+                    departure_times_arr
+                        .iter_mut()
+                        .take(end)
+                        .skip(start)
+                        .for_each(|entry| {
+                            *entry = mins_until_depart as u16;
+                            mins_until_depart -= 1;
+                        });
+                    start = end + 1;
+                }
+                DepartureTime::Timetable(Box::new(departure_times_arr))
+            }
+            DepartureStorage::Sorted => {
+                DepartureTime::Sorted(minutes.map(|minute| minute as u16).collect())
+            }
+        }
+    }
+
+    /// Minutes from `minute_of_day` until the next departure. Zero after the
+    /// last departure of the day, as departures don't wrap around midnight.
+    pub fn minutes_to_departure(&self, minute_of_day: usize) -> u16 {
+        match self {
+            DepartureTime::Instantaneous => 0,
+            DepartureTime::Timetable(arr) => arr[minute_of_day],
+            DepartureTime::Sorted(minutes) => {
+                let minute = minute_of_day as u16;
+                let next = minutes.partition_point(|departure| *departure < minute);
+                minutes.get(next).map_or(0, |departure| departure - minute)
             }
-            */
-            start = end + 1;
         }
+    }
 
+    /// Whether something leaves at `minute_of_day`. A timetable can't tell
+    /// the minutes after the last departure apart, so they count too.
+    pub fn departs_at(&self, minute_of_day: usize) -> bool {
+        match self {
+            DepartureTime::Instantaneous => true,
+            DepartureTime::Timetable(arr) => arr[minute_of_day] == 0,
+            DepartureTime::Sorted(minutes) => {
+                minutes.binary_search(&(minute_of_day as u16)).is_ok()
+            }
+        }
+    }
+
+    /// The bytes allocated for the departures.
+    pub fn heap_bytes(&self) -> usize {
+        match self {
+            DepartureTime::Instantaneous => 0,
+            DepartureTime::Timetable(arr) => std::mem::size_of_val(arr.as_ref()),
+            DepartureTime::Sorted(minutes) => std::mem::size_of_val(minutes.as_ref()),
+        }
+    }
+}
+
+impl Connection {
+    pub fn from_direct_connection(con: &DirectConnection) -> Self {
+        Self::with_departures(con, DepartureStorage::default())
+    }
+
+    pub fn with_departures(con: &DirectConnection, storage: DepartureStorage) -> Self {
+        let mut service_ids = con.service_ids.clone();
+        service_ids.sort();
+        service_ids.dedup();
         Self {
-            duration_minutes: con.duration_minutes as u16,
-            departure_times: DepartureTime::Timetable(Box::new(departure_times_arr)),
+            duration: Seconds::from_fractional_minutes(con.duration_minutes),
+            departure_times: DepartureTime::new(&con.departure_times, storage),
+            line_ids: con.line_ids.clone(),
+            service_ids,
+            replacement_for: None,
+            trips: vec![],
+            dates: Self::dates(&con.dated_departures),
         }
     }
 
+    fn dates(departures: &[NaiveDateTime]) -> HashMap<u16, Vec<NaiveDate>> {
+        let mut dates: HashMap<u16, Vec<NaiveDate>> = HashMap::new();
+        for departure in departures {
+            let minute = departure.hour() * 60 + departure.minute();
+            dates
+                .entry(minute as u16)
+                .or_default()
+                .push(departure.date());
+        }
+        for dates in dates.values_mut() {
+            dates.sort();
+            dates.dedup();
+        }
+        dates
+    }
+
+    pub fn is_timetabled(&self) -> bool {
+        !matches!(self.departure_times, DepartureTime::Instantaneous)
+    }
+
+    /// Whether a line or service running along this edge carries on along
+    /// `next`, so that passengers needn't change.
+    pub fn continues_into(&self, next: &Connection) -> bool {
+        self.line_ids
+            .iter()
+            .any(|line| next.line_ids.contains(line))
+            || self
+                .service_ids
+                .iter()
+                .any(|service| next.service_ids.binary_search(service).is_ok())
+    }
+
     pub fn get_minutes_to_departure(&self, minutes_since_midnight: usize) -> u16 {
-        match &self.departure_times {
-            DepartureTime::Instantaneous => 0,
-            DepartureTime::Timetable(arr) => arr[minutes_since_midnight % 1440],
+        self.departure_times
+            .minutes_to_departure(minutes_since_midnight % 1440)
+    }
+
+    /// The wait from `time` until the next departure, which is on the minute.
+    pub fn wait_for_departure(&self, time: Seconds) -> Seconds {
+        if !self.is_timetabled() {
+            return Seconds(0);
         }
+        let minute = time.next_minute();
+        Seconds::from_minutes(minute + self.get_minutes_to_departure(minute as usize) as u32) - time
     }
 
-    pub fn from_dist(dist: f64) -> Self {
+    pub fn trip_departure(&self, trip: u32) -> Option<&TripDeparture> {
+        self.trips
+            .binary_search_by_key(&trip, |departure| departure.trip)
+            .ok()
+            .map(|i| &self.trips[i])
+    }
+
+    /// Time from `time`, counted from midnight at the start of `date`, until
+    /// arriving at the end of this edge, or `None` if nothing leaves in the
+    /// day after it. Departures that are cancelled or don't run that day are
+    /// skipped and delayed ones arrive late. A delayed train still has to be
+    /// caught at its timetabled departure.
+    pub fn time_to_arrival(
+        &self,
+        time: Seconds,
+        date: NaiveDate,
+        changes: Option<&HashMap<u16, DepartureChange>>,
+    ) -> Option<Seconds> {
+        let departure = self.departure_from(time, date, changes)?;
+        let delay = match changes.and_then(|changes| changes.get(&departure.minute_of_day())) {
+            Some(DepartureChange::Delayed(delay)) => Seconds::from_minutes((*delay).into()),
+            _ => Seconds(0),
+        };
+        Some(departure - time + self.duration + delay)
+    }
+
+    /// When the first departure from `time` that runs and isn't cancelled
+    /// leaves, within a day.
+    pub fn departure_from(
+        &self,
+        time: Seconds,
+        date: NaiveDate,
+        changes: Option<&HashMap<u16, DepartureChange>>,
+    ) -> Option<Seconds> {
+        let mut departure = time + self.wait_for_departure(time);
+        while departure < time + Seconds::DAY {
+            let minute = departure.minute_of_day();
+            let cancelled = changes
+                .is_some_and(|changes| changes.get(&minute) == Some(&DepartureChange::Cancelled));
+            if !self.departure_times.departs_at(minute.into()) {
+                // Past the last departure of the day, so wait for the first
+                // of the next.
+                let midnight = Seconds(departure.0 - departure.0 % Seconds::DAY.0) + Seconds::DAY;
+                departure = midnight + self.wait_for_departure(midnight);
+                continue;
+            }
+            if !cancelled && self.runs(departure, date) {
+                return Some(departure);
+            }
+            let after = departure + Seconds(60);
+            departure = after + self.wait_for_departure(after);
+        }
+        None
+    }
+
+    // Whether the departure at `departure`, counted from midnight at the
+    // start of `date`, runs.
+    fn runs(&self, departure: Seconds, date: NaiveDate) -> bool {
+        if self.dates.is_empty() {
+            return true;
+        }
+        match self.dates.get(&departure.minute_of_day()) {
+            Some(dates) => {
+                let day = date + Duration::days((departure.0 / Seconds::DAY.0).into());
+                dates.binary_search(&day).is_ok()
+            }
+            None => true,
+        }
+    }
+
+    /// A rail replacement bus that leaves every `interval_minutes` all day.
+    pub fn replacement_bus(
+        closure_id: &str,
+        dist: f64,
+        metres_per_minute: f64,
+        interval_minutes: u16,
+        storage: DepartureStorage,
+    ) -> Self {
+        let departures = (0..24 * 60)
+            .step_by(interval_minutes.max(1) as usize)
+            .map(|minute| NaiveTime::from_hms_opt(minute / 60, minute % 60, 0).unwrap())
+            .collect();
+        let mut connection = Self::with_departures(
+            &DirectConnection {
+                duration_minutes: (dist / metres_per_minute).ceil(),
+                departure_times: departures,
+                ..Default::default()
+            },
+            storage,
+        );
+        connection.replacement_for = Some(closure_id.to_string());
+        connection
+    }
+
+    pub fn from_dist(dist: f64, metres_per_minute: f64) -> Self {
         Self {
-            // 200m per minute.
-            duration_minutes: (dist / 80.) as u16,
+            duration: Seconds::from_fractional_minutes(dist / metres_per_minute),
             departure_times: DepartureTime::Instantaneous,
+            line_ids: vec![],
+            service_ids: vec![],
+            replacement_for: None,
+            trips: vec![],
+            dates: HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sorted_departures_match_table() {
+        let departures: Vec<NaiveTime> = [0, 7, 8, 95, 600, 601, 1000, 1439]
+            .iter()
+            .map(|m| NaiveTime::from_hms_opt(m / 60, m % 60, 0).unwrap())
+            .collect();
+        for departures in [&departures[..], &departures[1..7], &[]] {
+            let table = DepartureTime::new(departures, DepartureStorage::Table);
+            let sorted = DepartureTime::new(departures, DepartureStorage::Sorted);
+            for minute in 0..24 * 60 {
+                assert_eq!(
+                    table.minutes_to_departure(minute),
+                    sorted.minutes_to_departure(minute),
+                    "at minute {}",
+                    minute
+                );
+            }
+            assert_eq!(sorted.heap_bytes(), departures.len() * 2);
+        }
+        assert_eq!(
+            DepartureTime::new(&departures, DepartureStorage::Sorted).minutes_to_departure(9),
+            86
+        );
+
+        // Unsorted and repeated, as in some stored connections.
+        let mut shuffled = departures.clone();
+        shuffled.reverse();
+        shuffled.push(departures[3]);
+        for storage in [DepartureStorage::Table, DepartureStorage::Sorted] {
+            let departures = DepartureTime::new(&shuffled, storage);
+            assert_eq!(departures.minutes_to_departure(9), 86);
+            assert_eq!(departures.minutes_to_departure(95), 0);
         }
     }
 }
@@ -0,0 +1,344 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::File,
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use serde::Serialize;
+
+use crate::{
+    config::{DiffConfig, RoutingConfig},
+    tfl::model::direct_connection::DirectConnection,
+};
+
+use super::{disruptions::Disruptions, snapshot::GraphSnapshot, tfl_graph::TflGraph};
+
+/// An edge whose duration or number of departures changed.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EdgeChange {
+    pub origin: String,
+    pub destination: String,
+    pub before_minutes: f64,
+    pub after_minutes: f64,
+    pub before_departures: usize,
+    pub after_departures: usize,
+}
+
+/// How long it takes from a probe origin to a station, before and after.
+/// `None` if the station can't be reached.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TravelTimeShift {
+    pub destination: String,
+    pub before_minutes: Option<u16>,
+    pub after_minutes: Option<u16>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProbeDiff {
+    pub origin: String,
+    /// Over the stations reachable both before and after.
+    pub mean_shift_minutes: f64,
+    pub newly_reachable: usize,
+    pub newly_unreachable: usize,
+    /// Shifts of at least `diff.travel_time_change_minutes`, largest first.
+    pub shifts: Vec<TravelTimeShift>,
+}
+
+/// What changed between two versions of the network.
+#[derive(Debug, Default, Serialize)]
+pub struct GraphDiff {
+    pub added_stops: Vec<String>,
+    pub removed_stops: Vec<String>,
+    /// As `origin-destination`.
+    pub added_edges: Vec<String>,
+    pub removed_edges: Vec<String>,
+    pub duration_changes: Vec<EdgeChange>,
+    pub frequency_changes: Vec<EdgeChange>,
+    pub probes: Vec<ProbeDiff>,
+}
+
+impl GraphDiff {
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let file =
+            File::create(path).with_context(|| format!("Couldn't write {}", path.display()))?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    pub fn print_summary(&self) {
+        println!(
+            "Stops: {} added, {} removed.",
+            self.added_stops.len(),
+            self.removed_stops.len()
+        );
+        println!(
+            "Edges: {} added, {} removed, {} with changed durations, {} with changed frequencies.",
+            self.added_edges.len(),
+            self.removed_edges.len(),
+            self.duration_changes.len(),
+            self.frequency_changes.len()
+        );
+        for probe in &self.probes {
+            println!(
+                "From {}: {:+.1} minutes on average, {} shifted, {} newly reachable, {} newly unreachable.",
+                probe.origin,
+                probe.mean_shift_minutes,
+                probe.shifts.len(),
+                probe.newly_reachable,
+                probe.newly_unreachable
+            );
+        }
+    }
+}
+
+/// Compares two snapshots of the network, and the travel times from
+/// `probes` leaving at `start` in the graphs built from them.
+pub fn diff(
+    before: GraphSnapshot,
+    after: GraphSnapshot,
+    routing: &RoutingConfig,
+    settings: &DiffConfig,
+    probes: &[String],
+    start: NaiveDateTime,
+) -> Result<GraphDiff> {
+    let mut diff = GraphDiff::default();
+
+    let stop_ids = |snapshot: &GraphSnapshot| -> BTreeSet<String> {
+        snapshot.stop_points.iter().map(|s| s.id.clone()).collect()
+    };
+    let (stops_before, stops_after) = (stop_ids(&before), stop_ids(&after));
+    diff.added_stops = stops_after.difference(&stops_before).cloned().collect();
+    diff.removed_stops = stops_before.difference(&stops_after).cloned().collect();
+
+    let edges = |snapshot: &GraphSnapshot| -> BTreeMap<String, DirectConnection> {
+        snapshot
+            .connections
+            .iter()
+            .map(|c| (format!("{}-{}", c.origin, c.destination), c.clone()))
+            .collect()
+    };
+    let (edges_before, edges_after) = (edges(&before), edges(&after));
+    for (id, old) in &edges_before {
+        let new = match edges_after.get(id) {
+            Some(new) => new,
+            None => {
+                diff.removed_edges.push(id.clone());
+                continue;
+            }
+        };
+        let change = EdgeChange {
+            origin: old.origin.clone(),
+            destination: old.destination.clone(),
+            before_minutes: old.duration_minutes,
+            after_minutes: new.duration_minutes,
+            before_departures: old.departure_times.len(),
+            after_departures: new.departure_times.len(),
+        };
+        if (new.duration_minutes - old.duration_minutes).abs() >= settings.duration_change_minutes {
+            diff.duration_changes.push(change.clone());
+        }
+        let (old_count, new_count) = (
+            change.before_departures as f64,
+            change.after_departures as f64,
+        );
+        if (new_count - old_count).abs() / old_count.max(1.) >= settings.frequency_change_ratio {
+            diff.frequency_changes.push(change);
+        }
+    }
+    diff.added_edges = edges_after
+        .keys()
+        .filter(|id| !edges_before.contains_key(*id))
+        .cloned()
+        .collect();
+
+    let before = before.build_graph(routing)?;
+    let after = after.build_graph(routing)?;
+    for origin in probes {
+        diff.probes.push(probe(
+            &before,
+            &after,
+            origin,
+            start,
+            settings.travel_time_change_minutes,
+        ));
+    }
+
+    Ok(diff)
+}
+
+// A probe that isn't in one of the graphs reaches nothing in it.
+fn probe(
+    before: &TflGraph,
+    after: &TflGraph,
+    origin: &str,
+    start: NaiveDateTime,
+    threshold: u16,
+) -> ProbeDiff {
+    let minutes = |graph: &TflGraph| -> HashMap<String, u16> {
+        graph
+            .tt_from_stop_id(origin.to_string(), start, &Disruptions::default())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|path| (path.destination.id, path.duration.minutes()))
+            .collect()
+    };
+    let (minutes_before, minutes_after) = (minutes(before), minutes(after));
+
+    let mut destinations: Vec<&String> =
+        minutes_before.keys().chain(minutes_after.keys()).collect();
+    destinations.sort();
+    destinations.dedup();
+
+    let (mut total_shift, mut compared) = (0_i64, 0);
+    let (mut newly_reachable, mut newly_unreachable) = (0, 0);
+    let mut shifts = vec![];
+    for destination in destinations {
+        let shift = TravelTimeShift {
+            destination: destination.clone(),
+            before_minutes: minutes_before.get(destination).copied(),
+            after_minutes: minutes_after.get(destination).copied(),
+        };
+        let change = match (shift.before_minutes, shift.after_minutes) {
+            (Some(old), Some(new)) => {
+                total_shift += new as i64 - old as i64;
+                compared += 1;
+                old.abs_diff(new)
+            }
+            (None, _) => {
+                newly_reachable += 1;
+                u16::MAX
+            }
+            (_, None) => {
+                newly_unreachable += 1;
+                u16::MAX
+            }
+        };
+        if change >= threshold {
+            shifts.push((change, shift));
+        }
+    }
+    shifts.sort_by_key(|(change, _)| std::cmp::Reverse(*change));
+
+    ProbeDiff {
+        origin: origin.to_string(),
+        mean_shift_minutes: total_shift as f64 / compared.max(1) as f64,
+        newly_reachable,
+        newly_unreachable,
+        shifts: shifts.into_iter().map(|(_, shift)| shift).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, NaiveTime};
+
+    use crate::naptan::reader::NaptanStop;
+
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let stop = |id: &str, lat| {
+            NaptanStop {
+                id: id.into(),
+                name: id.into(),
+                stop_type: None,
+                indicator: None,
+                bearing: None,
+                lat,
+                lon: 0.,
+                station: None,
+                children: vec![],
+                stop_areas: vec![],
+            }
+            .to_stop_point()
+        };
+        let every = |minutes: u32| {
+            (0..24 * 60)
+                .step_by(minutes as usize)
+                .map(|m| NaiveTime::from_hms_opt(m / 60, m % 60, 0).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let connection =
+            |origin: &str, destination: &str, minutes, every_minutes| DirectConnection {
+                origin: origin.into(),
+                destination: destination.into(),
+                duration_minutes: minutes,
+                departure_times: every(every_minutes),
+                ..Default::default()
+            };
+
+        let before = GraphSnapshot::new(
+            vec![
+                connection("A", "B", 5., 10),
+                connection("B", "C", 5., 10),
+                connection("C", "D", 5., 10),
+            ],
+            vec![
+                stop("A", 51.),
+                stop("B", 51.1),
+                stop("C", 51.2),
+                stop("D", 51.3),
+            ],
+            vec![],
+            vec![],
+            vec![],
+        );
+        // B to C slows down and runs half as often, D closes and E opens.
+        let after = GraphSnapshot::new(
+            vec![
+                connection("A", "B", 5., 10),
+                connection("B", "C", 15., 20),
+                connection("C", "E", 5., 10),
+            ],
+            vec![
+                stop("A", 51.),
+                stop("B", 51.1),
+                stop("C", 51.2),
+                stop("E", 51.4),
+            ],
+            vec![],
+            vec![],
+            vec![],
+        );
+
+        let start = NaiveDate::from_ymd_opt(2022, 5, 2)
+            .unwrap()
+            .and_hms_opt(10, 0, 0)
+            .unwrap();
+        let diff = diff(
+            before,
+            after,
+            &RoutingConfig::default(),
+            &DiffConfig::default(),
+            &["A".to_string()],
+            start,
+        )
+        .unwrap();
+
+        assert_eq!(diff.added_stops, ["E"]);
+        assert_eq!(diff.removed_stops, ["D"]);
+        assert_eq!(diff.added_edges, ["C-E"]);
+        assert_eq!(diff.removed_edges, ["C-D"]);
+        assert_eq!(diff.duration_changes.len(), 1);
+        assert_eq!(diff.duration_changes[0].after_minutes, 15.);
+        assert_eq!(diff.frequency_changes.len(), 1);
+        assert_eq!(diff.frequency_changes[0].after_departures, 72);
+
+        let probe = &diff.probes[0];
+        assert_eq!(probe.newly_reachable, 1);
+        assert_eq!(probe.newly_unreachable, 1);
+        // C is 20 minutes further away with the wait at B, and B hasn't moved.
+        assert_eq!(
+            probe.shifts.last().unwrap(),
+            &TravelTimeShift {
+                destination: "C".into(),
+                before_minutes: Some(15),
+                after_minutes: Some(35),
+            }
+        );
+        assert_eq!(probe.shifts.len(), 3);
+    }
+}
@@ -0,0 +1,324 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chrono::{Local, NaiveDate, NaiveDateTime, NaiveTime};
+use tokio::sync::RwLock;
+
+use crate::util::time::Seconds;
+
+use super::connection::Connection;
+
+/// A live change to a single timetabled departure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepartureChange {
+    /// Arrives this many minutes late at the end of the edge.
+    Delayed(u16),
+    Cancelled,
+}
+
+/// A live change to every train on a line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineChange {
+    /// Trains are running this many minutes late.
+    Delayed(u16),
+    /// Suspended or closed, so no trains are running.
+    Closed,
+}
+
+/// Live changes to the timetable, applied on top of the graph when routing.
+///
+/// Departures are identified by the stations at either end of the edge and
+/// the minute of the day they are timetabled to leave.
+///
+/// Line status and predicted departures describe the network right now, so
+/// they only apply to edges travelled within the live window.
+#[derive(Debug, Default, Clone)]
+pub struct Disruptions {
+    // Origin -> destination -> departure minute.
+    departures: HashMap<String, HashMap<String, HashMap<u16, DepartureChange>>>,
+    // Line id -> change.
+    lines: HashMap<String, LineChange>,
+    // Station -> line id -> predicted departure minutes, sorted.
+    predicted_departures: HashMap<String, HashMap<String, Vec<u16>>>,
+    // When line status and predictions start and stop applying.
+    live_window: Option<(NaiveDateTime, NaiveDateTime)>,
+}
+
+impl Disruptions {
+    pub fn is_empty(&self) -> bool {
+        self.departures.is_empty() && self.lines.is_empty() && self.predicted_departures.is_empty()
+    }
+
+    /// The number of departures that are delayed or cancelled.
+    pub fn len(&self) -> usize {
+        self.departures
+            .values()
+            .flat_map(|d| d.values())
+            .map(|changes| changes.len())
+            .sum()
+    }
+
+    /// The number of lines that are delayed or closed.
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn set_departure(
+        &mut self,
+        origin: &str,
+        destination: &str,
+        departure_minute: u16,
+        change: DepartureChange,
+    ) {
+        self.departures
+            .entry(origin.to_string())
+            .or_default()
+            .entry(destination.to_string())
+            .or_default()
+            .insert(departure_minute % 1440, change);
+    }
+
+    pub fn for_edge(
+        &self,
+        origin: &str,
+        destination: &str,
+    ) -> Option<&HashMap<u16, DepartureChange>> {
+        self.departures.get(origin)?.get(destination)
+    }
+
+    pub fn set_line(&mut self, line_id: &str, change: LineChange) {
+        self.lines.insert(line_id.to_string(), change);
+    }
+
+    #[cfg(test)]
+    pub fn line(&self, line_id: &str) -> Option<LineChange> {
+        self.lines.get(line_id).copied()
+    }
+
+    pub fn add_predicted_departure(&mut self, station: &str, line_id: &str, minute: u16) {
+        let minutes = self
+            .predicted_departures
+            .entry(station.to_string())
+            .or_default()
+            .entry(line_id.to_string())
+            .or_default();
+        if let Err(idx) = minutes.binary_search(&(minute % 1440)) {
+            minutes.insert(idx, minute % 1440);
+        }
+    }
+
+    /// Line status and predictions apply from `start` for `minutes`.
+    pub fn set_live_window(&mut self, start: NaiveDateTime, minutes: u16) {
+        let end = start + chrono::Duration::minutes(minutes.into());
+        self.live_window = Some((start, end));
+    }
+
+    /// Adds `other` on top of these disruptions, `other` winning on conflicts.
+    pub fn extend(&mut self, other: &Disruptions) {
+        for (origin, destinations) in &other.departures {
+            for (destination, changes) in destinations {
+                self.departures
+                    .entry(origin.clone())
+                    .or_default()
+                    .entry(destination.clone())
+                    .or_default()
+                    .extend(changes);
+            }
+        }
+        self.lines.extend(other.lines.clone());
+        for (station, lines) in &other.predicted_departures {
+            self.predicted_departures
+                .entry(station.clone())
+                .or_default()
+                .extend(lines.clone());
+        }
+        if other.live_window.is_some() {
+            self.live_window = other.live_window;
+        }
+    }
+
+    /// Time from `time` until arriving at the end of `connection`, or
+    /// `None` if every line running along it is closed or nothing runs along
+    /// it for a day. `time` counts from midnight at the start of `date`.
+    ///
+    /// `previous` is the edge the search arrived on. A line delay is added
+    /// once when boarding, and while staying on the line the timetable is
+    /// read that many minutes earlier so that the delay doesn't compound.
+    pub fn time_to_arrival(
+        &self,
+        origin: &str,
+        destination: &str,
+        connection: &Connection,
+        previous: Option<&Connection>,
+        date: NaiveDate,
+        time: Seconds,
+    ) -> Option<Seconds> {
+        let changes = self.for_edge(origin, destination);
+        if connection.line_ids.is_empty() || !self.is_live(date, time) {
+            return connection.time_to_arrival(time, date, changes);
+        }
+
+        // The edge is only as disrupted as its least disrupted line.
+        let delay = connection
+            .line_ids
+            .iter()
+            .filter_map(|line| match self.lines.get(line) {
+                Some(LineChange::Closed) => None,
+                Some(LineChange::Delayed(delay)) => Some(*delay),
+                None => Some(0),
+            })
+            .min()
+            .map(|delay| Seconds::from_minutes(delay.into()))?;
+
+        let staying_on = previous.is_some_and(|previous| {
+            previous
+                .line_ids
+                .iter()
+                .any(|line| connection.line_ids.contains(line))
+        });
+        if staying_on {
+            let late = if time >= delay {
+                time - delay
+            } else {
+                time + Seconds::DAY - delay
+            };
+            return connection.time_to_arrival(late, date, changes);
+        }
+
+        let wait = self
+            .predicted_wait(origin, &connection.line_ids, time)
+            .unwrap_or_default();
+        Some(wait + connection.time_to_arrival(time + wait, date, changes)? + delay)
+    }
+
+    /// Whether line status or predictions apply to `connection` from
+    /// `origin` at `time`, counted from midnight at the start of `date`, so
+    /// that its trains aren't running to the timetable.
+    pub fn retimes_lines(
+        &self,
+        origin: &str,
+        connection: &Connection,
+        date: NaiveDate,
+        time: Seconds,
+    ) -> bool {
+        let predicted = self.predicted_departures.get(origin);
+        self.is_live(date, time)
+            && connection.line_ids.iter().any(|line| {
+                self.lines.contains_key(line)
+                    || predicted.is_some_and(|lines| lines.contains_key(line))
+            })
+    }
+
+    fn is_live(&self, date: NaiveDate, time: Seconds) -> bool {
+        match self.live_window {
+            Some((start, end)) => {
+                let at = date.and_time(NaiveTime::MIN) + chrono::Duration::seconds(time.0.into());
+                start <= at && at < end
+            }
+            None => false,
+        }
+    }
+
+    // Time until the next predicted train on any of `line_ids`.
+    // Predictions don't say which way the train is going, so this can only
+    // be optimistic, and the timetable still applies after it.
+    fn predicted_wait(&self, station: &str, line_ids: &[String], time: Seconds) -> Option<Seconds> {
+        let lines = self.predicted_departures.get(station)?;
+        let day = Seconds::DAY.0;
+        line_ids
+            .iter()
+            .filter_map(|line| lines.get(line))
+            .filter_map(|minutes| {
+                minutes
+                    .iter()
+                    .map(|predicted| Seconds((*predicted as u32 * 60 + day - time.0 % day) % day))
+                    .filter(|wait| *wait < Seconds::from_minutes(60))
+                    .min()
+            })
+            .min()
+    }
+}
+
+/// Where a set of disruptions came from. Each source replaces its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DisruptionSource {
+    PushPort,
+    TflStatus,
+}
+
+struct Published {
+    disruptions: Disruptions,
+    expires_at: Option<Instant>,
+}
+
+impl Published {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+}
+
+/// The disruptions that are currently applied to served queries.
+#[derive(Default)]
+pub struct LiveDisruptions {
+    sources: RwLock<HashMap<DisruptionSource, Published>>,
+    current: RwLock<Arc<Disruptions>>,
+}
+
+impl LiveDisruptions {
+    pub async fn current(&self) -> Arc<Disruptions> {
+        let now = Instant::now();
+        let expired = self
+            .sources
+            .read()
+            .await
+            .values()
+            .any(|published| published.is_expired(now));
+        if expired {
+            let mut sources = self.sources.write().await;
+            sources.retain(|_, published| !published.is_expired(now));
+            self.merge(&sources).await;
+        }
+        self.current.read().await.clone()
+    }
+
+    /// The disruptions for a query starting at `start`: the current ones if
+    /// it is today, and none otherwise. Departures are only known by the
+    /// minute of the day they leave at, so they would apply to every day.
+    pub async fn for_start(&self, start: NaiveDateTime) -> Arc<Disruptions> {
+        if start.date() == Local::now().date_naive() {
+            self.current().await
+        } else {
+            Arc::default()
+        }
+    }
+
+    /// Replaces the disruptions from `source`. They are dropped after `ttl`,
+    /// so that a poller that stops working can't leave stale ones behind.
+    pub async fn publish(
+        &self,
+        source: DisruptionSource,
+        disruptions: Disruptions,
+        ttl: Option<Duration>,
+    ) {
+        let mut sources = self.sources.write().await;
+        sources.insert(
+            source,
+            Published {
+                disruptions,
+                expires_at: ttl.map(|ttl| Instant::now() + ttl),
+            },
+        );
+        self.merge(&sources).await;
+    }
+
+    async fn merge(&self, sources: &HashMap<DisruptionSource, Published>) {
+        let mut merged = Disruptions::default();
+        for published in sources.values() {
+            merged.extend(&published.disruptions);
+        }
+        *self.current.write().await = Arc::new(merged);
+    }
+}
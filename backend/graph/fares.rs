@@ -0,0 +1,439 @@
+use std::collections::HashMap;
+
+use chrono::{Datelike, Duration, NaiveDateTime, NaiveTime, Weekday};
+use serde::Serialize;
+
+use crate::{
+    config::FareConfig,
+    tfl::model::stops_response::{StopPoint, StopType, TransportMode},
+    transxchange::operating_profile::bank_holidays_on,
+};
+
+use super::journey::{Journey, LegMode};
+
+/// The fare zones a station is in, from `low` to `high` on a boundary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Zones {
+    pub low: u8,
+    pub high: u8,
+}
+
+impl Zones {
+    /// TfL's zones, e.g. "2", "2+3" or "2/3".
+    pub fn parse(zone: &str) -> Option<Self> {
+        let zones = zone
+            .split(['+', '/'])
+            .map(|zone| zone.trim().parse::<u8>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self {
+            low: *zones.iter().min()?,
+            high: *zones.iter().max()?,
+        })
+    }
+}
+
+// The fewest zones a ride passes through, with each station it calls at
+// counted in whichever of its zones keeps them down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Span {
+    lowest_high: u8,
+    highest_low: u8,
+}
+
+impl Span {
+    fn of(zones: Zones) -> Self {
+        Self {
+            lowest_high: zones.high,
+            highest_low: zones.low,
+        }
+    }
+
+    fn and(self, other: Self) -> Self {
+        Self {
+            lowest_high: self.lowest_high.min(other.lowest_high),
+            highest_low: self.highest_low.max(other.highest_low),
+        }
+    }
+
+    // Whether whatever this becomes is through no more zones than `other`
+    // would become.
+    fn within(self, other: Self) -> bool {
+        self.lowest_high >= other.lowest_high && self.highest_low <= other.highest_low
+    }
+
+    fn zones(self) -> Zones {
+        if self.lowest_high >= self.highest_low {
+            // The stations share a zone, and the outermost one is never
+            // dearer.
+            Zones {
+                low: self.lowest_high,
+                high: self.lowest_high,
+            }
+        } else {
+            Zones {
+                low: self.lowest_high,
+                high: self.highest_low,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FareMode {
+    /// Buses and trams, on the bus fare and hopper.
+    Bus,
+    /// The Tube, DLR, Overground, Elizabeth line and rail, on zone fares.
+    Zonal,
+}
+
+/// How rides from a station are charged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FareStop {
+    pub mode: FareMode,
+    pub zones: Option<Zones>,
+}
+
+impl FareStop {
+    /// A station without a zone, whose rides can't be priced.
+    pub const UNKNOWN: Self = Self {
+        mode: FareMode::Zonal,
+        zones: None,
+    };
+
+    pub fn of(stop: &StopPoint) -> Self {
+        let bus_stop = matches!(
+            stop.stop_type,
+            Some(
+                StopType::NaptanPublicBusCoachTram
+                    | StopType::NaptanPrivateBusCoachTram
+                    | StopType::NaptanOnstreetBusCoachStopPair
+                    | StopType::NaptanOnstreetBusCoachStopCluster
+                    | StopType::NaptanBusCoachStation
+                    | StopType::NaptanBusWayPoint
+                    | StopType::NaptanHailAndRideSection
+            )
+        );
+        let buses_only = !stop.modes.is_empty()
+            && stop
+                .modes
+                .iter()
+                .all(|mode| matches!(mode, TransportMode::Bus | TransportMode::Tram));
+        Self {
+            mode: if bus_stop || buses_only {
+                FareMode::Bus
+            } else {
+                FareMode::Zonal
+            },
+            zones: stop.zone().and_then(Zones::parse),
+        }
+    }
+}
+
+/// What a journey costs on pay as you go.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Fare {
+    pub pence: u32,
+    /// Whether the daily cap for the zones travelled in brought it down.
+    pub capped: bool,
+    pub parts: Vec<FarePart>,
+}
+
+/// A fare charged along a journey.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum FarePart {
+    /// Free within the hopper time of the last bus paid for.
+    Bus { pence: u32, hopper: bool },
+    /// For riding from the first station to the last without boarding a bus
+    /// in between, however many lines that takes.
+    Zonal {
+        zones: Zones,
+        peak: bool,
+        pence: u32,
+    },
+}
+
+/// A journey's fare so far, built up ride by ride.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FareState {
+    // For the fares already charged.
+    paid: u32,
+    // When the last bus fare was paid.
+    hopper_from: Option<NaiveDateTime>,
+    // The zones of the ride on zone fares under way, and whether it started
+    // at peak time.
+    zonal: Option<(Span, bool)>,
+    // Every zone travelled in, for the daily cap.
+    all_zones: Option<Span>,
+    // Set by a ride on zone fares to or from a station without a zone, or
+    // between zones without a fare.
+    unknown: bool,
+}
+
+impl FareState {
+    /// Whether the rest of any journey costs no more from here than from
+    /// `other`: no more paid, a ride on zone fares under way through no more
+    /// zones at the same rate, no more zones towards the cap and a hopper
+    /// fare started no earlier.
+    pub fn no_dearer_than(&self, other: &FareState) -> bool {
+        let within = |span: Option<Span>, other: Option<Span>| match (span, other) {
+            (None, None) => true,
+            (Some(span), Some(other)) => span.within(other),
+            _ => false,
+        };
+        let zonal_within = match (self.zonal, other.zonal) {
+            (None, None) => true,
+            (Some((span, peak)), Some((other, other_peak))) => {
+                peak == other_peak && span.within(other)
+            }
+            _ => false,
+        };
+        self.paid <= other.paid
+            && (!self.unknown || other.unknown)
+            && self.hopper_from >= other.hopper_from
+            && within(self.all_zones, other.all_zones)
+            && zonal_within
+    }
+}
+
+/// Prices journeys on the fares in `FareConfig`.
+pub struct FareModel<'a> {
+    settings: &'a FareConfig,
+}
+
+impl<'a> FareModel<'a> {
+    pub fn new(settings: &'a FareConfig) -> Self {
+        Self { settings }
+    }
+
+    /// Adds a hop from `from` to `to` on a ride leaving at `departure`.
+    /// `boarding` is false when staying on the vehicle of the hop before.
+    /// Returns the fares charged for it.
+    pub fn ride(
+        &self,
+        state: &mut FareState,
+        from: FareStop,
+        to: FareStop,
+        boarding: bool,
+        departure: NaiveDateTime,
+    ) -> Vec<FarePart> {
+        let mut charged = vec![];
+        match from.mode {
+            FareMode::Bus if boarding => {
+                charged.extend(self.end_zonal(state));
+                let hopper = state.hopper_from.is_some_and(|paid| {
+                    departure - paid <= Duration::minutes(self.settings.hopper_minutes.into())
+                });
+                let pence = if hopper {
+                    0
+                } else {
+                    state.hopper_from = Some(departure);
+                    self.settings.bus_pence
+                };
+                state.paid += pence;
+                charged.push(FarePart::Bus { pence, hopper });
+            }
+            FareMode::Bus => {}
+            FareMode::Zonal => match (from.zones, to.zones) {
+                (Some(from), Some(to)) => {
+                    let span = Span::of(from).and(Span::of(to));
+                    state.zonal = Some(match state.zonal {
+                        Some((zonal, peak)) => (zonal.and(span), peak),
+                        None => (span, is_peak(departure)),
+                    });
+                    state.all_zones = Some(state.all_zones.map_or(span, |all| all.and(span)));
+                }
+                _ => state.unknown = true,
+            },
+        }
+        charged
+    }
+
+    /// What the journey so far costs, at most the daily cap for the zones it
+    /// is in. `None` if a ride on zone fares can't be priced.
+    pub fn total(&self, state: &FareState) -> Option<u32> {
+        self.finish(*state).map(|(pence, _, _)| pence)
+    }
+
+    /// The fare of a journey whose stations are in `stops`.
+    pub fn fare(&self, journey: &Journey, stops: &HashMap<String, FareStop>) -> Option<Fare> {
+        let stop = |id: &String| stops.get(id).copied().unwrap_or(FareStop::UNKNOWN);
+        let mut state = FareState::default();
+        let mut parts = vec![];
+        for leg in journey.legs.iter().filter(|leg| leg.mode == LegMode::Ride) {
+            for (i, hop) in leg.stops.windows(2).enumerate() {
+                parts.extend(self.ride(
+                    &mut state,
+                    stop(&hop[0]),
+                    stop(&hop[1]),
+                    i == 0,
+                    leg.departure,
+                ));
+            }
+        }
+        let (pence, capped, last) = self.finish(state)?;
+        parts.extend(last);
+        Some(Fare {
+            pence,
+            capped,
+            parts,
+        })
+    }
+
+    // The total, whether it is capped, and the fare of the ride on zone
+    // fares that was under way.
+    fn finish(&self, mut state: FareState) -> Option<(u32, bool, Option<FarePart>)> {
+        let last = self.end_zonal(&mut state);
+        if state.unknown {
+            return None;
+        }
+        let cap = match state.all_zones {
+            Some(span) => {
+                let zones = span.zones();
+                self.settings
+                    .zone_caps
+                    .iter()
+                    .filter(|cap| cap.low_zone <= zones.low && zones.high <= cap.high_zone)
+                    .map(|cap| cap.pence)
+                    .min()
+            }
+            None => Some(self.settings.bus_cap_pence),
+        };
+        let pence = cap.map_or(state.paid, |cap| cap.min(state.paid));
+        Some((pence, pence < state.paid, last))
+    }
+
+    // Charges for the ride on zone fares under way, once it is over.
+    fn end_zonal(&self, state: &mut FareState) -> Option<FarePart> {
+        let (span, peak) = state.zonal.take()?;
+        let zones = span.zones();
+        // Fares through fewer zones are never dearer.
+        let pence = self
+            .settings
+            .zone_fares
+            .iter()
+            .filter(|fare| fare.low_zone <= zones.low && zones.high <= fare.high_zone)
+            .map(|fare| if peak { fare.peak } else { fare.off_peak })
+            .min();
+        match pence {
+            Some(pence) => {
+                state.paid += pence;
+                Some(FarePart::Zonal { zones, peak, pence })
+            }
+            None => {
+                state.unknown = true;
+                None
+            }
+        }
+    }
+}
+
+/// Whether zone fares are at their peak: from 06:30 to 09:30 and 16:00 to
+/// 19:00 on weekdays other than bank holidays.
+pub fn is_peak(at: NaiveDateTime) -> bool {
+    let time = |hour, min| NaiveTime::from_hms_opt(hour, min, 0).unwrap();
+    !matches!(at.weekday(), Weekday::Sat | Weekday::Sun)
+        && !bank_holidays_on(at.date()).contains(&"AllBankHolidays")
+        && ((time(6, 30)..time(9, 30)).contains(&at.time())
+            || (time(16, 0)..time(19, 0)).contains(&at.time()))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    #[test]
+    fn test_fares() {
+        let settings = FareConfig::default();
+        let fares = FareModel::new(&settings);
+        let zonal = |zone| FareStop {
+            mode: FareMode::Zonal,
+            zones: Zones::parse(zone),
+        };
+        let bus = FareStop {
+            mode: FareMode::Bus,
+            zones: None,
+        };
+        // A Monday.
+        let at = |hour, min| {
+            NaiveDate::from_ymd_opt(2024, 3, 4)
+                .unwrap()
+                .and_hms_opt(hour, min, 0)
+                .unwrap()
+        };
+
+        let mut state = FareState::default();
+        let mut parts = vec![];
+        // Zones 1 to 3 in the morning peak, changing lines at the boundary.
+        parts.extend(fares.ride(&mut state, zonal("1"), zonal("2+3"), true, at(8, 0)));
+        parts.extend(fares.ride(&mut state, zonal("2+3"), zonal("3"), true, at(8, 20)));
+        // Two buses within the hour, and another once it's up.
+        parts.extend(fares.ride(&mut state, bus, bus, true, at(8, 40)));
+        parts.extend(fares.ride(&mut state, bus, bus, false, at(8, 40)));
+        parts.extend(fares.ride(&mut state, bus, bus, true, at(9, 30)));
+        parts.extend(fares.ride(&mut state, bus, bus, true, at(9, 50)));
+        // Back to the boundary station, off peak and within zone 3.
+        parts.extend(fares.ride(&mut state, zonal("3"), zonal("2+3"), true, at(10, 0)));
+        assert_eq!(fares.total(&state), Some(370 + 175 + 175 + 180));
+        assert_eq!(
+            parts,
+            [
+                FarePart::Zonal {
+                    zones: Zones { low: 1, high: 3 },
+                    peak: true,
+                    pence: 370
+                },
+                FarePart::Bus {
+                    pence: 175,
+                    hopper: false
+                },
+                FarePart::Bus {
+                    pence: 0,
+                    hopper: true
+                },
+                FarePart::Bus {
+                    pence: 175,
+                    hopper: false
+                },
+            ]
+        );
+
+        // Buses all day are capped.
+        let mut buses = FareState::default();
+        for hour in [7, 10, 13, 16] {
+            fares.ride(&mut buses, bus, bus, true, at(hour, 0));
+        }
+        assert_eq!(fares.total(&buses), Some(525));
+
+        // Beyond the zones.
+        let mut unknown = FareState::default();
+        fares.ride(&mut unknown, zonal("1"), zonal(""), true, at(8, 0));
+        assert_eq!(fares.total(&unknown), None);
+
+        // Reaching a boundary station from the outer zone is cheaper so far,
+        // but dearer on into the inner one.
+        let mut outer = FareState::default();
+        fares.ride(&mut outer, zonal("2"), zonal("1+2"), true, at(10, 0));
+        let mut inner = FareState::default();
+        fares.ride(&mut inner, zonal("1"), zonal("1+2"), true, at(10, 0));
+        assert!(fares.total(&outer) < fares.total(&inner));
+        assert!(!outer.no_dearer_than(&inner));
+        assert!(!inner.no_dearer_than(&outer));
+        fares.ride(&mut outer, zonal("1+2"), zonal("1"), false, at(10, 5));
+        fares.ride(&mut inner, zonal("1+2"), zonal("1"), false, at(10, 5));
+        assert!(fares.total(&outer) > fares.total(&inner));
+        assert!(inner.no_dearer_than(&outer));
+
+        assert!(!is_peak(at(10, 0)));
+        // The early May bank holiday.
+        assert!(!is_peak(
+            NaiveDate::from_ymd_opt(2024, 5, 6)
+                .unwrap()
+                .and_hms_opt(8, 0, 0)
+                .unwrap()
+        ));
+    }
+}
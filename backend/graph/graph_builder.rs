@@ -0,0 +1,89 @@
+use std::collections::HashSet;
+
+use anyhow::Result;
+use futures::TryStreamExt;
+
+use crate::{
+    config::RoutingConfig,
+    db::{repository::Repository, storage::Storage},
+    national_rail::crosswalk::TiplocMapping,
+    tfl::model::{
+        direct_connection::DirectConnection, planned_closure::PlannedClosure,
+        stops_response::StopPoint, trip::Trip,
+    },
+};
+
+use super::{snapshot::GraphSnapshot, tfl_graph::TflGraph};
+
+pub struct GraphBuilder {
+    connection_repo: Box<dyn Repository<DirectConnection>>,
+    stop_repo: Box<dyn Repository<StopPoint>>,
+    closure_repo: Box<dyn Repository<PlannedClosure>>,
+    tiploc_repo: Box<dyn Repository<TiplocMapping>>,
+    trip_repo: Box<dyn Repository<Trip>>,
+}
+
+impl GraphBuilder {
+    pub fn new(storage: &Storage) -> Self {
+        Self {
+            connection_repo: storage.repo(),
+            stop_repo: storage.repo(),
+            closure_repo: storage.repo(),
+            tiploc_repo: storage.repo(),
+            trip_repo: storage.repo(),
+        }
+    }
+
+    pub async fn build_graph(&self, routing: &RoutingConfig) -> Result<TflGraph> {
+        self.load_snapshot().await?.build_graph(routing)
+    }
+
+    pub async fn load_snapshot(&self) -> Result<GraphSnapshot> {
+        let connections = self.get_all_connections().await?;
+
+        let stop_ids: Vec<_> = connections
+            .iter()
+            .flat_map(|e| vec![e.origin.clone(), e.destination.clone()])
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+
+        let mut stop_points = self.stop_repo.get_by_ids(&stop_ids).await?;
+        // The stations of platforms, so that they can be collapsed.
+        loop {
+            let known: HashSet<&String> = stop_points.iter().map(|s| &s.id).collect();
+            let stations: Vec<String> = stop_points
+                .iter()
+                .filter_map(|s| s.station_naptan.as_ref())
+                .filter(|id| !known.contains(id))
+                .cloned()
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect();
+            let stations = self.stop_repo.get_by_ids(&stations).await?;
+            if stations.is_empty() {
+                break;
+            }
+            stop_points.extend(stations);
+        }
+
+        let closures = self.closure_repo.get_all().await?.try_collect().await?;
+
+        let tiplocs = self.tiploc_repo.get_all().await?.try_collect().await?;
+
+        let trips = self.trip_repo.get_all().await?.try_collect().await?;
+
+        Ok(GraphSnapshot::new(
+            connections,
+            stop_points,
+            closures,
+            tiplocs,
+            trips,
+        ))
+    }
+
+    async fn get_all_connections(&self) -> Result<Vec<DirectConnection>> {
+        let cursor = self.connection_repo.get_all().await?;
+        cursor.try_collect::<Vec<_>>().await
+    }
+}
@@ -0,0 +1,73 @@
+use std::{fmt::Display, path::PathBuf};
+
+use anyhow::Result;
+
+use crate::{
+    config::{Config, RoutingConfig},
+    db::storage::Storage,
+};
+
+use super::{graph_builder::GraphBuilder, snapshot::GraphSnapshot, tfl_graph::TflGraph};
+
+/// Where the served graph is (re)built from.
+#[derive(Clone)]
+pub enum GraphSource {
+    Storage(Storage),
+    /// A snapshot written by `build-graph --out`.
+    Snapshot(PathBuf),
+}
+
+impl GraphSource {
+    /// Uses the snapshot if one is given, otherwise the local storage if
+    /// `local` is set, otherwise the hosted MongoDB.
+    pub async fn new(snapshot: Option<PathBuf>, local: bool, config: &Config) -> Result<Self> {
+        Ok(match snapshot {
+            Some(path) => GraphSource::Snapshot(path),
+            None if local => GraphSource::Storage(Storage::local(config).await?),
+            None => GraphSource::Storage(Storage::atlas(config).await?),
+        })
+    }
+
+    /// `local` or `atlas` for the local storage or the hosted MongoDB, or
+    /// else the path of a snapshot.
+    pub async fn parse(source: &str, config: &Config) -> Result<Self> {
+        Ok(match source {
+            "local" => GraphSource::Storage(Storage::local(config).await?),
+            "atlas" => GraphSource::Storage(Storage::atlas(config).await?),
+            path => GraphSource::Snapshot(path.into()),
+        })
+    }
+
+    pub async fn load_snapshot(&self) -> Result<GraphSnapshot> {
+        match self {
+            GraphSource::Storage(storage) => GraphBuilder::new(storage).load_snapshot().await,
+            GraphSource::Snapshot(path) => {
+                let path = path.clone();
+                tokio::task::spawn_blocking(move || GraphSnapshot::load(path)).await?
+            }
+        }
+    }
+
+    pub async fn build_graph(&self, routing: &RoutingConfig) -> Result<TflGraph> {
+        match self {
+            GraphSource::Storage(storage) => GraphBuilder::new(storage).build_graph(routing).await,
+            GraphSource::Snapshot(path) => {
+                let path = path.clone();
+                let routing = routing.clone();
+                tokio::task::spawn_blocking(move || {
+                    GraphSnapshot::load(path)?.build_graph(&routing)
+                })
+                .await?
+            }
+        }
+    }
+}
+
+impl Display for GraphSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GraphSource::Storage(storage) => write!(f, "{}", storage),
+            GraphSource::Snapshot(path) => write!(f, "snapshot {}", path.display()),
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashSet;
+
+use chrono::{Duration, NaiveDateTime};
+use serde::Serialize;
+
+use crate::util::time::{as_minutes, Seconds};
+
+use super::{fares::Fare, station::Station};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LegMode {
+    Walk,
+    Ride,
+}
+
+/// Part of a journey spent walking, or on one line without changing.
+#[derive(Debug, Clone, Serialize)]
+pub struct Leg {
+    pub mode: LegMode,
+    /// The lines that run the whole leg, if known.
+    pub line_ids: Vec<String>,
+    pub origin: Station,
+    pub destination: Station,
+    /// The ids of the stations along the leg, both ends included.
+    pub stops: Vec<String>,
+    pub departure: NaiveDateTime,
+    pub arrival: NaiveDateTime,
+}
+
+/// The quickest way from one location to another, as found by
+/// `TflGraph::journey`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Journey {
+    pub departure: NaiveDateTime,
+    pub arrival: NaiveDateTime,
+    /// Served as `minutes`, rounded.
+    #[serde(rename = "minutes", with = "as_minutes")]
+    pub duration: Seconds,
+    pub legs: Vec<Leg>,
+    /// Only worked out by `TflGraph::pareto_journeys`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fare: Option<Fare>,
+}
+
+impl Journey {
+    /// How many times the journey changes from one line to another.
+    pub fn changes(&self) -> usize {
+        self.rides().count().saturating_sub(1)
+    }
+
+    /// When the first line is boarded, if any is.
+    pub fn boarding(&self) -> Option<NaiveDateTime> {
+        self.rides().next().map(|leg| leg.departure)
+    }
+
+    /// The share of the hops between stops that either journey rides along
+    /// which only one of them does, from 0 for the same route to 1 for ones
+    /// with nothing in common.
+    pub fn dissimilarity(&self, other: &Journey) -> f64 {
+        let (hops, other_hops) = (self.hops(), other.hops());
+        let union = hops.union(&other_hops).count();
+        if union == 0 {
+            return 0.;
+        }
+        1. - hops.intersection(&other_hops).count() as f64 / union as f64
+    }
+
+    /// A start that just misses the first line boarded, to find the next
+    /// departure.
+    pub fn next_start(&self) -> Option<NaiveDateTime> {
+        let first = self.legs.iter().position(|leg| leg.mode == LegMode::Ride)?;
+        let reached = match first {
+            0 => self.departure,
+            _ => self.legs[first - 1].arrival,
+        };
+        Some(self.departure + (self.legs[first].departure - reached) + Duration::minutes(1))
+    }
+
+    fn rides(&self) -> impl Iterator<Item = &Leg> {
+        self.legs.iter().filter(|leg| leg.mode == LegMode::Ride)
+    }
+
+    fn hops(&self) -> HashSet<(&str, &str)> {
+        self.rides()
+            .flat_map(|leg| leg.stops.windows(2))
+            .map(|pair| (pair[0].as_str(), pair[1].as_str()))
+            .collect()
+    }
+}
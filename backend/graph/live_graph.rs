@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::RwLock;
+
+use super::tfl_graph::TflGraph;
+
+/// A built graph together with the version it was published under.
+pub struct VersionedGraph {
+    pub version: u64,
+    pub built_at: DateTime<Utc>,
+    // Queries temporarily add nodes to the graph, so they need write access.
+    pub graph: RwLock<TflGraph>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "state", rename_all = "camelCase")]
+pub enum BuildStatus {
+    Idle,
+    #[serde(rename_all = "camelCase")]
+    Building {
+        started_at: DateTime<Utc>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Failed {
+        failed_at: DateTime<Utc>,
+        error: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphStatus {
+    pub version: u64,
+    pub built_at: DateTime<Utc>,
+    pub build: BuildStatus,
+}
+
+/// The graph that is currently being served.
+///
+/// Rebuilt graphs are swapped in atomically. Queries hold an `Arc` to the
+/// graph they started on, so in-flight queries finish on the old graph while
+/// new ones see the new graph.
+pub struct LiveGraph {
+    current: RwLock<Arc<VersionedGraph>>,
+    status: RwLock<BuildStatus>,
+}
+
+impl LiveGraph {
+    pub fn new(graph: TflGraph) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(VersionedGraph {
+                version: 1,
+                built_at: Utc::now(),
+                graph: RwLock::new(graph),
+            })),
+            status: RwLock::new(BuildStatus::Idle),
+        }
+    }
+
+    pub async fn current(&self) -> Arc<VersionedGraph> {
+        self.current.read().await.clone()
+    }
+
+    pub async fn status(&self) -> GraphStatus {
+        let current = self.current().await;
+        GraphStatus {
+            version: current.version,
+            built_at: current.built_at,
+            build: self.status.read().await.clone(),
+        }
+    }
+
+    /// Marks a build as started. Returns false if one is already running.
+    pub async fn try_start_build(&self) -> bool {
+        let mut status = self.status.write().await;
+        if let BuildStatus::Building { .. } = *status {
+            return false;
+        }
+        *status = BuildStatus::Building {
+            started_at: Utc::now(),
+        };
+        true
+    }
+
+    /// Publishes the result of a build started with `try_start_build`.
+    pub async fn finish_build(&self, result: anyhow::Result<TflGraph>) {
+        let mut status = self.status.write().await;
+        match result {
+            Ok(graph) => {
+                let mut current = self.current.write().await;
+                *current = Arc::new(VersionedGraph {
+                    version: current.version + 1,
+                    built_at: Utc::now(),
+                    graph: RwLock::new(graph),
+                });
+                *status = BuildStatus::Idle;
+            }
+            Err(e) => {
+                *status = BuildStatus::Failed {
+                    failed_at: Utc::now(),
+                    error: e.to_string(),
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_swap_keeps_old_graph_alive() {
+        let live = LiveGraph::new(TflGraph::default());
+        let old = live.current().await;
+
+        assert!(live.try_start_build().await);
+        assert!(!live.try_start_build().await);
+        live.finish_build(Ok(TflGraph::default())).await;
+
+        let new = live.current().await;
+        assert_eq!(old.version, 1);
+        assert_eq!(new.version, 2);
+        assert!(matches!(live.status().await.build, BuildStatus::Idle));
+    }
+
+    #[tokio::test]
+    async fn test_failed_build_keeps_current_graph() {
+        let live = LiveGraph::new(TflGraph::default());
+        assert!(live.try_start_build().await);
+        live.finish_build(Err(anyhow::anyhow!("mongo down"))).await;
+
+        let status = live.status().await;
+        assert_eq!(status.version, 1);
+        assert!(matches!(status.build, BuildStatus::Failed { .. }));
+        assert!(live.try_start_build().await);
+    }
+}
@@ -1,6 +1,16 @@
+pub mod closures;
 pub mod connection;
+pub mod diff;
+pub mod disruptions;
+pub mod fares;
+pub mod graph_builder;
+pub mod graph_source;
+pub mod journey;
+pub mod live_graph;
 pub mod location;
-pub mod mongo_graph_builder;
 pub mod path;
+pub mod snapshot;
 pub mod station;
+pub mod synthetic;
 pub mod tfl_graph;
+pub mod validation;
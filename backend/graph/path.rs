@@ -1,10 +1,20 @@
 use serde::{Deserialize, Serialize};
 
+use crate::util::time::{as_minutes, Seconds};
+
 use super::station::Station;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Path {
-    pub minutes: u16,
+    /// Served as `minutes`, rounded.
+    #[serde(rename = "minutes", with = "as_minutes")]
+    pub duration: Seconds,
     pub destination: Station,
     pub path: Vec<String>,
 }
+
+impl Path {
+    pub fn minutes(&self) -> u16 {
+        self.duration.minutes()
+    }
+}
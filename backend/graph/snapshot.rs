@@ -0,0 +1,87 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::RoutingConfig;
+use crate::national_rail::crosswalk::TiplocMapping;
+use crate::tfl::model::{
+    direct_connection::DirectConnection, planned_closure::PlannedClosure,
+    stops_response::StopPoint, trip::Trip,
+};
+
+use super::tfl_graph::TflGraph;
+
+// Bump this whenever the layout of the snapshot changes, so that
+// stale snapshots fail loudly instead of deserialising garbage.
+const SNAPSHOT_VERSION: u32 = 9;
+
+/// Everything needed to build a `TflGraph` without a database.
+#[derive(Serialize, Deserialize)]
+pub struct GraphSnapshot {
+    version: u32,
+    pub connections: Vec<DirectConnection>,
+    pub stop_points: Vec<StopPoint>,
+    pub closures: Vec<PlannedClosure>,
+    pub tiplocs: Vec<TiplocMapping>,
+    pub trips: Vec<Trip>,
+}
+
+impl GraphSnapshot {
+    pub fn new(
+        connections: Vec<DirectConnection>,
+        stop_points: Vec<StopPoint>,
+        closures: Vec<PlannedClosure>,
+        tiplocs: Vec<TiplocMapping>,
+        trips: Vec<Trip>,
+    ) -> Self {
+        Self {
+            version: SNAPSHOT_VERSION,
+            connections,
+            stop_points,
+            closures,
+            tiplocs,
+            trips,
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .with_context(|| format!("Could not create snapshot {}", path.display()))?;
+        bincode::serialize_into(BufWriter::new(file), self)?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .with_context(|| format!("Could not open snapshot {}", path.display()))?;
+        let snapshot: GraphSnapshot = bincode::deserialize_from(BufReader::new(file))
+            .with_context(|| format!("Could not read snapshot {}", path.display()))?;
+
+        if snapshot.version != SNAPSHOT_VERSION {
+            bail!(
+                "Snapshot {} has version {}, expected {}. Rebuild it with build-graph.",
+                path.display(),
+                snapshot.version,
+                SNAPSHOT_VERSION
+            );
+        }
+        Ok(snapshot)
+    }
+
+    pub fn build_graph(self, routing: &RoutingConfig) -> Result<TflGraph> {
+        let mut graph = TflGraph::new(routing.clone());
+        graph.add_stations(self.connections, self.stop_points)?;
+        graph.add_trips(self.trips);
+        graph.add_walking_edges();
+        graph.add_closures(self.closures);
+        graph.add_tiplocs(self.tiplocs);
+        Ok(graph)
+    }
+}
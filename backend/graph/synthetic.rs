@@ -0,0 +1,251 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::NaiveTime;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::tfl::model::{
+    direct_connection::DirectConnection,
+    stops_response::{StopPoint, StopType},
+};
+
+use super::snapshot::GraphSnapshot;
+
+// Roughly Greater London.
+const MIN_LAT: f64 = 51.30;
+const MAX_LAT: f64 = 51.68;
+const MIN_LON: f64 = -0.50;
+const MAX_LON: f64 = 0.30;
+const METRES_PER_DEGREE_LAT: f64 = 111_000.;
+const METRES_PER_DEGREE_LON: f64 = 69_000.;
+
+/// A kind of line in a synthetic network.
+#[derive(Debug, Clone)]
+pub struct SyntheticLines {
+    pub count: usize,
+    pub stops_per_line: usize,
+    pub stop_spacing_metres: f64,
+    pub speed_metres_per_minute: f64,
+    pub headway_minutes: u32,
+}
+
+/// A made up network for benchmarks, built from straight-ish lines across
+/// London. The same seed always gives the same network.
+///
+/// Lines that pass within `interchange_metres` of an existing stop call at it
+/// instead of a new one, which is what makes a network out of them. Every
+/// line runs both ways from 05:00 until midnight.
+#[derive(Debug, Clone)]
+pub struct SyntheticNetwork {
+    pub lines: Vec<SyntheticLines>,
+    pub interchange_metres: f64,
+    pub seed: u64,
+}
+
+impl SyntheticNetwork {
+    /// About as many stops, bus routes and rail lines as London has.
+    pub fn london() -> Self {
+        Self {
+            lines: vec![
+                // Buses.
+                SyntheticLines {
+                    count: 650,
+                    stops_per_line: 40,
+                    stop_spacing_metres: 350.,
+                    speed_metres_per_minute: 200.,
+                    headway_minutes: 10,
+                },
+                // Tube, Overground and rail.
+                SyntheticLines {
+                    count: 25,
+                    stops_per_line: 30,
+                    stop_spacing_metres: 1500.,
+                    speed_metres_per_minute: 600.,
+                    headway_minutes: 4,
+                },
+            ],
+            interchange_metres: 150.,
+            seed: 42,
+        }
+    }
+
+    pub fn generate(&self) -> GraphSnapshot {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut stops = StopGrid::new(self.interchange_metres);
+        let mut departures: BTreeMap<(usize, usize), (f64, Vec<NaiveTime>)> = BTreeMap::new();
+
+        for kind in &self.lines {
+            for _ in 0..kind.count {
+                let route = self.route(&mut rng, kind, &mut stops);
+                let first_departure = 5 * 60 + rng.gen_range(0..kind.headway_minutes);
+                for direction in [route.clone(), route.into_iter().rev().collect()] {
+                    let mut offset = 0;
+                    for pair in direction.windows(2) {
+                        let metres =
+                            metres_between(stops.locations[pair[0]], stops.locations[pair[1]]);
+                        let minutes = (metres / kind.speed_metres_per_minute).ceil().max(1.);
+                        let (duration, times) = departures
+                            .entry((pair[0], pair[1]))
+                            .or_insert_with(|| (minutes, vec![]));
+                        *duration = duration.min(minutes);
+                        times.extend(
+                            (first_departure + offset..24 * 60)
+                                .step_by(kind.headway_minutes as usize)
+                                .map(|m| NaiveTime::from_hms_opt(m / 60, m % 60, 0).unwrap()),
+                        );
+                        offset += minutes as u32;
+                    }
+                }
+            }
+        }
+
+        let connections = departures
+            .into_iter()
+            .map(|((origin, destination), (duration, mut times))| {
+                times.sort();
+                times.dedup();
+                DirectConnection {
+                    origin: stop_id(origin),
+                    destination: stop_id(destination),
+                    duration_minutes: duration,
+                    departure_times: times,
+                    ..Default::default()
+                }
+            })
+            .collect();
+        let stop_points = stops
+            .locations
+            .iter()
+            .enumerate()
+            .map(|(i, (lat, lon))| stop_point(i, *lat, *lon))
+            .collect();
+        GraphSnapshot::new(connections, stop_points, vec![], vec![], vec![])
+    }
+
+    // The stops of a line heading off in a random direction, turning a
+    // little at each stop and staying within London.
+    fn route(&self, rng: &mut StdRng, kind: &SyntheticLines, stops: &mut StopGrid) -> Vec<usize> {
+        let mut lat = rng.gen_range(MIN_LAT..MAX_LAT);
+        let mut lon = rng.gen_range(MIN_LON..MAX_LON);
+        let mut bearing: f64 = rng.gen_range(0.0..std::f64::consts::TAU);
+        let mut route = vec![];
+        for _ in 0..kind.stops_per_line {
+            let stop = stops.stop_at(lat, lon);
+            if route.last() != Some(&stop) {
+                route.push(stop);
+            }
+            bearing += rng.gen_range(-0.3..0.3);
+            let (next_lat, next_lon) = (
+                lat + bearing.cos() * kind.stop_spacing_metres / METRES_PER_DEGREE_LAT,
+                lon + bearing.sin() * kind.stop_spacing_metres / METRES_PER_DEGREE_LON,
+            );
+            if !(MIN_LAT..MAX_LAT).contains(&next_lat) || !(MIN_LON..MAX_LON).contains(&next_lon) {
+                bearing += std::f64::consts::PI;
+                continue;
+            }
+            (lat, lon) = (next_lat, next_lon);
+        }
+        route
+    }
+}
+
+// Stops bucketed by cells `metres` wide, to find the one a line passes.
+struct StopGrid {
+    metres: f64,
+    cells: HashMap<(i64, i64), Vec<usize>>,
+    locations: Vec<(f64, f64)>,
+}
+
+impl StopGrid {
+    fn new(metres: f64) -> Self {
+        Self {
+            metres,
+            cells: HashMap::new(),
+            locations: vec![],
+        }
+    }
+
+    fn cell(&self, lat: f64, lon: f64) -> (i64, i64) {
+        (
+            (lat * METRES_PER_DEGREE_LAT / self.metres).floor() as i64,
+            (lon * METRES_PER_DEGREE_LON / self.metres).floor() as i64,
+        )
+    }
+
+    fn stop_at(&mut self, lat: f64, lon: f64) -> usize {
+        let (row, col) = self.cell(lat, lon);
+        let nearby = (-1..=1)
+            .flat_map(|dr| (-1..=1).map(move |dc| (row + dr, col + dc)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+            .find(|&i| metres_between(self.locations[i], (lat, lon)) <= self.metres);
+        if let Some(stop) = nearby {
+            return stop;
+        }
+        self.locations.push((lat, lon));
+        let stop = self.locations.len() - 1;
+        self.cells.entry((row, col)).or_default().push(stop);
+        stop
+    }
+}
+
+// Near enough at London's scale.
+fn metres_between(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let dlat = (a.0 - b.0) * METRES_PER_DEGREE_LAT;
+    let dlon = (a.1 - b.1) * METRES_PER_DEGREE_LON;
+    (dlat * dlat + dlon * dlon).sqrt()
+}
+
+fn stop_id(i: usize) -> String {
+    format!("SYN{:06}", i)
+}
+
+fn stop_point(i: usize, lat: f64, lon: f64) -> StopPoint {
+    StopPoint {
+        naptan_id: stop_id(i),
+        modes: vec![],
+        stop_type: Some(StopType::NaptanPublicBusCoachTram),
+        station_naptan: None,
+        lines: vec![],
+        id: stop_id(i),
+        common_name: format!("Stop {}", i),
+        child_ids: vec![],
+        stop_areas: vec![],
+        indicator: None,
+        bearing: None,
+        additional_properties: vec![],
+        lat,
+        lon,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_is_deterministic() {
+        let mut network = SyntheticNetwork::london();
+        for lines in &mut network.lines {
+            lines.count /= 25;
+        }
+        let ids = |snapshot: &GraphSnapshot| {
+            snapshot
+                .connections
+                .iter()
+                .map(|c| {
+                    (
+                        c.origin.clone(),
+                        c.destination.clone(),
+                        c.departure_times.len(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let snapshot = network.generate();
+        assert_eq!(ids(&snapshot), ids(&network.generate()));
+        // Every connection runs both ways.
+        assert_eq!(snapshot.connections.len() % 2, 0);
+        assert!(snapshot.stop_points.len() > 500);
+    }
+}
@@ -1,29 +1,120 @@
 use std::{
     collections::hash_map::Entry::{Occupied, Vacant},
     collections::{BinaryHeap, HashMap},
+    sync::OnceLock,
 };
 
-use crate::tfl::model::{direct_connection::DirectConnection, stops_response::StopPoint};
-use crate::util::min_scored::MinScored;
+use crate::config::{AlternativesConfig, FareConfig, RoutingConfig};
+use crate::db::mongo_doc::MongoDoc;
+use crate::national_rail::crosswalk::TiplocMapping;
+use crate::tfl::model::{
+    direct_connection::DirectConnection,
+    planned_closure::PlannedClosure,
+    stops_response::{StopPoint, StopType},
+    trip::Trip,
+};
+use crate::util::{min_scored::MinScored, time::Seconds};
 use anyhow::{Context, Result};
-use ball_tree::BallTree;
-use chrono::NaiveTime;
+use ball_tree::{BallTree, Point};
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
 use petgraph::{
-    graph::{EdgeReference, NodeIndex},
+    graph::{EdgeIndex, EdgeReference, NodeIndex},
+    unionfind::UnionFind,
     visit::{EdgeRef, IntoNodeReferences, VisitMap, Visitable},
     Graph,
 };
 
-use super::{connection::Connection, location::Location, path::Path, station::Station};
+use super::{
+    closures::ActiveClosures,
+    connection::{Connection, TripDeparture},
+    disruptions::{DepartureChange, Disruptions},
+    fares::{FareModel, FareState, FareStop},
+    journey::{Journey, Leg, LegMode},
+    location::Location,
+    path::Path,
+    station::Station,
+};
 
 #[derive(Default)]
 pub struct TflGraph {
     graph: Graph<Station, Connection>,
     ball_tree: Option<BallTree<Location, Station>>,
     station_id_to_node: HashMap<String, NodeIndex>,
+    // National rail feeds identify stations by TIPLOC.
+    tiploc_to_station: HashMap<String, String>,
+    // Minimum minutes to change trains, where known.
+    interchange_minutes: HashMap<NodeIndex, u16>,
+    // The days each trip on the edges runs on, by trip. Empty for every day.
+    trip_dates: Vec<Vec<NaiveDate>>,
+    closures: Vec<PlannedClosure>,
+    // How rides from each station are charged, by station id.
+    fare_stops: HashMap<String, FareStop>,
+    routing: RoutingConfig,
+    // Worked out on the first point to point query, once the graph is built.
+    max_metres_per_second: OnceLock<f64>,
+}
+
+// What a search from a start node found.
+struct SearchTree {
+    // The cost of reaching each node and when it is reached, since the
+    // start day's midnight. The same unless the search had penalties.
+    scores: HashMap<NodeIndex, (Seconds, Seconds)>,
+    parents: HashMap<NodeIndex, NodeIndex>,
+    // The edge each node was reached by.
+    parent_edges: HashMap<NodeIndex, EdgeIndex>,
+    start_score: Seconds,
+}
+
+impl SearchTree {
+    fn time(&self, node: NodeIndex) -> Seconds {
+        self.scores
+            .get(&node)
+            .map_or(self.start_score, |(_, time)| *time)
+    }
+}
+
+// What a search minimises besides time, to find other journeys than the
+// quickest.
+#[derive(Default)]
+struct Penalties {
+    edges: HashMap<EdgeIndex, Seconds>,
+    // Every time a line is boarded.
+    boarding: Seconds,
 }
 
-impl<'a> TflGraph {
+// A way of reaching a node in a search for the journeys that are best on
+// time, fare or changes.
+#[derive(Clone, Copy)]
+struct Label {
+    node: NodeIndex,
+    time: Seconds,
+    fare: FareState,
+    boardings: u8,
+    edge: Option<EdgeIndex>,
+    // The trip it is on, as in `search`.
+    trip: Option<(u32, i64)>,
+    parent: Option<usize>,
+    // Once another label reaches the node at least as early, with as few
+    // changes and a fare that can't end up dearer.
+    dominated: bool,
+}
+
+impl Label {
+    fn dominates(&self, other: &Label) -> bool {
+        self.time <= other.time
+            && self.boardings <= other.boardings
+            && self.fare.no_dearer_than(&other.fare)
+    }
+}
+
+impl TflGraph {
+    pub fn new(routing: RoutingConfig) -> Self {
+        Self {
+            routing,
+            ..Default::default()
+        }
+    }
+
     pub fn add_stations(
         &mut self,
         edges: Vec<DirectConnection>,
@@ -33,29 +124,205 @@ impl<'a> TflGraph {
             .iter()
             .map(|s| (s.id.clone(), s))
             .collect::<HashMap<_, _>>();
+        let collapse = self.routing.collapse_platforms;
 
         for edge in edges {
             let from_sp = stop_point_map.get(&edge.origin).unwrap();
             let to_sp = stop_point_map.get(&edge.destination).unwrap();
-            let from_idx = TflGraph::get_or_insert_node_idx(
-                &mut self.graph,
-                &mut self.station_id_to_node,
-                from_sp,
-            );
-            let to_idx = TflGraph::get_or_insert_node_idx(
-                &mut self.graph,
-                &mut self.station_id_to_node,
-                to_sp,
-            );
+            let from_idx = self.node_for_stop(from_sp, &stop_point_map, collapse);
+            let to_idx = self.node_for_stop(to_sp, &stop_point_map, collapse);
+            if from_idx == to_idx {
+                // Between platforms of the same station.
+                continue;
+            }
 
-            let connection = Connection::from_direct_connection(&edge);
+            let connection = Connection::with_departures(&edge, self.routing.departure_storage);
 
             self.graph.add_edge(from_idx, to_idx, connection);
         }
 
+        if !collapse {
+            self.add_station_links(&stop_points);
+        }
+
         Ok(())
     }
 
+    // With `collapse`, platforms and entrances are merged into the station
+    // NaPTAN places them in, which their ids then lead to.
+    fn node_for_stop(
+        &mut self,
+        stop_point: &StopPoint,
+        stop_point_map: &HashMap<String, &StopPoint>,
+        collapse: bool,
+    ) -> NodeIndex {
+        let mut station = stop_point;
+        while let (true, Some(true), Some(parent)) = (
+            collapse,
+            station.stop_type.map(StopType::is_part_of_station),
+            station
+                .station_naptan
+                .as_ref()
+                .and_then(|id| stop_point_map.get(id)),
+        ) {
+            station = parent;
+        }
+        self.fare_stops
+            .entry(station.id.clone())
+            .or_insert_with(|| FareStop::of(station));
+        let idx = TflGraph::get_or_insert_node_idx(
+            &mut self.graph,
+            &mut self.station_id_to_node,
+            station,
+        );
+        self.station_id_to_node
+            .entry(stop_point.id.clone())
+            .or_insert(idx);
+        idx
+    }
+
+    // Walking between a stop and its station, however far apart they are.
+    fn add_station_links(&mut self, stop_points: &[StopPoint]) {
+        for stop_point in stop_points {
+            let (stop_idx, station_idx) = match (
+                self.station_id_to_node.get(&stop_point.id),
+                stop_point
+                    .station_naptan
+                    .as_ref()
+                    .and_then(|id| self.station_id_to_node.get(id)),
+            ) {
+                (Some(stop_idx), Some(station_idx)) => (*stop_idx, *station_idx),
+                _ => continue,
+            };
+            let dist = self.graph[stop_idx]
+                .location
+                .distance(&self.graph[station_idx].location);
+            for (from_idx, to_idx) in [(stop_idx, station_idx), (station_idx, stop_idx)] {
+                let con = Connection::from_dist(dist, self.routing.walking_speed_metres_per_minute);
+                self.graph.add_edge(from_idx, to_idx, con);
+            }
+        }
+    }
+
+    /// Puts the legs of `trips` on the timetabled edges they run along. Must
+    /// be called after `add_stations`.
+    pub fn add_trips(&mut self, trips: Vec<Trip>) {
+        for trip in trips {
+            let index = self.trip_dates.len() as u32;
+            let mut on_graph = false;
+            for leg in trip.legs() {
+                let (from_idx, to_idx) = match (
+                    self.station_id_to_node.get(leg.origin),
+                    self.station_id_to_node.get(leg.destination),
+                ) {
+                    (Some(from_idx), Some(to_idx)) if from_idx != to_idx => (*from_idx, *to_idx),
+                    _ => continue,
+                };
+                // Of parallel edges, e.g. from two platforms of a station, the
+                // one with the trip's departure.
+                let departure = leg.departure_minutes as usize;
+                let edge = self
+                    .graph
+                    .edges_connecting(from_idx, to_idx)
+                    .filter(|e| e.weight().is_timetabled())
+                    .max_by_key(|e| e.weight().get_minutes_to_departure(departure) == 0)
+                    .map(|e| e.id());
+                if let Some(edge) = edge {
+                    self.graph[edge].trips.push(TripDeparture {
+                        trip: index,
+                        departure: leg.departure_minutes,
+                        arrival: leg.arrival_minutes,
+                    });
+                    on_graph = true;
+                }
+            }
+            if on_graph {
+                self.trip_dates.push(trip.dates);
+            }
+        }
+    }
+
+    /// Matches national rail TIPLOCs to the stations in the graph, and
+    /// keeps their stations' interchange times.
+    pub fn add_tiplocs(&mut self, mappings: Vec<TiplocMapping>) {
+        for mapping in mappings {
+            let (stop_id, node_idx) = match mapping
+                .stop_id
+                .and_then(|id| Some((*self.station_id_to_node.get(&id)?, id)))
+            {
+                Some((node_idx, stop_id)) => (stop_id, node_idx),
+                None => continue,
+            };
+            if let Some(minutes) = mapping.interchange_minutes {
+                let interchange = self.interchange_minutes.entry(node_idx).or_default();
+                *interchange = (*interchange).max(minutes);
+            }
+            self.tiploc_to_station.insert(mapping.tiploc, stop_id);
+        }
+    }
+
+    /// Keeps planned closures to apply to queries that start during them,
+    /// and adds the rail replacement buses that run instead.
+    pub fn add_closures(&mut self, closures: Vec<PlannedClosure>) {
+        for closure in &closures {
+            let closure_id = closure.id();
+            for route in &closure.replacement_bus_routes {
+                for pair in route.windows(2) {
+                    let (from_idx, to_idx) = match (
+                        self.station_id_to_node.get(&pair[0]),
+                        self.station_id_to_node.get(&pair[1]),
+                    ) {
+                        (Some(from_idx), Some(to_idx)) => (*from_idx, *to_idx),
+                        _ => continue,
+                    };
+                    let dist = self.graph[from_idx]
+                        .location
+                        .distance(&self.graph[to_idx].location);
+                    let connection = Connection::replacement_bus(
+                        &closure_id,
+                        dist,
+                        self.routing.replacement_bus_speed_metres_per_minute,
+                        self.routing.replacement_bus_interval_minutes,
+                        self.routing.departure_storage,
+                    );
+                    self.graph.add_edge(from_idx, to_idx, connection);
+                }
+            }
+        }
+        self.closures = closures;
+    }
+
+    /// The stations of each part of the network that can't be reached from
+    /// the others, even on foot, largest first.
+    pub fn components(&self) -> Vec<Vec<&Station>> {
+        let mut components = UnionFind::new(self.graph.node_count());
+        for edge in self.graph.edge_references() {
+            components.union(edge.source().index(), edge.target().index());
+        }
+        let mut stations: HashMap<usize, Vec<&Station>> = HashMap::new();
+        for (idx, station) in self.graph.node_references() {
+            stations
+                .entry(components.find(idx.index()))
+                .or_default()
+                .push(station);
+        }
+        let mut stations: Vec<_> = stations.into_values().collect();
+        stations.sort_by_key(|component| std::cmp::Reverse(component.len()));
+        stations
+    }
+
+    /// The bytes allocated for the departures along the edges.
+    pub fn departure_bytes(&self) -> usize {
+        self.graph
+            .edge_weights()
+            .map(|connection| connection.departure_times.heap_bytes())
+            .sum()
+    }
+
+    pub fn station_for_tiploc(&self, tiploc: &str) -> Option<&str> {
+        self.tiploc_to_station.get(tiploc).map(String::as_str)
+    }
+
     fn get_walking_connections(
         &self,
         station: &Station,
@@ -65,10 +332,11 @@ impl<'a> TflGraph {
             .as_ref()
             .unwrap()
             .query()
-            .nn_within(&station.location, 1000.)
+            .nn_within(&station.location, self.routing.walking_radius_metres)
             .map(|(_, dist, close_station)| {
                 let close_idx = self.station_id_to_node.get(&close_station.id).unwrap();
-                (station_idx, *close_idx, Connection::from_dist(dist))
+                let con = Connection::from_dist(dist, self.routing.walking_speed_metres_per_minute);
+                (station_idx, *close_idx, con)
             })
             .filter(|(idx1, idx2, _)| idx1 != idx2)
             .collect::<Vec<_>>()
@@ -102,9 +370,7 @@ impl<'a> TflGraph {
         stop_point: &StopPoint,
     ) -> NodeIndex {
         match map.entry(stop_point.id.clone()) {
-            Occupied(entry) => {
-                return *entry.get();
-            }
+            Occupied(entry) => *entry.get(),
             Vacant(entry) => {
                 let station = Station::from_stop_point(stop_point);
                 let idx = graph.add_node(station);
@@ -117,22 +383,23 @@ impl<'a> TflGraph {
     pub fn travel_times_from_loc(
         &mut self,
         start_loc: Location,
-        start_time: NaiveTime,
+        start: NaiveDateTime,
+        disruptions: &Disruptions,
     ) -> Vec<Path> {
-        let start = Station {
+        let start_station = Station {
             id: "".into(),
             location: start_loc,
             name: "".into(),
         };
 
-        let start_idx = self.graph.add_node(start.clone());
-        let connections = self.get_walking_connections(&start, start_idx);
+        let start_idx = self.graph.add_node(start_station.clone());
+        let connections = self.get_walking_connections(&start_station, start_idx);
 
         for (idx, close_idx, con) in connections {
             self.graph.add_edge(idx, close_idx, con);
         }
 
-        let result = self.tt_from_start_idx(start_idx, start_time);
+        let result = self.tt_from_start_idx(start_idx, start, disruptions);
 
         // Remove the temporarily added start node.
         self.graph.remove_node(start_idx);
@@ -143,24 +410,28 @@ impl<'a> TflGraph {
     pub fn travel_times_from_locs(
         &mut self,
         start_locs: Vec<Location>,
-        start_time: NaiveTime,
+        start: NaiveDateTime,
+        disruptions: &Disruptions,
     ) -> Vec<Path> {
         if start_locs.len() == 1 {
-            return self
-                .travel_times_from_loc(Location(*start_locs.first().unwrap().clone()), start_time);
+            return self.travel_times_from_loc(
+                Location(*start_locs.first().unwrap().clone()),
+                start,
+                disruptions,
+            );
         }
 
         // Keep track of the longest time taken to a station.
         let mut longest_paths: HashMap<String, Path> = HashMap::new();
 
         for loc in start_locs {
-            let paths = self.travel_times_from_loc(loc, start_time);
+            let paths = self.travel_times_from_loc(loc, start, disruptions);
             for path in paths {
                 let key = path.destination.id.clone();
 
                 match longest_paths.entry(key) {
                     Occupied(mut ent) => {
-                        if (*ent.get()).minutes < path.minutes {
+                        if ent.get().duration < path.duration {
                             ent.insert(path);
                         }
                     }
@@ -171,75 +442,810 @@ impl<'a> TflGraph {
             }
         }
 
-        longest_paths.into_iter().map(|(_, path)| path).collect()
+        longest_paths.into_values().collect()
     }
 
-    pub fn tt_from_stop_id(&self, start: String, start_time: NaiveTime) -> Result<Vec<Path>> {
+    pub fn tt_from_stop_id(
+        &self,
+        stop_id: String,
+        start: NaiveDateTime,
+        disruptions: &Disruptions,
+    ) -> Result<Vec<Path>> {
         let start_idx = *self
             .station_id_to_node
-            .get(&start)
+            .get(&stop_id)
             .context("Invalid stop point ID")?;
 
-        Ok(self.tt_from_start_idx(start_idx, start_time))
+        Ok(self.tt_from_start_idx(start_idx, start, disruptions))
+    }
+
+    fn tt_from_start_idx(
+        &self,
+        start_idx: NodeIndex,
+        start: NaiveDateTime,
+        disruptions: &Disruptions,
+    ) -> Vec<Path> {
+        let tree = self.search(start_idx, start, disruptions, None, &Penalties::default());
+        tree.scores
+            .iter()
+            .map(|(n_idx, (_, time))| Path {
+                duration: *time - tree.start_score,
+                destination: self.graph.node_weight(*n_idx).unwrap().clone(),
+                path: TflGraph::get_path(&tree.parents, *n_idx)
+                    .iter()
+                    .map(|idx| self.graph.node_weight(*idx).unwrap().id.clone())
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// The quickest journey from `from` to `to`, leaving at `start`, or
+    /// `None` if `to` can't be reached.
+    pub fn journey(
+        &mut self,
+        from: Location,
+        to: Location,
+        start: NaiveDateTime,
+        disruptions: &Disruptions,
+    ) -> Option<Journey> {
+        self.with_endpoints(from, to, |graph, start_idx, target_idx| {
+            graph
+                .find_journey(
+                    start_idx,
+                    target_idx,
+                    start,
+                    disruptions,
+                    &Penalties::default(),
+                )
+                .map(|(journey, _)| journey)
+        })
+    }
+
+    /// Up to `count` journeys from `from` to `to`, the quickest and then
+    /// ones with fewer changes, the next departure and other routes, by
+    /// arrival. Journeys boarding their first line at the same time have to
+    /// differ by `settings.dissimilarity`.
+    pub fn alternatives(
+        &mut self,
+        from: Location,
+        to: Location,
+        start: NaiveDateTime,
+        disruptions: &Disruptions,
+        count: usize,
+        settings: &AlternativesConfig,
+    ) -> Vec<Journey> {
+        self.with_endpoints(from, to, |graph, start_idx, target_idx| {
+            let find = |start, penalties: &Penalties| {
+                graph.find_journey(start_idx, target_idx, start, disruptions, penalties)
+            };
+            let accept = |journeys: &mut Vec<Journey>, journey: Journey| {
+                let distinct = journeys.iter().all(|other| {
+                    journey.boarding() != other.boarding()
+                        || journey.dissimilarity(other) >= settings.dissimilarity
+                });
+                if distinct && journeys.len() < count {
+                    journeys.push(journey);
+                }
+            };
+
+            let (best, edges) = match find(start, &Penalties::default()) {
+                Some(found) => found,
+                None => return vec![],
+            };
+            // Routes are made less attractive by the time of every ride on
+            // them each time they're found.
+            let mut route_penalties = Penalties::default();
+            graph.penalise_rides(&mut route_penalties, &edges);
+            let mut next = best.next_start();
+            let changes = best.changes();
+            let mut journeys = vec![best];
+
+            if changes > 0 {
+                let fewer_changes = Penalties {
+                    boarding: Seconds::from_minutes(settings.change_penalty_minutes.into()),
+                    ..Default::default()
+                };
+                if let Some((journey, _)) = find(start, &fewer_changes) {
+                    accept(&mut journeys, journey);
+                }
+            }
+
+            match next.and_then(|start| find(start, &Penalties::default())) {
+                Some((journey, _)) => {
+                    next = journey.next_start();
+                    accept(&mut journeys, journey);
+                }
+                None => next = None,
+            }
+
+            for _ in 0..count * 2 {
+                if journeys.len() >= count {
+                    break;
+                }
+                match find(start, &route_penalties) {
+                    Some((journey, edges)) => {
+                        graph.penalise_rides(&mut route_penalties, &edges);
+                        accept(&mut journeys, journey);
+                    }
+                    None => break,
+                }
+            }
+
+            // Then ever later departures.
+            for _ in 0..count {
+                if journeys.len() >= count {
+                    break;
+                }
+                match next.and_then(|start| find(start, &Penalties::default())) {
+                    Some((journey, _)) => {
+                        next = journey.next_start();
+                        accept(&mut journeys, journey);
+                    }
+                    None => break,
+                }
+            }
+
+            journeys.sort_by_key(|journey| (journey.arrival, journey.changes()));
+            journeys
+        })
+    }
+
+    /// The journeys from `from` to `to` that none other beats on arrival,
+    /// fare and changes at once, ordered by arrival. They arrive at most
+    /// `settings.max_extra_minutes` after the quickest journey.
+    pub fn pareto_journeys(
+        &mut self,
+        from: Location,
+        to: Location,
+        start: NaiveDateTime,
+        disruptions: &Disruptions,
+        settings: &FareConfig,
+    ) -> Vec<Journey> {
+        self.with_endpoints(from, to, |graph, start_idx, target_idx| {
+            graph.pareto_search(start_idx, target_idx, start, disruptions, settings)
+        })
+    }
+
+    // Keeps every label at each node that no other label there dominates,
+    // and drops those the labels at the target already dominate. A label
+    // only dominates another whose fare so far is no cheaper in any part
+    // that later rides are charged by, so none it drops could have led to a
+    // cheaper journey.
+    fn pareto_search(
+        &self,
+        start_idx: NodeIndex,
+        target_idx: NodeIndex,
+        start: NaiveDateTime,
+        disruptions: &Disruptions,
+        settings: &FareConfig,
+    ) -> Vec<Journey> {
+        let quickest = match self.find_journey(
+            start_idx,
+            target_idx,
+            start,
+            disruptions,
+            &Penalties::default(),
+        ) {
+            Some((journey, _)) => journey,
+            None => return vec![],
+        };
+        let start_score = Seconds::of_day(start.time());
+        let latest = start_score
+            + quickest.duration
+            + Seconds::from_minutes(settings.max_extra_minutes.into());
+        let fares = FareModel::new(settings);
+        let closures = self.closures_at(start);
+        let midnight = start.date().and_hms_opt(0, 0, 0).unwrap();
+        let fare_stop = |node: NodeIndex| {
+            self.fare_stops
+                .get(&self.graph[node].id)
+                .copied()
+                .unwrap_or(FareStop::UNKNOWN)
+        };
+
+        let mut labels = vec![Label {
+            node: start_idx,
+            time: start_score,
+            fare: FareState::default(),
+            boardings: 0,
+            edge: None,
+            trip: None,
+            parent: None,
+            dominated: false,
+        }];
+        let mut bags: HashMap<NodeIndex, Vec<usize>> = HashMap::from([(start_idx, vec![0])]);
+        let mut visit_next = BinaryHeap::from([MinScored(start_score, 0)]);
+
+        while let Some(MinScored(_, idx)) = visit_next.pop() {
+            let label = labels[idx];
+            if label.dominated || label.node == target_idx {
+                continue;
+            }
+            let previous = label.edge.map(|edge| &self.graph[edge]);
+            for edge in self.graph.edges(label.node) {
+                let (next, connection) = (edge.target(), edge.weight());
+                if !closures.allows(&self.graph[label.node].id, &self.graph[next].id, connection) {
+                    continue;
+                }
+                let boarding = connection.is_timetabled()
+                    && !previous.is_some_and(|previous| {
+                        previous.is_timetabled() && previous.continues_into(connection)
+                    });
+                let boardings = label.boardings.saturating_add(u8::from(boarding));
+                if boardings > settings.max_changes.saturating_add(1) {
+                    continue;
+                }
+                let change =
+                    self.change_minutes(label.node, previous, connection, label.trip.is_some());
+                let time_to_arrive = match self.time_along(
+                    edge,
+                    previous,
+                    start.date(),
+                    label.time,
+                    change,
+                    disruptions,
+                ) {
+                    Some(time) => time,
+                    None => continue,
+                };
+                let (trip, time_to_arrive) = self.trip_along(
+                    edge,
+                    label.trip,
+                    label.time,
+                    change,
+                    time_to_arrive,
+                    start.date(),
+                    disruptions,
+                );
+                let time = label.time + time_to_arrive;
+                if time + self.lower_bound(next, target_idx) > latest {
+                    continue;
+                }
+
+                let mut fare = label.fare;
+                if connection.is_timetabled() {
+                    let departure = time - connection.duration;
+                    fares.ride(
+                        &mut fare,
+                        fare_stop(label.node),
+                        fare_stop(next),
+                        boarding,
+                        midnight + Duration::seconds(departure.0.into()),
+                    );
+                }
+                let new = Label {
+                    node: next,
+                    time,
+                    fare,
+                    boardings,
+                    edge: Some(edge.id()),
+                    trip,
+                    parent: Some(idx),
+                    dominated: false,
+                };
+                let beaten = [next, target_idx].iter().any(|node| {
+                    bags.get(node)
+                        .is_some_and(|bag| bag.iter().any(|&i| labels[i].dominates(&new)))
+                });
+                if beaten {
+                    continue;
+                }
+                let bag = bags.entry(next).or_default();
+                bag.retain(|&i| {
+                    let beats = new.dominates(&labels[i]);
+                    labels[i].dominated |= beats;
+                    !beats
+                });
+                labels.push(new);
+                bag.push(labels.len() - 1);
+                visit_next.push(MinScored(
+                    time + self.lower_bound(next, target_idx),
+                    labels.len() - 1,
+                ));
+            }
+        }
+
+        let mut journeys: Vec<Journey> = bags
+            .remove(&target_idx)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|idx| {
+                let mut tree = SearchTree {
+                    scores: HashMap::new(),
+                    parents: HashMap::new(),
+                    parent_edges: HashMap::new(),
+                    start_score,
+                };
+                let mut edges = vec![];
+                let mut label = &labels[idx];
+                while let (Some(edge), Some(parent)) = (label.edge, label.parent) {
+                    tree.scores.insert(label.node, (label.time, label.time));
+                    edges.push(edge);
+                    label = &labels[parent];
+                }
+                edges.reverse();
+                let mut journey = self.journey_along(&tree, &edges, start);
+                journey.fare = fares.fare(&journey, &self.fare_stops);
+                journey
+            })
+            .collect();
+        let key = |journey: &Journey| {
+            (
+                journey.arrival,
+                journey.fare.as_ref().map_or(u32::MAX, |fare| fare.pence),
+                journey.changes(),
+            )
+        };
+        journeys.sort_by_key(key);
+        // Labels that differed in how later rides would be charged can have
+        // come to the same or a worse fare in the end.
+        let mut front: Vec<Journey> = vec![];
+        for journey in journeys {
+            let (_, pence, changes) = key(&journey);
+            if !front.iter().any(|kept| {
+                let (_, kept_pence, kept_changes) = key(kept);
+                kept_pence <= pence && kept_changes <= changes
+            }) {
+                front.push(journey);
+            }
+        }
+        front
+    }
+
+    // Runs `f` with temporary nodes at `from` and `to`, walking edges from
+    // the one and to the other, and the indices of the two.
+    fn with_endpoints<T>(
+        &mut self,
+        from: Location,
+        to: Location,
+        f: impl FnOnce(&Self, NodeIndex, NodeIndex) -> T,
+    ) -> T {
+        let start_station = Station {
+            id: "".into(),
+            location: from,
+            name: "Start".into(),
+        };
+        let target_station = Station {
+            id: "".into(),
+            location: to,
+            name: "Destination".into(),
+        };
+        let start_idx = self.graph.add_node(start_station.clone());
+        let target_idx = self.graph.add_node(target_station.clone());
+        let mut connections = self.get_walking_connections(&start_station, start_idx);
+        connections.extend(
+            self.get_walking_connections(&target_station, target_idx)
+                .into_iter()
+                .map(|(target_idx, close_idx, con)| (close_idx, target_idx, con)),
+        );
+        let dist = start_station.location.distance(&target_station.location);
+        if dist <= self.routing.walking_radius_metres {
+            connections.push((
+                start_idx,
+                target_idx,
+                Connection::from_dist(dist, self.routing.walking_speed_metres_per_minute),
+            ));
+        }
+        for (idx, close_idx, con) in connections {
+            self.graph.add_edge(idx, close_idx, con);
+        }
+
+        let result = f(self, start_idx, target_idx);
+
+        // Remove the temporarily added nodes, the last one first so that
+        // the other keeps its index.
+        self.graph.remove_node(target_idx);
+        self.graph.remove_node(start_idx);
+
+        result
+    }
+
+    // The journey to `target_idx` and the edges along it.
+    fn find_journey(
+        &self,
+        start_idx: NodeIndex,
+        target_idx: NodeIndex,
+        start: NaiveDateTime,
+        disruptions: &Disruptions,
+        penalties: &Penalties,
+    ) -> Option<(Journey, Vec<EdgeIndex>)> {
+        let tree = self.search(start_idx, start, disruptions, Some(target_idx), penalties);
+        tree.scores.contains_key(&target_idx).then(|| {
+            let mut edges: Vec<EdgeIndex> = TflGraph::get_path(&tree.parents, target_idx)
+                .iter()
+                .filter_map(|node| tree.parent_edges.get(node).copied())
+                .collect();
+            edges.reverse();
+            (self.journey_along(&tree, &edges, start), edges)
+        })
+    }
+
+    fn penalise_rides(&self, penalties: &mut Penalties, edges: &[EdgeIndex]) {
+        for edge in edges {
+            let connection = &self.graph[*edge];
+            if connection.is_timetabled() {
+                *penalties.edges.entry(*edge).or_default() += connection.duration;
+            }
+        }
     }
 
-    fn tt_from_start_idx(&self, start_idx: NodeIndex, start_time: NaiveTime) -> Vec<Path> {
+    // The legs along `edges`, which `tree` found. Consecutive edges are one
+    // leg while walking or while a line runs along all of them.
+    fn journey_along(
+        &self,
+        tree: &SearchTree,
+        edges: &[EdgeIndex],
+        start: NaiveDateTime,
+    ) -> Journey {
+        let midnight = start.date().and_hms_opt(0, 0, 0).unwrap();
+        let at = |time: Seconds| midnight + Duration::seconds(time.0.into());
+
+        let mut legs: Vec<Leg> = vec![];
+        let mut previous: Option<&Connection> = None;
+        let mut arrival = tree.start_score;
+        for edge in edges {
+            let connection = &self.graph[*edge];
+            let (from, to) = self.graph.edge_endpoints(*edge).unwrap();
+            arrival = tree.time(to);
+            let (mode, departure) = if connection.is_timetabled() {
+                (LegMode::Ride, arrival - connection.duration)
+            } else {
+                (LegMode::Walk, tree.time(from))
+            };
+            let carries_on = legs.last().is_some_and(|leg| leg.mode == mode)
+                && (mode == LegMode::Walk
+                    || previous.is_some_and(|previous| previous.continues_into(connection)));
+            let destination = self.graph[to].clone();
+            if carries_on {
+                let leg = legs.last_mut().unwrap();
+                leg.line_ids
+                    .retain(|line| connection.line_ids.contains(line));
+                leg.stops.push(destination.id.clone());
+                leg.destination = destination;
+                leg.arrival = at(arrival);
+            } else {
+                let origin = self.graph[from].clone();
+                legs.push(Leg {
+                    mode,
+                    line_ids: connection.line_ids.clone(),
+                    stops: vec![origin.id.clone(), destination.id.clone()],
+                    origin,
+                    destination,
+                    departure: at(departure),
+                    arrival: at(arrival),
+                });
+            }
+            previous = Some(connection);
+        }
+        // Walks to and from a station the locations are at.
+        legs.retain(|leg| leg.mode == LegMode::Ride || leg.departure < leg.arrival);
+
+        Journey {
+            departure: start,
+            arrival: at(arrival),
+            duration: arrival - tree.start_score,
+            legs,
+            fare: None,
+        }
+    }
+
+    // Searches from `start_idx` for the time every node is reached at. With
+    // a `target`, only until it is reached, and towards it: nodes are tried
+    // in order of the time they're reached at plus the least time it could
+    // take from them to the target. With `penalties`, it is the cost of
+    // reaching them, time plus penalties, that is minimised.
+    fn search(
+        &self,
+        start_idx: NodeIndex,
+        start: NaiveDateTime,
+        disruptions: &Disruptions,
+        target: Option<NodeIndex>,
+        penalties: &Penalties,
+    ) -> SearchTree {
+        let lower_bound = |node: NodeIndex| match target {
+            Some(target) => self.lower_bound(node, target),
+            None => Seconds(0),
+        };
+        let closures = self.closures_at(start);
+        let start_time = start.time();
+
         let mut visited = self.graph.visit_map();
         let mut scores = HashMap::new();
         let mut parents: HashMap<NodeIndex, NodeIndex> = HashMap::new();
+        // The edge each node was reached by, to tell boarding from staying on a line.
+        let mut parent_edges: HashMap<NodeIndex, EdgeIndex> = HashMap::new();
+        // The trip each node was reached on, and the minutes from the start
+        // day's midnight to that of the trip's. Not known while live
+        // line status or predictions retime the line.
+        let mut boarded: HashMap<NodeIndex, (u32, i64)> = HashMap::new();
 
         let mut visit_next = BinaryHeap::new();
-        let start_score = (start_time - NaiveTime::from_hms(0, 0, 0)).num_minutes() as u16;
+        let start_score = Seconds::of_day(start_time);
         // All nodes should be in here.
-        visit_next.push(MinScored(start_score, start_idx));
+        visit_next.push(MinScored(
+            start_score + lower_bound(start_idx),
+            (start_score, start_score, start_idx),
+        ));
 
-        while let Some(MinScored(node_score, node_idx)) = visit_next.pop() {
+        while let Some(MinScored(_, (node_cost, node_score, node_idx))) = visit_next.pop() {
             if visited.is_visited(&node_idx) {
                 continue;
             }
+            if Some(node_idx) == target {
+                break;
+            }
             let edges: Vec<EdgeReference<Connection>> = self.graph.edges(node_idx).collect();
             for edge in edges {
                 let next = edge.target();
                 if visited.is_visited(&next) {
                     continue;
                 }
+                if !closures.allows(
+                    &self.graph[node_idx].id,
+                    &self.graph[next].id,
+                    edge.weight(),
+                ) {
+                    continue;
+                }
 
-                let edge_weight = edge.weight();
-                let time_to_depart = edge_weight.get_minutes_to_departure(node_score as usize);
-                let travel_time = edge_weight.duration_minutes;
-                // Score is the number of minutes required to reach the node since the start of the journey.
-                let mut next_score = time_to_depart + travel_time + node_score;
+                let previous = parent_edges.get(&node_idx).map(|idx| &self.graph[*idx]);
+                let on_trip = boarded.get(&node_idx).copied();
+                let change =
+                    self.change_minutes(node_idx, previous, edge.weight(), on_trip.is_some());
+                let time_to_arrive = match self.time_along(
+                    edge,
+                    previous,
+                    start.date(),
+                    node_score,
+                    change,
+                    disruptions,
+                ) {
+                    Some(time) => time,
+                    None => continue,
+                };
+                let (trip, time_to_arrive) = self.trip_along(
+                    edge,
+                    on_trip,
+                    node_score,
+                    change,
+                    time_to_arrive,
+                    start.date(),
+                    disruptions,
+                );
+
+                let mut penalty = penalties.edges.get(&edge.id()).copied().unwrap_or_default();
+                if penalties.boarding > Seconds(0)
+                    && edge.weight().is_timetabled()
+                    && !previous.is_some_and(|previous| {
+                        previous.is_timetabled() && previous.continues_into(edge.weight())
+                    })
+                {
+                    penalty += penalties.boarding;
+                }
+
+                // Score is the time the node is reached, since the start day's midnight.
+                let mut next_score = time_to_arrive + node_score;
+                let mut next_cost = time_to_arrive + node_cost + penalty;
 
                 match scores.entry(next) {
                     Occupied(ent) => {
-                        let existing_score = *ent.get();
-                        if next_score < existing_score {
-                            *ent.into_mut() = next_score;
+                        let (existing_cost, existing_score) = *ent.get();
+                        if next_cost < existing_cost {
+                            *ent.into_mut() = (next_cost, next_score);
                             parents.insert(next, node_idx);
+                            parent_edges.insert(next, edge.id());
+                            Self::set_boarded(&mut boarded, next, trip);
                         } else {
-                            next_score = existing_score;
+                            (next_cost, next_score) = (existing_cost, existing_score);
                         }
                     }
                     Vacant(ent) => {
-                        ent.insert(next_score);
+                        ent.insert((next_cost, next_score));
                         parents.insert(next, node_idx);
+                        parent_edges.insert(next, edge.id());
+                        Self::set_boarded(&mut boarded, next, trip);
                     }
                 }
-                visit_next.push(MinScored(next_score, next));
+                visit_next.push(MinScored(
+                    next_cost + lower_bound(next),
+                    (next_cost, next_score, next),
+                ));
             }
             visited.visit(node_idx);
         }
-        scores
-            .into_iter()
-            .map(|(n_idx, score)| Path {
-                minutes: score - start_score,
-                destination: self.graph.node_weight(n_idx).unwrap().clone(),
-                path: TflGraph::get_path(&parents, n_idx)
-                    .iter()
-                    .map(|idx| self.graph.node_weight(*idx).unwrap().id.clone())
-                    .collect(),
-            })
-            .collect()
+        SearchTree {
+            scores,
+            parents,
+            parent_edges,
+            start_score,
+        }
+    }
+
+    // The least time it could take from `node` to `target`.
+    fn lower_bound(&self, node: NodeIndex, target: NodeIndex) -> Seconds {
+        let metres_per_second = self.max_metres_per_second();
+        if !metres_per_second.is_finite() {
+            return Seconds(0);
+        }
+        Seconds(
+            (self.graph[node]
+                .location
+                .distance(&self.graph[target].location)
+                / metres_per_second) as u32,
+        )
+    }
+
+    // Closures that start or end during a journey are ignored.
+    fn closures_at(&self, start: NaiveDateTime) -> ActiveClosures<'_> {
+        let start_utc = Local
+            .from_local_datetime(&start)
+            .earliest()
+            .map_or_else(|| start.and_utc(), |start| start.with_timezone(&Utc));
+        ActiveClosures::at(&self.closures, start_utc)
+    }
+
+    // The time from reaching the start of `edge` at `time`, counted from
+    // midnight at the start of `date`, until arriving at its end, changing
+    // from `previous` first. `None` if every line along it is disrupted or
+    // nothing runs along it.
+    fn time_along(
+        &self,
+        edge: EdgeReference<Connection>,
+        previous: Option<&Connection>,
+        date: NaiveDate,
+        time: Seconds,
+        change: Seconds,
+        disruptions: &Disruptions,
+    ) -> Option<Seconds> {
+        let time_to_arrive = if disruptions.is_empty() {
+            edge.weight().time_to_arrival(time + change, date, None)?
+        } else {
+            disruptions.time_to_arrival(
+                &self.graph[edge.source()].id,
+                &self.graph[edge.target()].id,
+                edge.weight(),
+                previous,
+                date,
+                time + change,
+            )?
+        };
+        Some(time_to_arrive + change)
+    }
+
+    // The fastest any edge covers the distance between its ends, walking
+    // included, so that no journey can beat the distance at this speed.
+    // Edges that cover a distance in no time are timetable rounding and
+    // would make this infinite, so they're left out.
+    fn max_metres_per_second(&self) -> f64 {
+        *self.max_metres_per_second.get_or_init(|| {
+            let mut instant = 0;
+            let speed = self
+                .graph
+                .edge_references()
+                .filter_map(|edge| {
+                    let metres = self.graph[edge.source()]
+                        .location
+                        .distance(&self.graph[edge.target()].location);
+                    match edge.weight().duration {
+                        Seconds(0) if metres > 0. => {
+                            instant += 1;
+                            None
+                        }
+                        Seconds(0) => Some(0.),
+                        duration => Some(metres / duration.0 as f64),
+                    }
+                })
+                .fold(self.routing.walking_speed_metres_per_minute / 60., f64::max);
+            if instant > 0 {
+                println!("Ignoring {instant} edges that take no time for the search bound");
+            }
+            speed
+        })
+    }
+
+    fn set_boarded(
+        boarded: &mut HashMap<NodeIndex, (u32, i64)>,
+        node: NodeIndex,
+        trip: Option<(u32, i64)>,
+    ) {
+        match trip {
+            Some(trip) => boarded.insert(node, trip),
+            None => boarded.remove(&node),
+        };
+    }
+
+    // The trip taken along `edge` when reaching its start at `time` on
+    // `on_trip`, and the time until arriving at its end: staying on that
+    // trip if it arrives no later than `time_to_arrive`, taken changing
+    // after `change`. Delayed and cancelled departures apply to the trip,
+    // but it isn't known while line status or predictions retime the line.
+    #[allow(clippy::too_many_arguments)]
+    fn trip_along(
+        &self,
+        edge: EdgeReference<Connection>,
+        on_trip: Option<(u32, i64)>,
+        time: Seconds,
+        change: Seconds,
+        time_to_arrive: Seconds,
+        date: NaiveDate,
+        disruptions: &Disruptions,
+    ) -> (Option<(u32, i64)>, Seconds) {
+        let (origin, destination) = (&self.graph[edge.source()].id, &self.graph[edge.target()].id);
+        let connection = edge.weight();
+        if !connection.is_timetabled()
+            || disruptions.retimes_lines(origin, connection, date, time + change)
+        {
+            return (None, time_to_arrive);
+        }
+        let changes = disruptions.for_edge(origin, destination);
+        let seated = on_trip.and_then(|(trip, offset)| {
+            let departure = connection.trip_departure(trip)?;
+            let minute = (departure.departure as i64 + offset).rem_euclid(1440) as u16;
+            let delay = match changes.and_then(|changes| changes.get(&minute)) {
+                Some(DepartureChange::Cancelled) => return None,
+                Some(DepartureChange::Delayed(delay)) => *delay as i64,
+                None => 0,
+            };
+            let seconds = (departure.arrival as i64 + offset + delay) * 60 - time.0 as i64;
+            Some((trip, offset, Seconds(u32::try_from(seconds).ok()?)))
+        });
+        match seated {
+            Some((trip, offset, seconds)) if seconds <= time_to_arrive => {
+                (Some((trip, offset)), seconds)
+            }
+            _ => {
+                let trip = connection
+                    .departure_from(time + change, date, changes)
+                    .and_then(|departure| {
+                        self.trip_departing(connection, (departure.0 / 60) as i64, date)
+                    });
+                (trip, time_to_arrive)
+            }
+        }
+    }
+
+    // The trip along `connection` that leaves `departure` minutes after the
+    // start day's midnight, with the minutes from then to the midnight the
+    // trip counts from.
+    fn trip_departing(
+        &self,
+        connection: &Connection,
+        departure: i64,
+        start_date: NaiveDate,
+    ) -> Option<(u32, i64)> {
+        connection.trips.iter().find_map(|td| {
+            let offset = departure - td.departure as i64;
+            let dates = &self.trip_dates[td.trip as usize];
+            let runs = offset % 1440 == 0
+                && (dates.is_empty()
+                    || dates
+                        .binary_search(&(start_date + Duration::days(offset / 1440)))
+                        .is_ok());
+            runs.then_some((td.trip, offset))
+        })
+    }
+
+    // Time to allow for changing from `previous` to `next` at `station`.
+    // Walking to or from a station and staying on a train are free. Riders
+    // whose trip is known change whenever they board, since staying on it
+    // is tried separately.
+    fn change_minutes(
+        &self,
+        station: NodeIndex,
+        previous: Option<&Connection>,
+        next: &Connection,
+        on_trip: bool,
+    ) -> Seconds {
+        match (self.interchange_minutes.get(&station), previous) {
+            (Some(minutes), Some(previous))
+                if previous.is_timetabled()
+                    && next.is_timetabled()
+                    && (on_trip || !previous.continues_into(next)) =>
+            {
+                Seconds::from_minutes((*minutes).into())
+            }
+            _ => Seconds(0),
+        }
     }
 
     fn get_path(parents: &HashMap<NodeIndex, NodeIndex>, child: NodeIndex) -> Vec<NodeIndex> {
@@ -255,25 +1261,537 @@ impl<'a> TflGraph {
 #[cfg(test)]
 mod tests {
     use geo::Point;
-    use mongodb::options::ClientOptions;
 
-    use crate::graph::mongo_graph_builder::MongoGraphBuilder;
+    use crate::{
+        db::{storage::Storage, tfl_loader::Loader},
+        graph::{
+            fares::{FarePart, Zones},
+            graph_builder::GraphBuilder,
+            synthetic::SyntheticNetwork,
+        },
+        naptan::reader::NaptanStop,
+        tfl::{
+            fixture_client::ReplayClient,
+            model::stops_response::{AdditionalProperty, TransportMode},
+        },
+    };
+    use chrono::{NaiveDate, NaiveTime};
+    use futures::TryStreamExt;
 
     use super::*;
 
+    fn stop(id: &str, lat: f64, lon: f64) -> StopPoint {
+        NaptanStop {
+            id: id.into(),
+            name: id.into(),
+            stop_type: None,
+            indicator: None,
+            bearing: None,
+            lat,
+            lon,
+            station: None,
+            children: vec![],
+            stop_areas: vec![],
+        }
+        .to_stop_point()
+    }
+
+    fn in_zone(zone: &str, stop: StopPoint) -> StopPoint {
+        StopPoint {
+            additional_properties: vec![AdditionalProperty {
+                category: "Geo".into(),
+                key: "Zone".into(),
+                value: zone.into(),
+            }],
+            ..stop
+        }
+    }
+
+    fn time(hour: u32, min: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, min, 0).unwrap()
+    }
+
+    // A `line` that takes `minutes`, leaving every five minutes all day.
+    fn every_five_minutes(
+        origin: &str,
+        destination: &str,
+        minutes: f64,
+        line: &str,
+    ) -> DirectConnection {
+        DirectConnection {
+            origin: origin.into(),
+            destination: destination.into(),
+            duration_minutes: minutes,
+            departure_times: (0..24 * 60)
+                .step_by(5)
+                .map(|m| time(m / 60, m % 60))
+                .collect(),
+            line_ids: vec![line.into()],
+            ..Default::default()
+        }
+    }
+
     #[tokio::test]
     async fn test_from_location() {
-        let mut atlas_opts = ClientOptions::parse("mongodb://localhost:27017")
+        let root = tempfile::tempdir().unwrap();
+        let storage = Storage::Files(root.path().to_path_buf());
+        let mut client = ReplayClient::checked_in();
+        let mut loader = Loader::new(&mut client, &storage);
+        loader.load_stops().await.unwrap();
+        loader.load_routes().await.unwrap();
+        loader.load_timetables(&[]).await.unwrap();
+
+        let graph_builder = GraphBuilder::new(&storage);
+        let mut graph = graph_builder
+            .build_graph(&RoutingConfig::default())
             .await
             .unwrap();
-        atlas_opts.app_name = Some("travel-time".to_string());
-        let atlas_client = mongodb::Client::with_options(atlas_opts).unwrap();
-        let graph_builder = MongoGraphBuilder::from_client(atlas_client).await;
-        let mut graph = graph_builder.build_graph().await.unwrap();
 
         let loc = Location(Point::new(51.501105, -0.232320));
-        let time = NaiveTime::from_hms(10, 0, 0);
-        let results = graph.travel_times_from_loc(loc, time);
+        let time = NaiveDate::from_ymd_opt(2022, 5, 2)
+            .unwrap()
+            .and_hms_opt(10, 0, 0)
+            .unwrap();
+        let results = graph.travel_times_from_loc(loc, time, &Disruptions::default());
         assert!(!results.is_empty());
     }
+
+    #[test]
+    fn test_stay_on_trip() {
+        let connections = vec![
+            DirectConnection {
+                origin: "A".into(),
+                destination: "B".into(),
+                duration_minutes: 10.,
+                departure_times: vec![time(10, 0)],
+                line_ids: vec!["x".into()],
+                ..Default::default()
+            },
+            DirectConnection {
+                origin: "B".into(),
+                destination: "C".into(),
+                duration_minutes: 6.,
+                departure_times: vec![time(10, 11), time(10, 14), time(10, 30)],
+                line_ids: vec!["x".into(), "y".into()],
+                ..Default::default()
+            },
+        ];
+        let day = NaiveDate::from_ymd_opt(2022, 5, 2).unwrap();
+        let at = |hour, min| day.and_time(time(hour, min));
+        let trips = vec![
+            // Waits at B while the faster y overtakes it.
+            Trip::from_legs(
+                "x".into(),
+                Some("x".into()),
+                day,
+                [
+                    ("A", "B", at(10, 0), at(10, 10)),
+                    ("B", "C", at(10, 14), at(10, 20)),
+                ],
+            ),
+            Trip::from_legs(
+                "y".into(),
+                Some("y".into()),
+                day,
+                [("B", "C", at(10, 11), at(10, 17))],
+            ),
+        ];
+
+        let minutes_to_c = |trips: Vec<Trip>, disruptions: &Disruptions| {
+            let mut graph = TflGraph::new(RoutingConfig::default());
+            graph
+                .add_stations(
+                    connections.clone(),
+                    vec![stop("A", 51., 0.), stop("B", 51.1, 0.), stop("C", 51.2, 0.)],
+                )
+                .unwrap();
+            graph.add_trips(trips);
+            graph.add_walking_edges();
+            graph.add_tiplocs(vec![TiplocMapping {
+                tiploc: "B".into(),
+                crs: None,
+                name: None,
+                atco_code: None,
+                stop_id: Some("B".into()),
+                interchange_minutes: Some(5),
+            }]);
+            graph
+                .tt_from_stop_id("A".into(), at(10, 0), disruptions)
+                .unwrap()
+                .into_iter()
+                .find(|p| p.destination.id == "C")
+                .unwrap()
+                .minutes()
+        };
+
+        // Without trips the y looks like the same line, and there's no time
+        // to change onto it with them.
+        let none = Disruptions::default();
+        assert_eq!(minutes_to_c(vec![], &none), 17);
+        assert_eq!(minutes_to_c(trips.clone(), &none), 20);
+
+        // Trips are still followed where nothing is disrupted.
+        let mut elsewhere = Disruptions::default();
+        elsewhere.set_departure("C", "A", 600, DepartureChange::Cancelled);
+        assert_eq!(minutes_to_c(trips.clone(), &elsewhere), 20);
+        // Staying on one that's running late.
+        let mut late = Disruptions::default();
+        late.set_departure("B", "C", 614, DepartureChange::Delayed(2));
+        assert_eq!(minutes_to_c(trips, &late), 22);
+    }
+
+    #[test]
+    fn test_no_rounding_drift() {
+        // Stops about 111 m apart in a row, 83 seconds' walk from each other,
+        // and a slow train to each from far away, so that they're in the graph.
+        let mut stops: Vec<_> = (0..9)
+            .map(|i| stop(&i.to_string(), 51., i as f64 * 0.001))
+            .collect();
+        stops.push(stop("far", 51., 1.));
+        let connections = (0..9)
+            .map(|i| DirectConnection {
+                origin: "far".into(),
+                destination: i.to_string(),
+                duration_minutes: 100.,
+                ..Default::default()
+            })
+            .collect();
+        let mut graph = TflGraph::new(RoutingConfig {
+            walking_radius_metres: 150.,
+            ..Default::default()
+        });
+        graph.add_stations(connections, stops).unwrap();
+        graph.add_walking_edges();
+
+        let start = NaiveDate::from_ymd_opt(2022, 5, 2)
+            .unwrap()
+            .and_hms_opt(10, 0, 0)
+            .unwrap();
+        let path = graph
+            .tt_from_stop_id("0".into(), start, &Disruptions::default())
+            .unwrap()
+            .into_iter()
+            .find(|p| p.destination.id == "8")
+            .unwrap();
+        // Eight hops used to be rounded down to a minute each.
+        assert_eq!(path.path.len(), 9);
+        assert_eq!(path.duration, Seconds(8 * 83));
+        assert_eq!(path.minutes(), 11);
+    }
+
+    #[test]
+    fn test_bound_ignores_instant_edges() {
+        let stops = vec![
+            stop("A", 51., 0.),
+            stop("B", 51., 0.01),
+            stop("C", 51., 0.1),
+        ];
+        let connections = vec![
+            DirectConnection {
+                origin: "A".into(),
+                destination: "B".into(),
+                duration_minutes: 0.,
+                ..Default::default()
+            },
+            DirectConnection {
+                origin: "B".into(),
+                destination: "C".into(),
+                duration_minutes: 10.,
+                ..Default::default()
+            },
+        ];
+        let mut graph = TflGraph::new(RoutingConfig::default());
+        graph.add_stations(connections, stops).unwrap();
+
+        assert!(graph.max_metres_per_second().is_finite());
+        let (a, c) = (graph.station_id_to_node["A"], graph.station_id_to_node["C"]);
+        assert!(graph.lower_bound(a, c) > Seconds(0));
+    }
+
+    #[test]
+    fn test_journey() {
+        let connection = |origin: &str, destination: &str, departure| DirectConnection {
+            origin: origin.into(),
+            destination: destination.into(),
+            duration_minutes: 10.,
+            departure_times: vec![departure],
+            line_ids: vec!["x".into()],
+            ..Default::default()
+        };
+        let mut graph = TflGraph::new(RoutingConfig::default());
+        graph
+            .add_stations(
+                vec![
+                    connection("A", "B", time(10, 0)),
+                    connection("B", "C", time(10, 12)),
+                    connection("D", "A", time(10, 0)),
+                ],
+                // D is a 42 second walk from C.
+                vec![
+                    stop("A", 51., 0.),
+                    stop("B", 51., 0.05),
+                    stop("C", 51., 0.1),
+                    stop("D", 51., 0.1005),
+                ],
+            )
+            .unwrap();
+        graph.add_walking_edges();
+
+        let day = NaiveDate::from_ymd_opt(2022, 5, 2).unwrap();
+        let journey = graph
+            .journey(
+                Location(Point::new(51., 0.)),
+                Location(Point::new(51., 0.1005)),
+                day.and_time(time(10, 0)),
+                &Disruptions::default(),
+            )
+            .unwrap();
+
+        assert_eq!(journey.duration, Seconds(22 * 60 + 42));
+        assert_eq!(journey.legs.len(), 2);
+        let (ride, walk) = (&journey.legs[0], &journey.legs[1]);
+        assert_eq!(ride.mode, LegMode::Ride);
+        assert_eq!(ride.line_ids, ["x"]);
+        assert_eq!(ride.stops, ["A", "B", "C"]);
+        assert_eq!(ride.departure, day.and_time(time(10, 0)));
+        assert_eq!(ride.arrival, day.and_time(time(10, 22)));
+        assert_eq!(walk.mode, LegMode::Walk);
+        assert_eq!(walk.origin.id, "C");
+        assert_eq!(walk.destination.name, "Destination");
+
+        // The temporary nodes are gone again.
+        assert_eq!(graph.graph.node_count(), 4);
+        // Nowhere near a station.
+        assert!(graph
+            .journey(
+                Location(Point::new(51., 0.)),
+                Location(Point::new(51., 1.)),
+                day.and_time(time(10, 0)),
+                &Disruptions::default(),
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn test_alternatives() {
+        let mut graph = TflGraph::new(RoutingConfig::default());
+        graph
+            .add_stations(
+                vec![
+                    // Quickest, with a change at B.
+                    every_five_minutes("A", "B", 5., "p"),
+                    every_five_minutes("B", "C", 5., "q"),
+                    // Direct but slower.
+                    every_five_minutes("A", "C", 15., "r"),
+                    // Slowest, with a change at D.
+                    every_five_minutes("A", "D", 6., "s"),
+                    every_five_minutes("D", "C", 6., "t"),
+                ],
+                vec![
+                    stop("A", 51., 0.),
+                    stop("B", 51., 0.02),
+                    stop("D", 51., 0.03),
+                    stop("C", 51., 0.05),
+                ],
+            )
+            .unwrap();
+        graph.add_walking_edges();
+
+        let day = NaiveDate::from_ymd_opt(2022, 5, 2).unwrap();
+        let journeys = graph.alternatives(
+            Location(Point::new(51., 0.)),
+            Location(Point::new(51., 0.05)),
+            day.and_time(time(10, 0)),
+            &Disruptions::default(),
+            4,
+            &AlternativesConfig::default(),
+        );
+
+        let summary: Vec<_> = journeys
+            .iter()
+            .map(|journey| {
+                let lines: Vec<_> = journey
+                    .legs
+                    .iter()
+                    .flat_map(|leg| leg.line_ids.clone())
+                    .collect();
+                (journey.boarding().unwrap().time(), lines.join("+"))
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (time(10, 0), "p+q".to_string()),
+                (time(10, 0), "r".to_string()),
+                (time(10, 5), "p+q".to_string()),
+                (time(10, 0), "s+t".to_string()),
+            ]
+        );
+        assert_eq!(journeys[1].changes(), 0);
+        assert_eq!(journeys[3].arrival, day.and_time(time(10, 16)));
+        assert_eq!(graph.graph.node_count(), 4);
+    }
+
+    #[test]
+    fn test_pareto_journeys() {
+        let bus_stop = |id, lon| StopPoint {
+            stop_type: Some(StopType::NaptanPublicBusCoachTram),
+            ..stop(id, 51., lon)
+        };
+        let mut graph = TflGraph::new(RoutingConfig::default());
+        graph
+            .add_stations(
+                vec![
+                    every_five_minutes("A", "C", 10., "tube"),
+                    // As dear as the tube, and slower.
+                    every_five_minutes("A", "C", 25., "slow"),
+                    every_five_minutes("P", "Q", 20., "bus"),
+                    // Quicker than the one bus, with a change.
+                    every_five_minutes("P", "R", 8., "bus1"),
+                    every_five_minutes("R", "Q", 8., "bus2"),
+                ],
+                // P and Q are 42 second walks from A and C.
+                vec![
+                    in_zone("1", stop("A", 51., 0.)),
+                    bus_stop("P", 0.0005),
+                    bus_stop("R", 0.015),
+                    bus_stop("Q", 0.0295),
+                    in_zone("2", stop("C", 51., 0.03)),
+                ],
+            )
+            .unwrap();
+        graph.add_walking_edges();
+
+        // A Monday, off peak.
+        let day = NaiveDate::from_ymd_opt(2024, 3, 4).unwrap();
+        let journeys = graph.pareto_journeys(
+            Location(Point::new(51., 0.)),
+            Location(Point::new(51., 0.03)),
+            day.and_time(time(10, 0)),
+            &Disruptions::default(),
+            &FareConfig::default(),
+        );
+
+        let summary: Vec<_> = journeys
+            .iter()
+            .map(|journey| {
+                let lines: Vec<_> = journey
+                    .legs
+                    .iter()
+                    .flat_map(|leg| leg.line_ids.clone())
+                    .collect();
+                (
+                    lines.join("+"),
+                    journey.fare.as_ref().unwrap().pence,
+                    journey.changes(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("tube".to_string(), 280, 0),
+                ("bus1+bus2".to_string(), 175, 1),
+                ("bus".to_string(), 175, 0),
+            ]
+        );
+        assert_eq!(journeys[0].arrival, day.and_time(time(10, 10)));
+        assert_eq!(
+            journeys[0].fare.as_ref().unwrap().parts,
+            [FarePart::Zonal {
+                zones: Zones { low: 1, high: 2 },
+                peak: false,
+                pence: 280,
+            }]
+        );
+        assert_eq!(graph.graph.node_count(), 5);
+    }
+
+    #[test]
+    fn test_journey_matches_search() {
+        let mut network = SyntheticNetwork::london();
+        for lines in &mut network.lines {
+            lines.count = lines.count.div_ceil(10);
+        }
+        let snapshot = network.generate();
+        let stops: Vec<(String, Location)> = snapshot
+            .stop_points
+            .iter()
+            .step_by(97)
+            .map(|stop| (stop.id.clone(), Location(Point::new(stop.lat, stop.lon))))
+            .collect();
+        let mut graph = snapshot.build_graph(&RoutingConfig::default()).unwrap();
+        let start = NaiveDate::from_ymd_opt(2022, 5, 2)
+            .unwrap()
+            .and_hms_opt(8, 30, 0)
+            .unwrap();
+        let disruptions = Disruptions::default();
+
+        let mut reached = 0;
+        for (_, from) in &stops[..4] {
+            let durations: HashMap<String, Seconds> = graph
+                .travel_times_from_loc(from.clone(), start, &disruptions)
+                .into_iter()
+                .map(|path| (path.destination.id, path.duration))
+                .collect();
+            for (stop, to) in &stops {
+                let journey = graph.journey(from.clone(), to.clone(), start, &disruptions);
+                reached += journey.is_some() as usize;
+                assert_eq!(
+                    journey.map(|journey| journey.duration),
+                    durations.get(stop).copied()
+                );
+            }
+        }
+        assert!(reached > stops.len());
+    }
+
+    #[tokio::test]
+    async fn test_planned_closures() {
+        let root = tempfile::tempdir().unwrap();
+        let storage = Storage::Files(root.path().to_path_buf());
+        let mut client = ReplayClient::checked_in();
+        let mut loader = Loader::new(&mut client, &storage);
+        loader.load_stops().await.unwrap();
+        loader.load_routes().await.unwrap();
+        loader.load_timetables(&[]).await.unwrap();
+        let may = |day| NaiveDate::from_ymd_opt(2022, 5, day).unwrap();
+        loader
+            .load_closures(vec![TransportMode::Tube], may(2), may(30))
+            .await
+            .unwrap();
+
+        // The Piccadilly closure doesn't say where it is, so it's skipped.
+        let closures: Vec<PlannedClosure> = storage
+            .repo()
+            .get_all()
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(closures.len(), 1);
+        assert_eq!(closures[0].replacement_bus_routes.len(), 2);
+
+        let graph = GraphBuilder::new(&storage)
+            .build_graph(&RoutingConfig::default())
+            .await
+            .unwrap();
+        let minutes_to_stamford_brook = |day| {
+            let start = may(day).and_hms_opt(10, 0, 0).unwrap();
+            graph
+                .tt_from_stop_id("940GZZLUHSD".into(), start, &Disruptions::default())
+                .unwrap()
+                .into_iter()
+                .find(|p| p.destination.id == "940GZZLUSFB")
+                .unwrap()
+                .minutes()
+        };
+
+        // On Monday the train takes 4 minutes. On Saturday the replacement
+        // bus leaves at 10:00, arrives at Ravenscourt Park at 10:08 and the
+        // next one gets to Stamford Brook at 10:16.
+        assert_eq!(minutes_to_stamford_brook(2), 4);
+        assert_eq!(minutes_to_stamford_brook(7), 16);
+    }
 }
@@ -0,0 +1,379 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::File,
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use geo::prelude::HaversineDistance;
+use serde::Serialize;
+
+use crate::{
+    config::{RoutingConfig, ValidationConfig},
+    tfl::model::{direct_connection::DirectConnection, stops_response::StopPoint},
+};
+
+use super::snapshot::GraphSnapshot;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Check {
+    NegativeDuration,
+    UnsortedDepartures,
+    MissingStop,
+    ZeroCoordinates,
+    ZeroDuration,
+    SpeedOutlier,
+    DuplicateStops,
+    OrphanStop,
+    Disconnected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// Data that routes wrongly or can't be built into the graph.
+    Error,
+    /// Data that is probably wrong, but might not be.
+    Warning,
+}
+
+impl Check {
+    pub fn severity(self) -> Severity {
+        match self {
+            Check::NegativeDuration
+            | Check::UnsortedDepartures
+            | Check::MissingStop
+            | Check::ZeroCoordinates => Severity::Error,
+            Check::ZeroDuration
+            | Check::SpeedOutlier
+            | Check::DuplicateStops
+            | Check::OrphanStop
+            | Check::Disconnected => Severity::Warning,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Issue {
+    pub check: Check,
+    pub severity: Severity,
+    /// The stops or connections concerned.
+    pub ids: Vec<String>,
+    pub message: String,
+}
+
+/// The problems found in the stored network, as written by `validate`.
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub errors: usize,
+    pub warnings: usize,
+    pub counts: BTreeMap<Check, usize>,
+    pub connections: usize,
+    pub stop_points: usize,
+    /// The parts of the graph that can't be reached from each other.
+    pub components: usize,
+    pub issues: Vec<Issue>,
+}
+
+impl Report {
+    fn add(&mut self, check: Check, ids: Vec<String>, message: String) {
+        match check.severity() {
+            Severity::Error => self.errors += 1,
+            Severity::Warning => self.warnings += 1,
+        }
+        *self.counts.entry(check).or_default() += 1;
+        self.issues.push(Issue {
+            check,
+            severity: check.severity(),
+            ids,
+            message,
+        });
+    }
+
+    /// Whether the network should fail CI: with any errors, or with any
+    /// warnings if `strict`.
+    pub fn fails(&self, strict: bool) -> bool {
+        self.errors > 0 || (strict && self.warnings > 0)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let file =
+            File::create(path).with_context(|| format!("Couldn't write {}", path.display()))?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
+
+/// Checks the stored connections and stop points, and the graph built from
+/// them.
+///
+/// Connections to stops that don't exist or with unsorted departures are
+/// reported and left out of the graph, which couldn't be built with them.
+pub fn validate(
+    connections: Vec<DirectConnection>,
+    stop_points: Vec<StopPoint>,
+    routing: &RoutingConfig,
+    settings: &ValidationConfig,
+) -> Result<Report> {
+    let mut report = Report {
+        connections: connections.len(),
+        stop_points: stop_points.len(),
+        ..Default::default()
+    };
+    let stops: HashMap<&str, &StopPoint> = stop_points.iter().map(|s| (s.id.as_str(), s)).collect();
+
+    for stop in &stop_points {
+        if stop.lat == 0. && stop.lon == 0. {
+            report.add(
+                Check::ZeroCoordinates,
+                vec![stop.id.clone()],
+                format!("{} is at 0,0", stop.common_name),
+            );
+        }
+    }
+
+    let mut known = vec![];
+    for con in connections {
+        let id = format!("{}-{}", con.origin, con.destination);
+        if con.duration_minutes < 0. {
+            report.add(
+                Check::NegativeDuration,
+                vec![id.clone()],
+                format!("Takes {} minutes", con.duration_minutes),
+            );
+        } else if con.duration_minutes == 0. {
+            report.add(
+                Check::ZeroDuration,
+                vec![id.clone()],
+                "Takes no time".into(),
+            );
+        }
+        if con.departure_times.windows(2).any(|w| w[0] >= w[1]) {
+            report.add(
+                Check::UnsortedDepartures,
+                vec![id.clone()],
+                "Departures aren't sorted or repeat. Run `fix --timetables`.".into(),
+            );
+            continue;
+        }
+
+        let (origin, destination) = match (
+            stops.get(con.origin.as_str()),
+            stops.get(con.destination.as_str()),
+        ) {
+            (Some(origin), Some(destination)) => (origin, destination),
+            _ => {
+                report.add(
+                    Check::MissingStop,
+                    vec![id],
+                    "Goes to or from a stop point that isn't stored".into(),
+                );
+                continue;
+            }
+        };
+        let metres = metres_between(origin, destination);
+        if con.duration_minutes > 0.
+            && metres / con.duration_minutes > settings.max_speed_metres_per_minute
+        {
+            report.add(
+                Check::SpeedOutlier,
+                vec![id],
+                format!(
+                    "Covers {:.0} m in {} minutes, {:.0} km/h",
+                    metres,
+                    con.duration_minutes,
+                    metres / con.duration_minutes * 60. / 1000.
+                ),
+            );
+        }
+        known.push(con);
+    }
+
+    check_duplicates(&stop_points, settings.duplicate_stop_metres, &mut report);
+    check_orphans(&stop_points, &known, &mut report);
+
+    let graph =
+        GraphSnapshot::new(known, stop_points, vec![], vec![], vec![]).build_graph(routing)?;
+    let components = graph.components();
+    report.components = components.len();
+    // Everything but the main network is an island.
+    for component in components.iter().skip(1) {
+        report.add(
+            Check::Disconnected,
+            component.iter().map(|station| station.id.clone()).collect(),
+            format!(
+                "{} stations, e.g. {}, can't be reached from the rest of the network",
+                component.len(),
+                component[0].name
+            ),
+        );
+    }
+
+    Ok(report)
+}
+
+// Stops are bucketed into cells at least `metres` wide, so that only stops
+// in neighbouring cells need comparing.
+fn check_duplicates(stop_points: &[StopPoint], metres: f64, report: &mut Report) {
+    let cell_degrees = metres / 111_000. * 2.;
+    let cell = |stop: &StopPoint| {
+        (
+            (stop.lat / cell_degrees).floor() as i64,
+            (stop.lon / cell_degrees).floor() as i64,
+        )
+    };
+    let mut cells: HashMap<(i64, i64), Vec<&StopPoint>> = HashMap::new();
+    for stop in stop_points.iter().filter(|s| !(s.lat == 0. && s.lon == 0.)) {
+        cells.entry(cell(stop)).or_default().push(stop);
+    }
+
+    let mut pairs = vec![];
+    for (&(lat, lon), stops) in &cells {
+        for stop in stops {
+            for neighbour in (-1..=1)
+                .flat_map(|dlat| (-1..=1).map(move |dlon| (lat + dlat, lon + dlon)))
+                .filter_map(|key| cells.get(&key))
+                .flatten()
+            {
+                if stop.id < neighbour.id
+                    && !same_station(stop, neighbour)
+                    && metres_between(stop, neighbour) <= metres
+                {
+                    pairs.push((stop.id.clone(), neighbour.id.clone()));
+                }
+            }
+        }
+    }
+    pairs.sort();
+    for (a, b) in pairs {
+        report.add(
+            Check::DuplicateStops,
+            vec![a, b],
+            format!("Within {} m of each other", metres),
+        );
+    }
+}
+
+// A stop is an orphan if no connection uses it, its children or its station.
+fn check_orphans(stop_points: &[StopPoint], connections: &[DirectConnection], report: &mut Report) {
+    let used: HashSet<&str> = connections
+        .iter()
+        .flat_map(|c| [c.origin.as_str(), c.destination.as_str()])
+        .collect();
+    let mut orphans: Vec<&StopPoint> = stop_points
+        .iter()
+        .filter(|stop| {
+            !used.contains(stop.id.as_str())
+                && !stop.child_ids.iter().any(|id| used.contains(id.as_str()))
+                && !stop
+                    .station_naptan
+                    .as_deref()
+                    .is_some_and(|id| used.contains(id))
+        })
+        .collect();
+    orphans.sort_by(|a, b| a.id.cmp(&b.id));
+    for stop in orphans {
+        report.add(
+            Check::OrphanStop,
+            vec![stop.id.clone()],
+            format!("No connection goes to or from {}", stop.common_name),
+        );
+    }
+}
+
+fn same_station(a: &StopPoint, b: &StopPoint) -> bool {
+    a.station_naptan.as_ref() == Some(&b.id)
+        || b.station_naptan.as_ref() == Some(&a.id)
+        || (a.station_naptan.is_some() && a.station_naptan == b.station_naptan)
+}
+
+fn metres_between(a: &StopPoint, b: &StopPoint) -> f64 {
+    geo::Point::new(a.lon, a.lat).haversine_distance(&geo::Point::new(b.lon, b.lat))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveTime;
+
+    use crate::naptan::reader::NaptanStop;
+
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let stop = |id: &str, lat, lon| {
+            NaptanStop {
+                id: id.into(),
+                name: id.into(),
+                stop_type: None,
+                indicator: None,
+                bearing: None,
+                lat,
+                lon,
+                station: None,
+                children: vec![],
+                stop_areas: vec![],
+            }
+            .to_stop_point()
+        };
+        let connection = |origin: &str, destination: &str, minutes| DirectConnection {
+            origin: origin.into(),
+            destination: destination.into(),
+            duration_minutes: minutes,
+            ..Default::default()
+        };
+        let time = |hour, min| NaiveTime::from_hms_opt(hour, min, 0).unwrap();
+
+        let stop_points = vec![
+            stop("A", 51.5, -0.1),
+            // A kilometre north of A.
+            stop("B", 51.509, -0.1),
+            // A metre from B.
+            stop("B2", 51.50901, -0.1),
+            stop("NULL", 0., 0.),
+            // Far from everything else.
+            stop("C", 52.5, -0.1),
+            stop("D", 52.509, -0.1),
+        ];
+        let mut unsorted = connection("B", "A", 3.);
+        unsorted.departure_times = vec![time(10, 10), time(10, 0)];
+        let connections = vec![
+            connection("A", "B", 2.),
+            unsorted,
+            connection("C", "D", -1.),
+            connection("D", "C", 0.),
+            // Teleports a kilometre.
+            connection("B2", "A", 0.1),
+            connection("A", "E", 2.),
+        ];
+
+        let report = validate(
+            connections,
+            stop_points,
+            &RoutingConfig::default(),
+            &ValidationConfig::default(),
+        )
+        .unwrap();
+        let issues = |check| {
+            report
+                .issues
+                .iter()
+                .filter(|i| i.check == check)
+                .map(|i| i.ids.join(" "))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(issues(Check::NegativeDuration), ["C-D"]);
+        assert_eq!(issues(Check::ZeroDuration), ["D-C"]);
+        assert_eq!(issues(Check::UnsortedDepartures), ["B-A"]);
+        assert_eq!(issues(Check::MissingStop), ["A-E"]);
+        assert_eq!(issues(Check::ZeroCoordinates), ["NULL"]);
+        assert_eq!(issues(Check::SpeedOutlier), ["B2-A"]);
+        assert_eq!(issues(Check::DuplicateStops), ["B B2"]);
+        assert_eq!(issues(Check::OrphanStop), ["NULL"]);
+        assert_eq!(report.components, 2);
+        assert_eq!(issues(Check::Disconnected).len(), 1);
+        assert_eq!(report.errors, 4);
+        assert!(report.fails(false));
+    }
+}
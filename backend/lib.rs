@@ -0,0 +1,262 @@
+use std::path::PathBuf;
+
+use clap::{ArgEnum, Args, Parser, Subcommand};
+use config::Config;
+use graph::graph_source::GraphSource;
+pub mod api;
+pub mod config;
+pub mod db;
+pub mod graph;
+pub mod naptan;
+pub mod national_rail;
+pub mod query;
+pub mod setup;
+pub mod tfl;
+pub mod transxchange;
+pub mod util;
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+pub struct Cli {
+    #[clap(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Load data from an external source into the local storage.
+    #[clap(subcommand)]
+    Ingest(IngestSource),
+    /// Fix existing data in the local storage.
+    Fix(FixArgs),
+    /// Copy data from the local storage to the hosted MongoDB.
+    CopyToAtlas,
+    /// Build the graph from the local storage.
+    BuildGraph(BuildGraphArgs),
+    /// Serve travel times over HTTP.
+    Serve(ServeArgs),
+    /// Compute travel times and print them without starting the server.
+    Query(QueryArgs),
+    /// Check the local storage for bad data and write a JSON report. Exits
+    /// with an error if any is found.
+    Validate(ValidateArgs),
+    /// Compare two versions of the network, e.g. before and after an
+    /// ingest, and write a JSON report.
+    Diff(DiffArgs),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum IngestSource {
+    /// Load data from the TFL api.
+    Tfl(IngestTflArgs),
+    /// Load national rail timetables.
+    NationalRail(IngestNationalRailArgs),
+    /// Load stops and the stations they belong to from NaPTAN. Run after
+    /// `ingest tfl --stops`, which would overwrite the station links.
+    Naptan(IngestNaptanArgs),
+    /// Load bus timetables from TransXChange files. Requires stop points.
+    Transxchange(IngestTxcArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct IngestTflArgs {
+    /// Load stop points.
+    #[clap(long)]
+    stops: bool,
+    /// Load routes.
+    #[clap(long)]
+    routes: bool,
+    /// Load route segments (i.e. between individual stops).
+    /// Requires routes.
+    #[clap(long)]
+    segments: bool,
+    /// Load timetables (departure times). Requires routes.
+    #[clap(long)]
+    timetables: bool,
+    /// Fetch every timetable again, including those that earlier runs loaded.
+    #[clap(long, requires = "timetables")]
+    reload_timetables: bool,
+    /// Leave out bus timetables, e.g. because they come from TransXChange.
+    #[clap(long, requires = "timetables")]
+    skip_bus_timetables: bool,
+    /// Load planned closures for the next `tfl.closure_days` days, replacing
+    /// those loaded before.
+    #[clap(long)]
+    closures: bool,
+    /// Save every TfL response under this directory, for replaying later.
+    #[clap(long, value_name = "DIR")]
+    record_fixtures: Option<PathBuf>,
+    /// Serve TfL responses from previously recorded fixtures instead of the API.
+    #[clap(long, value_name = "DIR", conflicts_with = "record-fixtures")]
+    replay_fixtures: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct IngestNationalRailArgs {
+    /// Download the latest national rail timetable and Darwin reference
+    /// data from S3.
+    #[clap(long)]
+    download: bool,
+    /// Match national rail TIPLOCs to stop points using NaPTAN's rail
+    /// references and the Darwin reference data. Requires stop points.
+    #[clap(long)]
+    crosswalk: bool,
+    /// Insert the national rail timetable into the local storage.
+    /// Requires the TIPLOC crosswalk.
+    #[clap(long)]
+    load: bool,
+    /// Insert the ATOC CIF timetable bundle instead, for the next
+    /// `national_rail.calendar_days` days. Requires the TIPLOC crosswalk.
+    #[clap(long, conflicts_with = "load")]
+    cif: bool,
+    /// The timetable file to load. Defaults to `national_rail.timetable_path`,
+    /// or `national_rail.cif_path` with --cif.
+    #[clap(long)]
+    timetable: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct IngestNaptanArgs {
+    /// The NaPTAN CSV directory. Defaults to `naptan.path`.
+    #[clap(long, value_name = "DIR")]
+    path: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct IngestTxcArgs {
+    /// A directory or zip of TransXChange files. Defaults to
+    /// `transxchange.path`.
+    #[clap(long)]
+    path: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct FixArgs {
+    /// Sort and deduplicate the departure times of all timetables.
+    #[clap(long)]
+    timetables: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct BuildGraphArgs {
+    /// Write the graph data to this file so that it can be served
+    /// without a database.
+    #[clap(long)]
+    out: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct ValidateArgs {
+    /// Where to write the report. Defaults to `validation.report_path`.
+    #[clap(long)]
+    out: Option<PathBuf>,
+    /// Also fail on warnings, e.g. orphan stops or speed outliers.
+    #[clap(long)]
+    strict: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct DiffArgs {
+    /// The network before: a snapshot written by `build-graph --out`, or
+    /// `local` or `atlas` for the local storage or hosted MongoDB.
+    #[clap(long)]
+    before: String,
+    /// The network after, like --before.
+    #[clap(long)]
+    after: String,
+    /// Compare travel times from this stop. Pass several times. Defaults to
+    /// `diff.probe_stop_ids`.
+    #[clap(long)]
+    probe: Vec<String>,
+    /// Departure time of the probes, e.g. 08:30.
+    #[clap(long, default_value = "08:30")]
+    at: String,
+    /// Departure date of the probes as YYYY-MM-DD. Defaults to today.
+    #[clap(long)]
+    on: Option<String>,
+    /// Where to write the report. Defaults to `diff.report_path`.
+    #[clap(long)]
+    out: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// Serve this snapshot instead of the graph in the hosted MongoDB.
+    #[clap(long)]
+    graph: Option<PathBuf>,
+    /// Serve the graph in the local storage instead of the hosted MongoDB.
+    #[clap(long, conflicts_with = "graph")]
+    local: bool,
+    /// Apply live delays and cancellations from the Darwin Push Port.
+    #[clap(long)]
+    push_port: bool,
+    /// Apply delays and cancellations from Push Port messages recorded in this directory.
+    #[clap(long, value_name = "DIR")]
+    push_port_replay: Option<PathBuf>,
+    /// Poll TfL line status and arrivals, and apply them to queries for now.
+    #[clap(long)]
+    tfl_live: bool,
+    /// Poll TfL line status and arrivals from fixtures recorded in this directory.
+    #[clap(long, value_name = "DIR", conflicts_with = "tfl-live")]
+    tfl_live_replay: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct QueryArgs {
+    /// Start location as "lat,lon". Pass several times to get the
+    /// longest travel time from any of them.
+    #[clap(long, required = true)]
+    from: Vec<String>,
+    /// Departure time, e.g. 08:30, or "now".
+    #[clap(long)]
+    at: String,
+    /// Departure date as YYYY-MM-DD, for planned closures. Defaults to today.
+    #[clap(long)]
+    on: Option<String>,
+    /// Query this snapshot instead of the graph in the hosted MongoDB.
+    #[clap(long)]
+    graph: Option<PathBuf>,
+    /// Query the graph in the local storage instead of the hosted MongoDB.
+    #[clap(long, conflicts_with = "graph")]
+    local: bool,
+    /// Apply delays and cancellations from Push Port messages recorded in this directory.
+    #[clap(long, value_name = "DIR")]
+    push_port_replay: Option<PathBuf>,
+    /// Apply TfL's current line status and arrivals.
+    #[clap(long)]
+    tfl_live: bool,
+    /// Apply TfL line status and arrivals from fixtures recorded in this directory.
+    #[clap(long, value_name = "DIR", conflicts_with = "tfl-live")]
+    tfl_live_replay: Option<PathBuf>,
+    #[clap(long, arg_enum, default_value = "csv")]
+    format: OutputFormat,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug)]
+pub enum OutputFormat {
+    Csv,
+    Json,
+}
+
+pub async fn run(command: Command, config: Config) -> anyhow::Result<()> {
+    match command {
+        Command::Ingest(IngestSource::Tfl(args)) => setup::ingest_tfl(args, &config).await,
+        Command::Ingest(IngestSource::NationalRail(args)) => {
+            setup::ingest_national_rail(args, &config).await
+        }
+        Command::Ingest(IngestSource::Naptan(args)) => setup::ingest_naptan(args, &config).await,
+        Command::Ingest(IngestSource::Transxchange(args)) => {
+            setup::ingest_transxchange(args, &config).await
+        }
+        Command::Fix(args) => setup::fix(args, &config).await,
+        Command::CopyToAtlas => setup::copy_to_atlas(&config).await,
+        Command::BuildGraph(args) => setup::build_graph(args, &config).await,
+        Command::Serve(args) => {
+            let source = GraphSource::new(args.graph.clone(), args.local, &config).await?;
+            api::rocket(config, source, args).await
+        }
+        Command::Query(args) => query::run(args, &config).await,
+        Command::Validate(args) => setup::validate(args, &config).await,
+        Command::Diff(args) => setup::diff(args, &config).await,
+    }
+}
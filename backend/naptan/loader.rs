@@ -0,0 +1,146 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::Result;
+use futures::TryStreamExt;
+use tokio::task;
+
+use crate::{
+    db::{repository::Repository, storage::Storage},
+    tfl::model::stops_response::StopPoint,
+};
+
+use super::reader::read_naptan;
+
+pub struct NaptanLoader {
+    stop_repo: Box<dyn Repository<StopPoint>>,
+}
+
+impl NaptanLoader {
+    pub fn new(storage: &Storage) -> Self {
+        Self {
+            stop_repo: storage.repo(),
+        }
+    }
+
+    /// Adds the NaPTAN stops and stop areas whose codes start with one of
+    /// `atco_prefixes`, and links every stop to its station.
+    ///
+    /// Stops that TfL already lists keep their modes and lines.
+    pub async fn load(&self, dir: PathBuf, atco_prefixes: Vec<String>) -> Result<()> {
+        let mut existing: HashMap<String, StopPoint> = self
+            .stop_repo
+            .get_all()
+            .await?
+            .map_ok(|stop| (stop.id.clone(), stop))
+            .try_collect()
+            .await?;
+
+        let naptan = task::spawn_blocking(move || {
+            read_naptan(&dir, |code| {
+                atco_prefixes
+                    .iter()
+                    .any(|prefix| code.starts_with(prefix.as_str()))
+            })
+        })
+        .await??;
+
+        let (mut added, mut updated) = (0, 0);
+        for naptan_stop in &naptan {
+            let stop = match existing.remove(&naptan_stop.id) {
+                Some(mut stop) => {
+                    naptan_stop.update(&mut stop);
+                    updated += 1;
+                    stop
+                }
+                None => {
+                    added += 1;
+                    naptan_stop.to_stop_point()
+                }
+            };
+            self.stop_repo.insert_or_replace(&stop).await?;
+        }
+        println!(
+            "Added {} stops from NaPTAN and updated {}. {} stops aren't in NaPTAN.",
+            added,
+            updated,
+            existing.len()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use chrono::{NaiveDate, NaiveTime};
+
+    use crate::{
+        config::RoutingConfig,
+        db::tfl_loader::Loader,
+        graph::{disruptions::Disruptions, graph_builder::GraphBuilder},
+        tfl::{fixture_client::ReplayClient, model::direct_connection::DirectConnection},
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_load_naptan() {
+        let root = tempfile::tempdir().unwrap();
+        let storage = Storage::Files(root.path().to_path_buf());
+        let mut client = ReplayClient::checked_in();
+        let mut loader = Loader::new(&mut client, &storage);
+        loader.load_stops().await.unwrap();
+
+        let naptan = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/naptan");
+        NaptanLoader::new(&storage)
+            .load(naptan, vec!["490".into(), "940".into()])
+            .await
+            .unwrap();
+
+        let repo = storage.repo::<StopPoint>();
+        let station = repo.get_by_id("940GZZLUHSD").await.unwrap().unwrap();
+        // TfL's lines are kept.
+        assert!(!station.lines.is_empty());
+        assert_eq!(
+            station.child_ids,
+            vec!["9400ZZLUHSD0", "9400ZZLUHSD1", "9400ZZLUHSD2"]
+        );
+        let platform = repo.get_by_id("9400ZZLUHSD1").await.unwrap().unwrap();
+        assert_eq!(platform.station_naptan.as_deref(), Some("940GZZLUHSD"));
+        assert_eq!(platform.indicator.as_deref(), Some("Platform 1"));
+        let bus_stop = repo.get_by_id("490007705S").await.unwrap().unwrap();
+        assert_eq!(bus_stop.bearing.as_deref(), Some("N"));
+        // Inactive, and outside London.
+        assert!(repo.get_by_id("490007705Z").await.unwrap().is_none());
+        assert!(repo.get_by_id("1800SB12341").await.unwrap().is_none());
+
+        // A train from platform 1 is a train from Hammersmith once the
+        // platforms are collapsed.
+        DirectConnection {
+            origin: "9400ZZLUHSD1".into(),
+            destination: "940GZZLURVP".into(),
+            duration_minutes: 2.,
+            departure_times: vec![NaiveTime::from_hms_opt(10, 0, 0).unwrap()],
+            ..Default::default()
+        }
+        .merge_insert(storage.repo().as_ref())
+        .await
+        .unwrap();
+        let snapshot = GraphBuilder::new(&storage).load_snapshot().await.unwrap();
+        let graph = snapshot.build_graph(&RoutingConfig::default()).unwrap();
+        let start = NaiveDate::from_ymd_opt(2022, 5, 2)
+            .unwrap()
+            .and_hms_opt(10, 0, 0)
+            .unwrap();
+        let paths = graph
+            .tt_from_stop_id("9400ZZLUHSD1".into(), start, &Disruptions::default())
+            .unwrap();
+        let ravenscourt_park = paths
+            .iter()
+            .find(|p| p.destination.id == "940GZZLURVP")
+            .unwrap();
+        assert_eq!(ravenscourt_park.minutes(), 2);
+        assert_eq!(ravenscourt_park.path, vec!["940GZZLURVP", "940GZZLUHSD"]);
+    }
+}
@@ -0,0 +1,216 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::tfl::model::stops_response::{StopPoint, StopType};
+
+/// A stop or stop area from the NaPTAN CSV download, with its place in the
+/// station hierarchy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NaptanStop {
+    /// The ATCO code of a stop, or the code of a stop area.
+    pub id: String,
+    pub name: String,
+    pub stop_type: Option<StopType>,
+    pub indicator: Option<String>,
+    pub bearing: Option<String>,
+    pub lat: f64,
+    pub lon: f64,
+    /// The stop area the stop is part of, or the parent of a stop area.
+    pub station: Option<String>,
+    pub children: Vec<String>,
+    pub stop_areas: Vec<String>,
+}
+
+impl NaptanStop {
+    /// Copies the NaPTAN details onto a stop that TfL already knows.
+    pub fn update(&self, stop: &mut StopPoint) {
+        stop.station_naptan = self.station.clone();
+        stop.child_ids = self.children.clone();
+        stop.stop_areas = self.stop_areas.clone();
+        stop.indicator = self.indicator.clone();
+        stop.bearing = self.bearing.clone();
+        stop.stop_type = stop.stop_type.or(self.stop_type);
+    }
+
+    /// A stop that TfL doesn't list, e.g. a platform or an entrance.
+    pub fn to_stop_point(&self) -> StopPoint {
+        StopPoint {
+            naptan_id: self.id.clone(),
+            modes: vec![],
+            stop_type: self.stop_type,
+            station_naptan: self.station.clone(),
+            lines: vec![],
+            id: self.id.clone(),
+            common_name: self.name.clone(),
+            child_ids: self.children.clone(),
+            stop_areas: self.stop_areas.clone(),
+            indicator: self.indicator.clone(),
+            bearing: self.bearing.clone(),
+            additional_properties: vec![],
+            lat: self.lat,
+            lon: self.lon,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct StopRow {
+    #[serde(rename = "ATCOCode")]
+    atco_code: String,
+    common_name: String,
+    indicator: Option<String>,
+    bearing: Option<String>,
+    longitude: Option<f64>,
+    latitude: Option<f64>,
+    stop_type: String,
+    status: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct StopAreaRow {
+    stop_area_code: String,
+    name: String,
+    longitude: Option<f64>,
+    latitude: Option<f64>,
+    stop_area_type: String,
+    status: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct StopInAreaRow {
+    stop_area_code: String,
+    atco_code: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AreaHierarchyRow {
+    parent_stop_area_code: String,
+    child_stop_area_code: String,
+}
+
+// Older downloads abbreviate the status.
+fn is_active(status: &Option<String>) -> bool {
+    matches!(status.as_deref(), None | Some("act") | Some("active"))
+}
+
+/// Reads the stops and stop areas in the NaPTAN CSV download at `dir`
+/// (`Stops.csv`, `StopAreas.csv`, `StopsInArea.csv` and, if present,
+/// `AreaHierarchy.csv`) whose codes `keep` accepts.
+///
+/// A stop's station is the first stop area it is in, and a stop area's is
+/// its parent area. Inactive stops and those without coordinates are left
+/// out.
+pub fn read_naptan(dir: &Path, keep: impl Fn(&str) -> bool) -> Result<Vec<NaptanStop>> {
+    let mut stops: BTreeMap<String, NaptanStop> = BTreeMap::new();
+
+    for row in read_csv::<StopAreaRow>(&dir.join("StopAreas.csv"))? {
+        let row = row?;
+        if let (true, true, Some(lat), Some(lon)) = (
+            keep(&row.stop_area_code),
+            is_active(&row.status),
+            row.latitude,
+            row.longitude,
+        ) {
+            stops.insert(
+                row.stop_area_code.clone(),
+                NaptanStop {
+                    id: row.stop_area_code,
+                    name: row.name,
+                    stop_type: StopType::from_naptan_code(&row.stop_area_type),
+                    indicator: None,
+                    bearing: None,
+                    lat,
+                    lon,
+                    station: None,
+                    children: vec![],
+                    stop_areas: vec![],
+                },
+            );
+        }
+    }
+
+    for row in read_csv::<StopRow>(&dir.join("Stops.csv"))? {
+        let row = row?;
+        if let (true, true, Some(lat), Some(lon)) = (
+            keep(&row.atco_code),
+            is_active(&row.status),
+            row.latitude,
+            row.longitude,
+        ) {
+            stops.insert(
+                row.atco_code.clone(),
+                NaptanStop {
+                    id: row.atco_code,
+                    name: row.common_name,
+                    stop_type: StopType::from_naptan_code(&row.stop_type),
+                    indicator: row.indicator,
+                    bearing: row.bearing,
+                    lat,
+                    lon,
+                    station: None,
+                    children: vec![],
+                    stop_areas: vec![],
+                },
+            );
+        }
+    }
+
+    let mut parents: Vec<(String, String)> = vec![];
+    for row in read_csv::<StopInAreaRow>(&dir.join("StopsInArea.csv"))? {
+        let row = row?;
+        if stops.contains_key(&row.stop_area_code) {
+            if let Some(stop) = stops.get_mut(&row.atco_code) {
+                stop.stop_areas.push(row.stop_area_code.clone());
+                if stop.station.is_none() {
+                    stop.station = Some(row.stop_area_code.clone());
+                    parents.push((row.stop_area_code, row.atco_code));
+                }
+            }
+        }
+    }
+
+    let hierarchy = dir.join("AreaHierarchy.csv");
+    if hierarchy.exists() {
+        for row in read_csv::<AreaHierarchyRow>(&hierarchy)? {
+            let row = row?;
+            if !stops.contains_key(&row.parent_stop_area_code) {
+                continue;
+            }
+            if let Some(area) = stops.get_mut(&row.child_stop_area_code) {
+                if area.station.is_none() {
+                    area.station = Some(row.parent_stop_area_code.clone());
+                    parents.push((row.parent_stop_area_code, row.child_stop_area_code));
+                }
+            }
+        }
+    }
+
+    let mut children: HashMap<String, Vec<String>> = HashMap::new();
+    for (parent, child) in parents {
+        children.entry(parent).or_default().push(child);
+    }
+    for (parent, mut child_ids) in children {
+        child_ids.sort();
+        stops.get_mut(&parent).unwrap().children = child_ids;
+    }
+
+    Ok(stops.into_values().collect())
+}
+
+fn read_csv<T: DeserializeOwned>(path: &Path) -> Result<impl Iterator<Item = Result<T>>> {
+    let reader = csv::Reader::from_path(path)
+        .with_context(|| format!("Couldn't open NaPTAN file {}", path.display()))?;
+    let path = path.display().to_string();
+    Ok(reader
+        .into_deserialize()
+        .map(move |row| row.with_context(|| format!("Invalid row in {}", path))))
+}
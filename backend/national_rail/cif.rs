@@ -0,0 +1,392 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::Path,
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{Datelike, NaiveDate, NaiveTime};
+use zip::ZipArchive;
+
+use super::xml_timetable::{Journey, Stop, StopType};
+
+/// How an STP (short term plan) schedule applies to the permanent one with
+/// the same train UID. On any date, the first of these that runs wins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stp {
+    /// The train doesn't run.
+    Cancellation,
+    New,
+    Overlay,
+    Permanent,
+}
+
+impl Stp {
+    fn parse(indicator: &str) -> Result<Self> {
+        match indicator {
+            "C" => Ok(Stp::Cancellation),
+            "N" => Ok(Stp::New),
+            "O" => Ok(Stp::Overlay),
+            "P" => Ok(Stp::Permanent),
+            _ => bail!("Unknown STP indicator {:?}", indicator),
+        }
+    }
+}
+
+/// A basic schedule (`BS` record) from a CIF timetable, with its locations.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    pub uid: String,
+    pub runs_from: NaiveDate,
+    pub runs_to: NaiveDate,
+    /// Monday first.
+    pub days_run: [bool; 7],
+    pub stp: Stp,
+    pub is_passenger_service: bool,
+    pub atoc_code: String,
+    pub stops: Vec<Stop>,
+}
+
+impl Schedule {
+    pub fn runs_on(&self, date: NaiveDate) -> bool {
+        self.runs_from <= date
+            && date <= self.runs_to
+            && self.days_run[date.weekday().num_days_from_monday() as usize]
+    }
+
+    /// This schedule as the journey it makes on `date`.
+    pub fn journey(&self, date: NaiveDate) -> Journey {
+        Journey {
+            rid: format!("{}{}", date.format("%Y%m%d"), self.uid),
+            uid: self.uid.clone(),
+            train_id: String::new(),
+            ssd: date,
+            toc: self.atoc_code.clone(),
+            is_passenger_service: self.is_passenger_service,
+            deleted: false,
+            cancel_reason: None,
+            stops: self.stops.clone(),
+        }
+    }
+}
+
+/// A station from the MSN (master station names) file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MsnStation {
+    pub tiploc: String,
+    pub crs: String,
+    pub name: String,
+    /// Minimum minutes to change trains here.
+    pub interchange_minutes: u16,
+}
+
+/// The parts of an ATOC CIF timetable bundle that matter for routing.
+#[derive(Debug, Default)]
+pub struct CifTimetable {
+    schedules: HashMap<String, Vec<Schedule>>,
+    pub stations: Vec<MsnStation>,
+}
+
+impl CifTimetable {
+    /// Reads the MCA schedules and MSN stations in the zip at `path`.
+    ///
+    /// Only schedules that run between `from` and `to` are kept. Permanent
+    /// schedules are dropped and STP ones lose their locations unless they
+    /// carry passengers and call at two or more locations that `keep`
+    /// accepts, which keeps the timetable of a region small enough to hold
+    /// in memory.
+    pub fn read(
+        path: &Path,
+        from: NaiveDate,
+        to: NaiveDate,
+        keep: impl Fn(&str) -> bool,
+    ) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Couldn't open {}", path.display()))?;
+        let mut archive = ZipArchive::new(file)?;
+        let names: Vec<String> = archive.file_names().map(String::from).collect();
+        let find = |extension: &str| {
+            names
+                .iter()
+                .find(|name| name.to_uppercase().ends_with(extension))
+                .cloned()
+                .with_context(|| format!("No {} file in {}", extension, path.display()))
+        };
+        let (mca, msn) = (find(".MCA")?, find(".MSN")?);
+
+        let mut timetable = CifTimetable {
+            stations: read_msn(archive.by_name(&msn)?)?,
+            ..Default::default()
+        };
+        for schedule in read_mca(archive.by_name(&mca)?) {
+            let mut schedule = schedule?;
+            if schedule.runs_to < from || to < schedule.runs_from {
+                continue;
+            }
+            if !schedule.is_passenger_service
+                || schedule.stops.iter().filter(|s| keep(&s.tiploc)).count() < 2
+            {
+                if schedule.stp == Stp::Permanent {
+                    continue;
+                }
+                // Still needed to cancel or replace a permanent schedule.
+                schedule.stops.clear();
+            }
+            timetable
+                .schedules
+                .entry(schedule.uid.clone())
+                .or_default()
+                .push(schedule);
+        }
+        Ok(timetable)
+    }
+
+    /// The schedules that run on `date`, after STP cancellations, new
+    /// schedules and overlays have been applied.
+    pub fn running_on(&self, date: NaiveDate) -> impl Iterator<Item = &Schedule> {
+        self.schedules.values().filter_map(move |schedules| {
+            schedules
+                .iter()
+                .filter(|s| s.runs_on(date))
+                .min_by_key(|s| s.stp)
+                .filter(|s| s.stp != Stp::Cancellation)
+        })
+    }
+}
+
+// Fixed width records are sliced by column, and short lines read as blank.
+fn field(line: &str, start: usize, len: usize) -> &str {
+    line.get(start..(start + len).min(line.len()))
+        .unwrap_or_default()
+        .trim()
+}
+
+fn read_msn(input: impl Read) -> Result<Vec<MsnStation>> {
+    let mut stations = vec![];
+    for line in BufReader::new(input).lines() {
+        let line = line?;
+        // The header is an A record too.
+        if !line.starts_with('A') || line.contains("FILE-SPEC=") {
+            continue;
+        }
+        stations.push(MsnStation {
+            tiploc: field(&line, 36, 7).to_string(),
+            crs: field(&line, 49, 3).to_string(),
+            name: field(&line, 5, 26).to_string(),
+            interchange_minutes: field(&line, 63, 2).parse().unwrap_or_default(),
+        });
+    }
+    Ok(stations)
+}
+
+fn read_mca(input: impl Read) -> impl Iterator<Item = Result<Schedule>> {
+    let mut lines = BufReader::new(input).lines();
+    let mut current: Option<Schedule> = None;
+    // Reported after the schedule before it.
+    let mut error = None;
+    std::iter::from_fn(move || loop {
+        if let Some(e) = error.take() {
+            return Some(Err(e));
+        }
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            Some(Err(e)) => return Some(Err(e.into())),
+            None => return current.take().map(Ok),
+        };
+        match line.get(..2).unwrap_or_default() {
+            "BS" => {
+                let finished = current.take();
+                match parse_basic_schedule(&line) {
+                    Ok(schedule) => current = schedule,
+                    Err(e) => error = Some(e),
+                }
+                if let Some(finished) = finished {
+                    return Some(Ok(finished));
+                }
+            }
+            "BX" => {
+                if let Some(schedule) = current.as_mut() {
+                    schedule.atoc_code = field(&line, 11, 2).to_string();
+                }
+            }
+            "LO" | "LI" | "LT" => {
+                if let Some(schedule) = current.as_mut() {
+                    schedule.stops.push(parse_location(&line));
+                }
+            }
+            _ => {}
+        }
+    })
+}
+
+// Deletions only appear in update extracts, which aren't supported.
+fn parse_basic_schedule(line: &str) -> Result<Option<Schedule>> {
+    if field(line, 2, 1) == "D" {
+        return Ok(None);
+    }
+    let days = field(line, 21, 7).as_bytes();
+    let mut days_run = [false; 7];
+    for (day, runs) in days_run.iter_mut().enumerate() {
+        *runs = days.get(day) == Some(&b'1');
+    }
+    Ok(Some(Schedule {
+        uid: field(line, 3, 6).to_string(),
+        runs_from: parse_date(field(line, 9, 6))?,
+        runs_to: parse_date(field(line, 15, 6))?,
+        days_run,
+        stp: Stp::parse(field(line, 79, 1))?,
+        // Passenger trains, buses and their STP equivalents.
+        is_passenger_service: matches!(field(line, 29, 1), "P" | "1" | "B" | "5"),
+        atoc_code: String::new(),
+        stops: vec![],
+    }))
+}
+
+fn parse_location(line: &str) -> Stop {
+    // The eighth character of the location is a suffix for TIPLOCs that a
+    // train visits twice.
+    let tiploc = field(line, 2, 7).to_string();
+    let (stop_type, times, activities) = match &line[..2] {
+        "LO" => (
+            StopType::Origin,
+            [None, Some(10), None, None, Some(15)],
+            field(line, 29, 12),
+        ),
+        "LT" => (
+            StopType::Destination,
+            [Some(10), None, None, Some(15), None],
+            field(line, 25, 12),
+        ),
+        _ if !field(line, 20, 5).is_empty() => (
+            StopType::PassThrough,
+            [None, None, Some(20), None, None],
+            "",
+        ),
+        _ => (
+            StopType::Intermediate,
+            [Some(10), Some(15), None, Some(25), Some(29)],
+            field(line, 42, 12),
+        ),
+    };
+    let working = |start: Option<usize>| start.and_then(|start| parse_time(field(line, start, 5)));
+    let working_arrival = working(times[0]);
+    let working_departure = working(times[1]);
+    let public = |start: Option<usize>, working: Option<NaiveTime>| {
+        start.and_then(|start| parse_public_time(field(line, start, 4), working))
+    };
+    Stop {
+        stop_type,
+        tiploc,
+        activities: activities.to_string(),
+        public_arrival: public(times[3], working_arrival),
+        public_departure: public(times[4], working_departure),
+        working_arrival,
+        working_departure,
+        working_pass: working(times[2]),
+        cancelled: false,
+    }
+}
+
+// HHMM, with an H for an extra half minute.
+fn parse_time(time: &str) -> Option<NaiveTime> {
+    let hours = time.get(0..2)?.parse().ok()?;
+    let minutes = time.get(2..4)?.parse().ok()?;
+    let seconds = if time.get(4..5) == Some("H") { 30 } else { 0 };
+    NaiveTime::from_hms_opt(hours, minutes, seconds)
+}
+
+// 0000 means there's no public time, unless the train really is there at
+// midnight.
+fn parse_public_time(time: &str, working: Option<NaiveTime>) -> Option<NaiveTime> {
+    let public = parse_time(time)?;
+    let near_midnight = working.is_some_and(|working| {
+        let minutes = (working - NaiveTime::MIN).num_minutes();
+        !(1..1439).contains(&minutes)
+    });
+    if public == NaiveTime::MIN && !near_midnight {
+        return None;
+    }
+    Some(public)
+}
+
+// YYMMDD, with 999999 for schedules that run indefinitely.
+fn parse_date(date: &str) -> Result<NaiveDate> {
+    if date == "999999" {
+        return Ok(NaiveDate::MAX);
+    }
+    NaiveDate::parse_from_str(&format!("20{}", date), "%Y%m%d")
+        .with_context(|| format!("Invalid CIF date {}", date))
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use std::io::Write;
+
+    use chrono::Timelike;
+    use zip::{write::FileOptions, ZipWriter};
+
+    use super::*;
+
+    /// The bundle in `fixtures/cif`, zipped.
+    pub(in crate::national_rail) fn fixture_zip() -> tempfile::NamedTempFile {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/cif");
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut zip = ZipWriter::new(file.reopen().unwrap());
+        for name in ["sample.MCA", "sample.MSN"] {
+            zip.start_file(name, FileOptions::default()).unwrap();
+            zip.write_all(&std::fs::read(fixtures.join(name)).unwrap())
+                .unwrap();
+        }
+        zip.finish().unwrap();
+        file
+    }
+
+    #[test]
+    fn test_read_cif() {
+        let file = fixture_zip();
+        let date = |day| NaiveDate::from_ymd_opt(2022, 5, day).unwrap();
+        let timetable = CifTimetable::read(file.path(), date(2), date(8), |tiploc| {
+            ["PADTON", "EALINGB", "SLOUGH"].contains(&tiploc)
+        })
+        .unwrap();
+        assert_eq!(timetable.stations.len(), 3);
+        assert_eq!(timetable.stations[1].tiploc, "EALINGB");
+        assert_eq!(timetable.stations[1].interchange_minutes, 5);
+
+        let running = |day| {
+            let mut running = timetable
+                .running_on(date(day))
+                .map(|s| (s.uid.as_str(), s.stp))
+                .collect::<Vec<_>>();
+            running.sort();
+            running
+        };
+        // The freight train isn't kept.
+        assert_eq!(running(2), vec![("C10001", Stp::Permanent)]);
+        assert_eq!(running(4), vec![("C10001", Stp::Overlay)]);
+        assert_eq!(running(5), vec![]);
+        assert_eq!(running(7), vec![("C20002", Stp::New)]);
+
+        let permanent = timetable.running_on(date(2)).next().unwrap();
+        let journey = permanent.journey(date(2));
+        let legs = journey
+            .legs()
+            .iter()
+            .map(|leg| (leg.origin.tiploc.as_str(), leg.departure.time()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            legs,
+            vec![
+                ("PADTON", NaiveTime::from_hms_opt(10, 0, 0).unwrap()),
+                ("EALINGB", NaiveTime::from_hms_opt(10, 12, 0).unwrap()),
+            ]
+        );
+
+        // Leaves at 23:50 and arrives just after midnight.
+        let new = timetable.running_on(date(7)).next().unwrap();
+        let journey = new.journey(date(7));
+        let leg = &journey.legs()[0];
+        assert_eq!(leg.origin.working_departure.unwrap().second(), 30);
+        assert_eq!(leg.arrival, date(8).and_hms_opt(0, 10, 0).unwrap());
+    }
+}
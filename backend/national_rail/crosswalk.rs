@@ -0,0 +1,315 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use quick_xml::{events::Event, Reader};
+use serde::{Deserialize, Serialize};
+
+use crate::db::mongo_doc::MongoDoc;
+
+use super::{push_port::attributes, xml_timetable::open_xml};
+
+/// Which stop point a national rail TIPLOC is, according to NaPTAN and the
+/// Darwin reference data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TiplocMapping {
+    #[serde(rename = "_id")]
+    pub tiploc: String,
+    pub crs: Option<String>,
+    pub name: Option<String>,
+    /// The NaPTAN code of the station.
+    pub atco_code: Option<String>,
+    /// The stored stop point, if the station is one.
+    pub stop_id: Option<String>,
+    /// Minimum minutes to change trains at the station, from a CIF
+    /// timetable's MSN file.
+    #[serde(default)]
+    pub interchange_minutes: Option<u16>,
+}
+
+impl MongoDoc for TiplocMapping {
+    fn database_name() -> &'static str {
+        "tfl"
+    }
+
+    fn collection_name() -> &'static str {
+        "tiplocs"
+    }
+
+    fn id(&self) -> String {
+        self.tiploc.clone()
+    }
+}
+
+impl TiplocMapping {
+    fn unmatched_reason(&self) -> Option<String> {
+        match (&self.atco_code, &self.stop_id) {
+            (_, Some(_)) => None,
+            (None, None) => Some("No NaPTAN code in the rail references".into()),
+            (Some(atco_code), None) => Some(format!("{} isn't a stop point", atco_code)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RailReference {
+    atco_code: String,
+    tiploc_code: String,
+    crs_code: Option<String>,
+    station_name: Option<String>,
+}
+
+/// Maps every TIPLOC in NaPTAN's `RailReferences.csv` and the Darwin
+/// reference data (`LocationRef`s) to one of `stop_ids`.
+///
+/// TIPLOCs that NaPTAN doesn't list, e.g. the low level platforms of a
+/// station, are matched through the station's CRS code.
+pub fn build_crosswalk(
+    rail_references: &Path,
+    darwin_reference: &Path,
+    stop_ids: &HashSet<String>,
+) -> Result<Vec<TiplocMapping>> {
+    let mut mappings: BTreeMap<String, TiplocMapping> = BTreeMap::new();
+    let mut atco_by_crs: HashMap<String, String> = HashMap::new();
+
+    let mut reader = csv::Reader::from_path(rail_references).with_context(|| {
+        format!(
+            "Couldn't open rail references {}",
+            rail_references.display()
+        )
+    })?;
+    for row in reader.deserialize() {
+        let row: RailReference = row?;
+        if let Some(crs) = &row.crs_code {
+            atco_by_crs
+                .entry(crs.clone())
+                .or_insert_with(|| row.atco_code.clone());
+        }
+        mappings.insert(
+            row.tiploc_code.clone(),
+            TiplocMapping {
+                tiploc: row.tiploc_code,
+                crs: row.crs_code,
+                name: row.station_name,
+                atco_code: Some(row.atco_code),
+                stop_id: None,
+                interchange_minutes: None,
+            },
+        );
+    }
+
+    for location in read_location_refs(darwin_reference)? {
+        let mapping = mappings
+            .entry(location.tiploc.clone())
+            .or_insert_with(|| location.clone());
+        mapping.crs = mapping.crs.take().or(location.crs);
+        mapping.name = mapping.name.take().or(location.name);
+    }
+
+    for mapping in mappings.values_mut() {
+        if mapping.atco_code.is_none() {
+            mapping.atco_code = mapping
+                .crs
+                .as_ref()
+                .and_then(|crs| atco_by_crs.get(crs))
+                .cloned();
+        }
+        mapping.stop_id = mapping
+            .atco_code
+            .as_deref()
+            .and_then(|atco_code| stop_id(atco_code, stop_ids));
+    }
+
+    Ok(mappings.into_values().collect())
+}
+
+// TfL knows stations by their NaPTAN stop area (e.g. 910GPADTON) rather than
+// the station's own code (9100PADTON).
+fn stop_id(atco_code: &str, stop_ids: &HashSet<String>) -> Option<String> {
+    let stop_area = match (atco_code.get(..3), atco_code.get(3..4), atco_code.get(4..)) {
+        (Some(prefix), Some("0"), Some(code)) if prefix.starts_with('9') => {
+            Some(format!("{}G{}", prefix, code))
+        }
+        _ => None,
+    };
+    [Some(atco_code.to_string()), stop_area]
+        .into_iter()
+        .flatten()
+        .find(|id| stop_ids.contains(id))
+}
+
+fn read_location_refs(path: &Path) -> Result<Vec<TiplocMapping>> {
+    let (input, _) = open_xml(path)
+        .with_context(|| format!("Couldn't open Darwin reference data {}", path.display()))?;
+    let mut reader = Reader::from_reader(input);
+    let mut buf = vec![];
+    let mut locations = vec![];
+    loop {
+        match reader.read_event(&mut buf)? {
+            Event::Start(element) | Event::Empty(element)
+                if element.local_name() == b"LocationRef" =>
+            {
+                let attrs = attributes(&element)?;
+                if let Some(tiploc) = attrs.get("tpl") {
+                    locations.push(TiplocMapping {
+                        tiploc: tiploc.clone(),
+                        crs: attrs.get("crs").cloned(),
+                        name: attrs.get("locname").cloned(),
+                        atco_code: None,
+                        stop_id: None,
+                        interchange_minutes: None,
+                    });
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(locations)
+}
+
+#[derive(Debug, Serialize)]
+struct UnmatchedTiploc {
+    tiploc: String,
+    crs: Option<String>,
+    name: Option<String>,
+    atco_code: Option<String>,
+    reason: String,
+    /// Timetable legs dropped because of it.
+    legs: usize,
+}
+
+/// The TIPLOCs that couldn't be matched to a stop point, and why.
+#[derive(Debug, Default)]
+pub struct UnmatchedTiplocs {
+    tiplocs: BTreeMap<String, UnmatchedTiploc>,
+}
+
+impl UnmatchedTiplocs {
+    pub fn from_crosswalk(mappings: &[TiplocMapping]) -> Self {
+        let mut unmatched = Self::default();
+        for mapping in mappings {
+            unmatched.add(&mapping.tiploc, Some(mapping));
+        }
+        unmatched
+    }
+
+    pub fn len(&self) -> usize {
+        self.tiplocs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiplocs.is_empty()
+    }
+
+    /// Records `tiploc` if `mapping` (its crosswalk entry, if any) doesn't
+    /// lead to a stop point.
+    fn add(&mut self, tiploc: &str, mapping: Option<&TiplocMapping>) {
+        let reason = match mapping {
+            Some(mapping) => match mapping.unmatched_reason() {
+                Some(reason) => reason,
+                None => return,
+            },
+            None => "Not in the reference data".to_string(),
+        };
+        self.tiplocs
+            .entry(tiploc.to_string())
+            .or_insert_with(|| UnmatchedTiploc {
+                tiploc: tiploc.to_string(),
+                crs: mapping.and_then(|m| m.crs.clone()),
+                name: mapping.and_then(|m| m.name.clone()),
+                atco_code: mapping.and_then(|m| m.atco_code.clone()),
+                reason,
+                legs: 0,
+            });
+    }
+
+    /// Records a timetable leg that was dropped because `tiploc` is unmatched.
+    pub fn add_leg(&mut self, tiploc: &str, mapping: Option<&TiplocMapping>) {
+        self.add(tiploc, mapping);
+        if let Some(unmatched) = self.tiplocs.get_mut(tiploc) {
+            unmatched.legs += 1;
+        }
+    }
+
+    /// Writes the report as CSV, the TIPLOCs that cost the most legs first.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut rows: Vec<_> = self.tiplocs.values().collect();
+        rows.sort_by_key(|row| std::cmp::Reverse(row.legs));
+        let mut writer = csv::Writer::from_path(path)
+            .with_context(|| format!("Couldn't write {}", path.display()))?;
+        for row in rows {
+            writer.serialize(row)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn test_build_crosswalk() {
+        let mut rail_references = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            rail_references,
+            "AtcoCode,TiplocCode,CrsCode,StationName,StationNameLang,GridType,Easting,Northing\n\
+             9100PADTON,PADTON,PAD,London Paddington Rail Station,,U,526500,181250\n\
+             9100EALINGB,EALINGB,EAL,Ealing Broadway Rail Station,,U,517850,180770\n\
+             9100RDNGSTN,RDNGSTN,RDG,Reading Rail Station,,U,471600,173900\n"
+        )
+        .unwrap();
+        let mut darwin_reference = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            darwin_reference,
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<PportTimetableRef xmlns="http://www.thalesgroup.com/rtti/XmlRefData/v3">
+  <LocationRef tpl="PADTON" crs="PAD" toc="NR" locname="London Paddington" />
+  <LocationRef tpl="PADTLL" crs="PAD" toc="NR" locname="London Paddington" />
+  <LocationRef tpl="ROYAOJN" locname="Royal Oak Junction" />
+</PportTimetableRef>"#
+        )
+        .unwrap();
+        let stop_ids = ["910GPADTON", "910GEALINGB"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        let mappings =
+            build_crosswalk(rail_references.path(), darwin_reference.path(), &stop_ids).unwrap();
+        let stop = |tiploc: &str| {
+            mappings
+                .iter()
+                .find(|m| m.tiploc == tiploc)
+                .unwrap()
+                .stop_id
+                .clone()
+        };
+        assert_eq!(stop("PADTON").as_deref(), Some("910GPADTON"));
+        // Through Paddington's CRS code.
+        assert_eq!(stop("PADTLL").as_deref(), Some("910GPADTON"));
+        assert_eq!(stop("EALINGB").as_deref(), Some("910GEALINGB"));
+        assert_eq!(stop("RDNGSTN"), None);
+        assert_eq!(stop("ROYAOJN"), None);
+
+        let mut unmatched = UnmatchedTiplocs::from_crosswalk(&mappings);
+        assert_eq!(unmatched.len(), 2);
+        unmatched.add_leg("RDNGSTN", mappings.iter().find(|m| m.tiploc == "RDNGSTN"));
+        unmatched.add_leg("SLOUGH", None);
+        assert_eq!(unmatched.len(), 3);
+        assert_eq!(unmatched.tiplocs["RDNGSTN"].legs, 1);
+        assert_eq!(
+            unmatched.tiplocs["SLOUGH"].reason,
+            "Not in the reference data"
+        );
+    }
+}
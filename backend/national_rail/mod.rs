@@ -1,3 +1,8 @@
+pub mod cif;
+pub mod crosswalk;
+pub mod push_port;
+pub mod push_port_consumer;
 pub mod s3;
+pub mod stomp;
 pub mod timetable_loader;
 pub mod xml_timetable;
@@ -0,0 +1,501 @@
+use std::{
+    collections::HashMap,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use flate2::read::GzDecoder;
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+
+use crate::graph::{
+    disruptions::{DepartureChange, Disruptions},
+    tfl_graph::TflGraph,
+};
+
+use super::xml_timetable::{passenger_legs, picks_up, sets_down};
+
+/// What part a calling point plays in a journey. Edges join the passenger
+/// stops among origin, intermediate and destination points.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointKind {
+    Origin,
+    Intermediate,
+    Destination,
+    /// Passing points and optional stops.
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallingPoint {
+    pub tiploc: String,
+    pub kind: PointKind,
+    /// Darwin activity codes, if the message gives them.
+    pub activities: Option<String>,
+    /// Minutes since midnight.
+    pub working_arrival: Option<u16>,
+    pub working_departure: Option<u16>,
+    pub public_arrival: Option<u16>,
+    pub public_departure: Option<u16>,
+    pub cancelled: bool,
+}
+
+impl CallingPoint {
+    fn picks_up(&self) -> bool {
+        matches!(self.kind, PointKind::Origin | PointKind::Intermediate)
+            && self.activities.as_deref().is_none_or(picks_up)
+    }
+
+    fn sets_down(&self) -> bool {
+        matches!(self.kind, PointKind::Intermediate | PointKind::Destination)
+            && self.activities.as_deref().is_none_or(sets_down)
+    }
+
+    fn key(&self) -> (String, Option<u16>, Option<u16>) {
+        (
+            self.tiploc.clone(),
+            self.working_arrival,
+            self.working_departure,
+        )
+    }
+}
+
+/// Estimated or actual times at a calling point, in minutes since midnight.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Forecast {
+    pub arrival: Option<u16>,
+    pub departure: Option<u16>,
+}
+
+/// The parts of a Push Port message that affect routing.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Update {
+    Schedule {
+        rid: String,
+        ssd: Option<NaiveDate>,
+        points: Vec<CallingPoint>,
+        deleted: bool,
+    },
+    TrainStatus {
+        rid: String,
+        ssd: Option<NaiveDate>,
+        points: Vec<(CallingPoint, Forecast)>,
+    },
+    Deactivated {
+        rid: String,
+    },
+}
+
+/// Parses a Push Port message, gzipped or not.
+pub fn parse_message(body: &[u8]) -> Result<Vec<Update>> {
+    let xml = if body.starts_with(&[0x1f, 0x8b]) {
+        let mut xml = String::new();
+        GzDecoder::new(body).read_to_string(&mut xml)?;
+        xml
+    } else {
+        String::from_utf8(body.to_vec())?
+    };
+    parse_xml(&xml)
+}
+
+fn parse_xml(xml: &str) -> Result<Vec<Update>> {
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+    let mut buf = vec![];
+    let mut updates = vec![];
+    // The update that is currently being read, if it is one we care about.
+    let mut current: Option<Update> = None;
+
+    loop {
+        let (element, is_empty) = match reader.read_event(&mut buf)? {
+            Event::Start(e) => (e.into_owned(), false),
+            Event::Empty(e) => (e.into_owned(), true),
+            Event::End(e) => {
+                if matches!(e.local_name(), b"schedule" | b"TS") {
+                    updates.extend(current.take());
+                }
+                buf.clear();
+                continue;
+            }
+            Event::Eof => break,
+            _ => {
+                buf.clear();
+                continue;
+            }
+        };
+        let attrs = attributes(&element)?;
+        let attr = |key: &str| attrs.get(key).map(String::as_str);
+
+        match (element.local_name(), current.as_mut()) {
+            (b"schedule", _) => {
+                current = Some(Update::Schedule {
+                    rid: attr("rid").unwrap_or_default().to_string(),
+                    ssd: attr("ssd").and_then(parse_date),
+                    points: vec![],
+                    deleted: attr("deleted") == Some("true"),
+                });
+            }
+            (b"TS", _) => {
+                current = Some(Update::TrainStatus {
+                    rid: attr("rid").unwrap_or_default().to_string(),
+                    ssd: attr("ssd").and_then(parse_date),
+                    points: vec![],
+                });
+            }
+            (b"deactivated", _) => updates.push(Update::Deactivated {
+                rid: attr("rid").unwrap_or_default().to_string(),
+            }),
+            (name, Some(Update::Schedule { points, .. })) => {
+                let kind = match name {
+                    b"OR" => Some(PointKind::Origin),
+                    b"IP" => Some(PointKind::Intermediate),
+                    b"DT" => Some(PointKind::Destination),
+                    b"OPOR" | b"OPIP" | b"PP" | b"OPDT" => Some(PointKind::Other),
+                    _ => None,
+                };
+                if let Some(kind) = kind {
+                    points.push(calling_point(&attrs, kind));
+                }
+            }
+            (b"Location", Some(Update::TrainStatus { points, .. })) => {
+                // Train status doesn't say what kind of stop a location is,
+                // but the working times give it away.
+                let kind = match (attr("wtp"), attr("wta"), attr("wtd")) {
+                    (Some(_), _, _) => PointKind::Other,
+                    (None, Some(_), Some(_)) => PointKind::Intermediate,
+                    (None, None, Some(_)) => PointKind::Origin,
+                    (None, Some(_), None) => PointKind::Destination,
+                    (None, None, None) => PointKind::Other,
+                };
+                points.push((calling_point(&attrs, kind), Forecast::default()));
+            }
+            (name @ (b"arr" | b"dep"), Some(Update::TrainStatus { points, .. })) => {
+                // Actual times trump estimates.
+                let time = attr("at").or_else(|| attr("et")).and_then(parse_time);
+                if let Some((_, forecast)) = points.last_mut() {
+                    match name {
+                        b"arr" => forecast.arrival = time.or(forecast.arrival),
+                        _ => forecast.departure = time.or(forecast.departure),
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        // Empty elements have no end event.
+        if is_empty && matches!(element.local_name(), b"schedule" | b"TS") {
+            updates.extend(current.take());
+        }
+        buf.clear();
+    }
+
+    Ok(updates)
+}
+
+pub(super) fn attributes(element: &BytesStart) -> Result<HashMap<String, String>> {
+    element
+        .attributes()
+        .map(|attr| {
+            let attr = attr?;
+            Ok((
+                String::from_utf8(attr.key.to_vec())?,
+                String::from_utf8(attr.unescaped_value()?.to_vec())?,
+            ))
+        })
+        .collect()
+}
+
+fn calling_point(attrs: &HashMap<String, String>, kind: PointKind) -> CallingPoint {
+    let time = |key: &str| attrs.get(key).and_then(|t| parse_time(t));
+    CallingPoint {
+        tiploc: attrs.get("tpl").cloned().unwrap_or_default(),
+        kind,
+        activities: attrs.get("act").cloned(),
+        working_arrival: time("wta"),
+        working_departure: time("wtd"),
+        public_arrival: time("pta"),
+        public_departure: time("ptd"),
+        cancelled: attrs.get("can").map(String::as_str) == Some("true"),
+    }
+}
+
+/// Parses `HH:MM` or `HH:MM:SS` into minutes since midnight, dropping seconds
+/// like the timetable loader does.
+fn parse_time(time: &str) -> Option<u16> {
+    let hours: u16 = time.get(0..2)?.parse().ok()?;
+    let minutes: u16 = time.get(3..5)?.parse().ok()?;
+    Some((hours * 60 + minutes) % 1440)
+}
+
+fn parse_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()
+}
+
+/// Minutes from `scheduled` to `actual`, treating anything more than twelve
+/// hours late as early, which isn't a delay.
+fn minutes_late(scheduled: u16, actual: u16) -> u16 {
+    let late = (actual as i32 - scheduled as i32).rem_euclid(1440) as u16;
+    if late > 720 {
+        0
+    } else {
+        late
+    }
+}
+
+#[derive(Debug, Default)]
+struct Train {
+    ssd: Option<NaiveDate>,
+    has_schedule: bool,
+    points: Vec<CallingPoint>,
+    forecasts: HashMap<(String, Option<u16>, Option<u16>), Forecast>,
+}
+
+/// The latest known state of every running train, built up from Push Port updates.
+#[derive(Debug, Default)]
+pub struct PushPortState {
+    trains: HashMap<String, Train>,
+}
+
+impl PushPortState {
+    pub fn train_count(&self) -> usize {
+        self.trains.len()
+    }
+
+    pub fn apply(&mut self, update: Update) {
+        match update {
+            Update::Schedule {
+                rid, deleted: true, ..
+            }
+            | Update::Deactivated { rid } => {
+                self.trains.remove(&rid);
+            }
+            Update::Schedule {
+                rid, ssd, points, ..
+            } => {
+                let train = self.trains.entry(rid).or_default();
+                train.ssd = ssd.or(train.ssd);
+                train.has_schedule = true;
+                train.points = points;
+            }
+            Update::TrainStatus { rid, ssd, points } => {
+                let train = self.trains.entry(rid).or_default();
+                train.ssd = ssd.or(train.ssd);
+                for (point, forecast) in points {
+                    let existing = train.forecasts.entry(point.key()).or_default();
+                    existing.arrival = forecast.arrival.or(existing.arrival);
+                    existing.departure = forecast.departure.or(existing.departure);
+
+                    // Without a schedule, the calling points are pieced
+                    // together from train status messages.
+                    if !train.has_schedule && !train.points.iter().any(|p| p.key() == point.key()) {
+                        train.points.push(point);
+                    }
+                }
+                if !train.has_schedule {
+                    let first = train.points.first().and_then(scheduled_time);
+                    train.points.sort_by_key(|p| {
+                        scheduled_time(p)
+                            .zip(first)
+                            .map(|(t, first)| (t as i32 - first as i32).rem_euclid(1440))
+                    });
+                }
+            }
+        }
+    }
+
+    pub fn apply_message(&mut self, body: &[u8]) -> Result<()> {
+        for update in parse_message(body)? {
+            self.apply(update);
+        }
+        Ok(())
+    }
+
+    /// Forgets trains that ran before `date`.
+    pub fn prune(&mut self, date: NaiveDate) {
+        self.trains
+            .retain(|_, train| train.ssd.is_none_or(|ssd| ssd >= date));
+    }
+
+    /// Applies every message recorded in `dir`, in file name order. Files may be gzipped.
+    pub async fn replay(&mut self, dir: &Path) -> Result<usize> {
+        let mut entries = tokio::fs::read_dir(dir)
+            .await
+            .with_context(|| format!("Could not read recorded messages in {}", dir.display()))?;
+        let mut paths: Vec<PathBuf> = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_file() {
+                paths.push(entry.path());
+            }
+        }
+        paths.sort();
+
+        for path in &paths {
+            let body = tokio::fs::read(path).await?;
+            self.apply_message(&body)
+                .with_context(|| format!("Could not parse {}", path.display()))?;
+        }
+        Ok(paths.len())
+    }
+
+    /// Delays and cancellations on the edges of `graph`.
+    pub fn disruptions(&self, graph: &TflGraph) -> Disruptions {
+        let mut disruptions = Disruptions::default();
+        for train in self.trains.values() {
+            // Edges join passenger stops, like the timetable's. Cancelled
+            // stops are kept so that their edges can be cancelled.
+            let legs = passenger_legs(
+                &train.points,
+                CallingPoint::picks_up,
+                CallingPoint::sets_down,
+            );
+            for (prev, current) in legs {
+                let (prev, current) = (&train.points[prev], &train.points[current]);
+                let (origin, destination, departure) = match (
+                    graph.station_for_tiploc(&prev.tiploc),
+                    graph.station_for_tiploc(&current.tiploc),
+                    prev.public_departure.or(prev.working_departure),
+                ) {
+                    (Some(o), Some(d), Some(dep)) => (o, d, dep),
+                    _ => continue,
+                };
+
+                if prev.cancelled || current.cancelled {
+                    disruptions.set_departure(
+                        origin,
+                        destination,
+                        departure,
+                        DepartureChange::Cancelled,
+                    );
+                    continue;
+                }
+
+                let forecast = |p: &CallingPoint| train.forecasts.get(&p.key()).copied();
+                let arrival_delay = forecast(current)
+                    .and_then(|f| f.arrival)
+                    .zip(current.public_arrival.or(current.working_arrival))
+                    .map(|(actual, scheduled)| minutes_late(scheduled, actual));
+                let departure_delay = forecast(prev)
+                    .and_then(|f| f.departure)
+                    .zip(prev.public_departure.or(prev.working_departure))
+                    .map(|(actual, scheduled)| minutes_late(scheduled, actual));
+
+                match arrival_delay.or(departure_delay) {
+                    Some(delay) if delay > 0 => disruptions.set_departure(
+                        origin,
+                        destination,
+                        departure,
+                        DepartureChange::Delayed(delay),
+                    ),
+                    _ => {}
+                }
+            }
+        }
+        disruptions
+    }
+}
+
+fn scheduled_time(point: &CallingPoint) -> Option<u16> {
+    point.working_departure.or(point.working_arrival)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveTime;
+
+    use crate::{
+        config::RoutingConfig,
+        national_rail::crosswalk::TiplocMapping,
+        tfl::model::{direct_connection::DirectConnection, stops_response::StopPoint},
+    };
+
+    use super::*;
+
+    fn stop(id: &str, lon: f64) -> StopPoint {
+        serde_json::from_value(serde_json::json!({
+            "naptanId": id, "modes": ["national-rail"], "lines": [], "id": id,
+            "commonName": id, "lat": 51.5, "lon": lon,
+        }))
+        .unwrap()
+    }
+
+    fn tiploc(tiploc: &str, stop_id: &str) -> TiplocMapping {
+        TiplocMapping {
+            tiploc: tiploc.into(),
+            crs: None,
+            name: None,
+            atco_code: None,
+            stop_id: Some(stop_id.into()),
+            interchange_minutes: None,
+        }
+    }
+
+    fn connection(origin: &str, destination: &str, times: &[&str]) -> DirectConnection {
+        DirectConnection {
+            origin: origin.into(),
+            destination: destination.into(),
+            duration_minutes: 10.,
+            departure_times: times
+                .iter()
+                .map(|t| NaiveTime::parse_from_str(t, "%H:%M").unwrap())
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recorded_messages() {
+        let mut graph = TflGraph::new(RoutingConfig::default());
+        graph
+            .add_stations(
+                vec![
+                    connection("910GPADTON", "910GEALINGB", &["10:00", "10:30"]),
+                    connection("910GEALINGB", "910GSLOUGH", &["10:12"]),
+                ],
+                vec![
+                    stop("910GPADTON", -0.17),
+                    stop("910GEALINGB", -0.30),
+                    stop("910GSLOUGH", -0.59),
+                ],
+            )
+            .unwrap();
+        graph.add_tiplocs(vec![
+            tiploc("PADTON", "910GPADTON"),
+            tiploc("EALINGB", "910GEALINGB"),
+            tiploc("SLOUGH", "910GSLOUGH"),
+        ]);
+
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/darwin/push_port");
+        let mut state = PushPortState::default();
+        assert_eq!(state.replay(&dir).await.unwrap(), 3);
+        // One train is delayed, the other is cancelled between Ealing and Slough.
+        assert_eq!(state.train_count(), 2);
+
+        let disruptions = state.disruptions(&graph);
+        let changes = disruptions.for_edge("910GPADTON", "910GEALINGB").unwrap();
+        assert_eq!(changes[&600], DepartureChange::Delayed(7));
+        let changes = disruptions.for_edge("910GEALINGB", "910GSLOUGH").unwrap();
+        assert_eq!(changes[&732], DepartureChange::Cancelled);
+        assert_eq!(disruptions.len(), 2);
+
+        // Delayed by 7 minutes: departs 10:00, arrives 10:17 rather than 10:10.
+        let paths = graph
+            .tt_from_stop_id(
+                "910GPADTON".into(),
+                NaiveDate::from_ymd_opt(2022, 5, 3)
+                    .unwrap()
+                    .and_hms_opt(10, 0, 0)
+                    .unwrap(),
+                &disruptions,
+            )
+            .unwrap();
+        let ealing = paths
+            .iter()
+            .find(|p| p.destination.id == "910GEALINGB")
+            .unwrap();
+        assert_eq!(ealing.minutes(), 17);
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use chrono::Local;
+
+use crate::{
+    config::PushPortConfig,
+    graph::{
+        disruptions::{DisruptionSource, LiveDisruptions},
+        live_graph::LiveGraph,
+    },
+};
+
+use super::{push_port::PushPortState, stomp::StompConnection};
+
+/// Keeps the disruptions applied to served queries up to date with the
+/// Darwin Push Port.
+pub struct PushPortConsumer {
+    state: PushPortState,
+    graph: Arc<LiveGraph>,
+    disruptions: Arc<LiveDisruptions>,
+    publish_interval: Duration,
+    last_published: Option<Instant>,
+}
+
+impl PushPortConsumer {
+    pub fn new(
+        graph: Arc<LiveGraph>,
+        disruptions: Arc<LiveDisruptions>,
+        config: &PushPortConfig,
+    ) -> Self {
+        Self {
+            state: PushPortState::default(),
+            graph,
+            disruptions,
+            publish_interval: Duration::from_secs(config.publish_interval_seconds),
+            last_published: None,
+        }
+    }
+
+    /// Applies previously recorded messages, e.g. to test against a known day.
+    pub async fn replay(&mut self, dir: &std::path::Path) -> Result<()> {
+        let count = self.state.replay(dir).await?;
+        println!(
+            "Replayed {} Push Port messages covering {} trains.",
+            count,
+            self.state.train_count()
+        );
+        self.publish().await;
+        Ok(())
+    }
+
+    /// Consumes the live feed forever, reconnecting whenever the connection drops.
+    pub async fn run(mut self, config: PushPortConfig) {
+        let mut failures = 0;
+        loop {
+            if let Err(e) = self.consume(&config).await {
+                failures += 1;
+                let wait = Duration::from_secs(5 * 2_u64.pow(failures.min(6)));
+                println!(
+                    "Push Port connection failed: {:#}. Reconnecting in {}s.",
+                    e,
+                    wait.as_secs()
+                );
+                tokio::time::sleep(wait).await;
+            } else {
+                failures = 0;
+            }
+        }
+    }
+
+    async fn consume(&mut self, config: &PushPortConfig) -> Result<()> {
+        let mut connection = StompConnection::connect(
+            &config.host,
+            config.port,
+            config.username.as_deref(),
+            config.password.as_deref(),
+        )
+        .await?;
+        connection.subscribe(&config.topic).await?;
+        println!("Subscribed to {} on {}.", config.topic, config.host);
+
+        loop {
+            let frame = connection.next_frame().await?;
+            match frame.command.as_str() {
+                "MESSAGE" => {
+                    if let Err(e) = self.state.apply_message(&frame.body) {
+                        println!("Skipping Push Port message: {:#}", e);
+                    }
+                }
+                "ERROR" => bail!(
+                    "Broker sent an error: {}",
+                    frame
+                        .headers
+                        .get("message")
+                        .map(String::as_str)
+                        .unwrap_or_default()
+                ),
+                _ => {}
+            }
+
+            let due = self
+                .last_published
+                .is_none_or(|at| at.elapsed() >= self.publish_interval);
+            if due {
+                // Trains from before yesterday have finished running.
+                self.state
+                    .prune(Local::now().date_naive() - chrono::Duration::days(1));
+                self.publish().await;
+            }
+        }
+    }
+
+    /// Recomputes the disruptions against the current graph and serves them.
+    async fn publish(&mut self) {
+        let current = self.graph.current().await;
+        let disruptions = self.state.disruptions(&*current.graph.read().await);
+        self.disruptions
+            .publish(DisruptionSource::PushPort, disruptions, None)
+            .await;
+        self.last_published = Some(Instant::now());
+    }
+}
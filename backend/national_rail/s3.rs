@@ -1,45 +1,72 @@
-use anyhow::{Ok, Result};
-use rusoto_core::Region;
+use std::path::Path;
+
+use anyhow::{Context, Ok, Result};
 use rusoto_s3::{GetObjectRequest, ListObjectsRequest, S3Client, S3};
 use tokio::{fs::File, io::copy};
 
+use crate::config::NationalRailConfig;
+
 pub struct NationalRailS3 {}
 
 impl NationalRailS3 {
-    pub async fn get_timetable_data() -> Result<()> {
-        let region = Region::EuWest1;
-        let client = S3Client::new(region);
-        let bucket_name = "darwin.xmltimetable".to_string();
+    /// Downloads the latest timetable and the Darwin reference data that
+    /// goes with it.
+    pub async fn get_timetable_data(config: &NationalRailConfig) -> Result<()> {
+        let client = S3Client::new(config.region()?);
+        Self::download_latest(
+            &client,
+            config,
+            &config.s3_timetable_suffix,
+            &config.download_path,
+        )
+        .await?;
+        Self::download_latest(
+            &client,
+            config,
+            &config.s3_reference_suffix,
+            &config.reference_path,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn download_latest(
+        client: &S3Client,
+        config: &NationalRailConfig,
+        suffix: &str,
+        path: &Path,
+    ) -> Result<()> {
+        let bucket_name = config.s3_bucket.clone();
         let request = ListObjectsRequest {
             bucket: bucket_name.clone(),
-            prefix: Some("PPTimetable/".to_string()),
+            prefix: Some(config.s3_prefix.clone()),
             ..Default::default()
         };
         let objects = client.list_objects(request).await?.contents.unwrap();
 
-        let timetable_key = objects
+        let key = objects
             .iter()
-            .filter(|obj| obj.key.as_ref().unwrap().ends_with("_v8.xml.gz"))
+            .filter(|obj| obj.key.as_ref().unwrap().ends_with(suffix))
             .max_by_key(|obj| obj.last_modified.as_ref().unwrap())
-            .unwrap()
+            .with_context(|| format!("No file ending in {} in the bucket", suffix))?
             .key
             .as_ref()
             .unwrap()
             .to_string();
 
-        println!("{:#?}", timetable_key);
+        println!("{:#?}", key);
 
         let request = GetObjectRequest {
             bucket: bucket_name.clone(),
-            key: timetable_key,
+            key,
             ..Default::default()
         };
 
-        let timetable_file = client.get_object(request).await?;
+        let object = client.get_object(request).await?;
 
-        let mut s3_file = timetable_file.body.unwrap().into_async_read();
+        let mut s3_file = object.body.unwrap().into_async_read();
 
-        let mut local_file = File::create("./data/timetable.xml.gz").await?;
+        let mut local_file = File::create(path).await?;
 
         copy(&mut s3_file, &mut local_file).await?;
         Ok(())
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Context, Result};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+};
+
+/// A frame received from a STOMP broker.
+#[derive(Debug)]
+pub struct Frame {
+    pub command: String,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+/// Just enough of STOMP 1.2 to subscribe to a topic, which is how Darwin
+/// and local ActiveMQ stand-ins publish Push Port messages.
+pub struct StompConnection {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl StompConnection {
+    pub async fn connect(
+        host: &str,
+        port: u16,
+        login: Option<&str>,
+        passcode: Option<&str>,
+    ) -> Result<Self> {
+        let stream = TcpStream::connect((host, port))
+            .await
+            .with_context(|| format!("Could not connect to {}:{}", host, port))?;
+        let (reader, writer) = stream.into_split();
+        let mut connection = Self {
+            reader: BufReader::new(reader),
+            writer,
+        };
+
+        let mut headers = vec![
+            ("accept-version", "1.2"),
+            ("host", host),
+            ("heart-beat", "0,0"),
+        ];
+        if let Some(login) = login {
+            headers.push(("login", login));
+        }
+        if let Some(passcode) = passcode {
+            headers.push(("passcode", passcode));
+        }
+        connection.send("CONNECT", &headers).await?;
+
+        let frame = connection.next_frame().await?;
+        match frame.command.as_str() {
+            "CONNECTED" => Ok(connection),
+            "ERROR" => bail!(
+                "Broker refused the connection: {}",
+                frame
+                    .headers
+                    .get("message")
+                    .map(String::as_str)
+                    .unwrap_or_default()
+            ),
+            other => bail!("Expected CONNECTED from the broker, got {}", other),
+        }
+    }
+
+    pub async fn subscribe(&mut self, destination: &str) -> Result<()> {
+        self.send(
+            "SUBSCRIBE",
+            &[("id", "0"), ("destination", destination), ("ack", "auto")],
+        )
+        .await
+    }
+
+    pub async fn next_frame(&mut self) -> Result<Frame> {
+        read_frame(&mut self.reader).await
+    }
+
+    async fn send(&mut self, command: &str, headers: &[(&str, &str)]) -> Result<()> {
+        let mut frame = format!("{}\n", command);
+        for (key, value) in headers {
+            frame.push_str(&format!("{}:{}\n", key, escape_header(value)));
+        }
+        frame.push_str("\n\0");
+        self.writer.write_all(frame.as_bytes()).await?;
+        Ok(())
+    }
+}
+
+pub async fn read_frame<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Frame> {
+    // Frames may be preceded by newlines, which are also heart-beats.
+    let command = loop {
+        let line = read_line(reader).await?;
+        if !line.is_empty() {
+            break line;
+        }
+    };
+
+    let mut headers = HashMap::new();
+    loop {
+        let line = read_line(reader).await?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            // The first occurrence of a repeated header wins.
+            headers
+                .entry(unescape_header(key))
+                .or_insert_with(|| unescape_header(value));
+        }
+    }
+
+    // Bodies may contain NULs (Push Port bodies are gzipped), so
+    // content-length is used when the broker sends it.
+    let body = match headers.get("content-length") {
+        Some(length) => {
+            let mut body = vec![0; length.parse().context("Invalid content-length")?];
+            reader.read_exact(&mut body).await?;
+            let mut terminator = vec![];
+            reader.read_until(0, &mut terminator).await?;
+            body
+        }
+        None => {
+            let mut body = vec![];
+            reader.read_until(0, &mut body).await?;
+            if body.pop() != Some(0) {
+                bail!("Connection closed in the middle of a frame");
+            }
+            body
+        }
+    };
+
+    Ok(Frame {
+        command,
+        headers,
+        body,
+    })
+}
+
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<String> {
+    let mut line = vec![];
+    if reader.read_until(b'\n', &mut line).await? == 0 {
+        bail!("Connection closed by the broker");
+    }
+    while matches!(line.last(), Some(b'\n') | Some(b'\r')) {
+        line.pop();
+    }
+    Ok(String::from_utf8(line)?)
+}
+
+fn escape_header(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace(':', "\\c")
+}
+
+fn unescape_header(value: &str) -> String {
+    value
+        .replace("\\n", "\n")
+        .replace("\\c", ":")
+        .replace("\\\\", "\\")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_frames() {
+        let bytes: &[u8] = b"\n\nMESSAGE\ndestination:/topic/darwin\ncontent-length:3\n\na\0b\0\nMESSAGE\nsubscription:0\n\nhello\0";
+        let mut reader = BufReader::new(bytes);
+
+        let frame = read_frame(&mut reader).await.unwrap();
+        assert_eq!(frame.command, "MESSAGE");
+        assert_eq!(frame.headers["destination"], "/topic/darwin");
+        assert_eq!(frame.body, b"a\0b");
+
+        let frame = read_frame(&mut reader).await.unwrap();
+        assert_eq!(frame.body, b"hello");
+        assert!(read_frame(&mut reader).await.is_err());
+    }
+}
@@ -1,111 +1,429 @@
-use std::vec;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
 use crate::{
-    db::mongo_repo::MongoRepository,
-    national_rail::xml_timetable::StopType,
-    tfl::model::{direct_connection::DirectConnection, stops_response::StopPoint},
+    db::{repository::Repository, storage::Storage},
+    tfl::model::{direct_connection::DirectConnection, stops_response::StopPoint, trip::Trip},
 };
 
-use super::xml_timetable::TimetableDoc;
+use super::{
+    cif::{CifTimetable, MsnStation},
+    crosswalk::{build_crosswalk, TiplocMapping, UnmatchedTiplocs},
+    xml_timetable::{Journey, JourneyReader, Leg},
+};
 use anyhow::{bail, Result};
-use chrono::NaiveTime;
-use futures::{stream, StreamExt, TryStreamExt};
-use mongodb::{bson::doc, Client};
-use quick_xml::de::from_str;
-use tokio::fs::read_to_string;
+use chrono::NaiveDate;
+use futures::TryStreamExt;
+use tokio::{sync::mpsc, task};
+
+// Journeys parsed ahead of the ones being inserted.
+const JOURNEY_BUFFER: usize = 1024;
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
 
 pub struct TimetableLoader {
-    dc_repo: MongoRepository<DirectConnection>,
-    tfl_stops_repo: MongoRepository<StopPoint>,
+    dc_repo: Box<dyn Repository<DirectConnection>>,
+    tfl_stops_repo: Box<dyn Repository<StopPoint>>,
+    tiploc_repo: Box<dyn Repository<TiplocMapping>>,
+    trip_repo: Box<dyn Repository<Trip>>,
 }
 impl TimetableLoader {
-    pub fn new(mongo_client: &Client) -> Self {
+    pub fn new(storage: &Storage) -> Self {
         Self {
-            dc_repo: MongoRepository::<DirectConnection>::new(mongo_client),
-            tfl_stops_repo: MongoRepository::<StopPoint>::new(mongo_client),
+            dc_repo: storage.repo::<DirectConnection>(),
+            tfl_stops_repo: storage.repo::<StopPoint>(),
+            tiploc_repo: storage.repo::<TiplocMapping>(),
+            trip_repo: storage.repo::<Trip>(),
         }
     }
-    pub async fn load_timetable<S: Into<String>>(&self, timetable_path: S) -> Result<()> {
-        let xml_str = read_to_string(timetable_path.into()).await?;
-        let timetable: TimetableDoc = from_str(&xml_str)?;
-        // Probably should've just used a for loop... oof
-        stream::iter(timetable.journeys)
-            .then(|j| async move {
-                let _ = stream::iter(j.stops.windows(2).map(|pair| (&pair[0], &pair[1])).filter(
-                    |(prev, current)| {
-                        let prev_ok = matches!(
-                            prev,
-                            StopType::Origin { .. } | StopType::Intermediate { .. }
-                        );
-
-                        let current_ok = matches!(
-                            current,
-                            StopType::Destination { .. } | StopType::Intermediate { .. }
-                        );
-
-                        prev_ok && current_ok
-                    },
-                ))
-                .then(|(prev, current)| self.insert_connection(prev, current))
-                .try_collect::<Vec<_>>()
-                .await;
-            })
-            .collect::<Vec<_>>()
-            .await;
+
+    /// Replaces the TIPLOC crosswalk with one built from NaPTAN's rail
+    /// references and the Darwin reference data, and reports the TIPLOCs
+    /// that don't match a stored stop point.
+    pub async fn load_crosswalk(
+        &self,
+        rail_references: &Path,
+        darwin_reference: &Path,
+        unmatched_report: &Path,
+    ) -> Result<()> {
+        let stop_ids: HashSet<String> = self
+            .tfl_stops_repo
+            .get_all()
+            .await?
+            .map_ok(|stop| stop.id)
+            .try_collect()
+            .await?;
+        let mappings = build_crosswalk(rail_references, darwin_reference, &stop_ids)?;
+
+        self.tiploc_repo.clear().await?;
+        self.tiploc_repo.insert_many(&mappings).await?;
+
+        let unmatched = UnmatchedTiplocs::from_crosswalk(&mappings);
+        unmatched.write(unmatched_report)?;
+        println!(
+            "Matched {} of {} TIPLOCs, see {} for the rest.",
+            mappings.len() - unmatched.len(),
+            mappings.len(),
+            unmatched_report.display()
+        );
         Ok(())
     }
 
-    async fn insert_connection(&self, prev: &StopType, current: &StopType) -> Result<()> {
-        let (depart_time, depart_tiploc) = match prev {
-            StopType::Origin { departure, tiploc }
-            | StopType::Intermediate {
-                departure, tiploc, ..
-            } => (Self::timestr_to_time(departure).unwrap(), tiploc),
-            // This is wrong and should panic if it happens.
-            _ => {
-                panic!("Error: Trying to get departure time from invalid enum variant.")
+    /// Loads a Darwin XML timetable, plain or gzipped. The file is parsed on
+    /// a blocking thread, a bounded number of journeys ahead of the inserts.
+    ///
+    /// Legs to or from TIPLOCs that the crosswalk doesn't match are dropped
+    /// and reported in `unmatched_report`.
+    pub async fn load_timetable(
+        &self,
+        timetable_path: PathBuf,
+        unmatched_report: &Path,
+    ) -> Result<()> {
+        let crosswalk = self.crosswalk().await?;
+
+        let (sender, mut receiver) = mpsc::channel(JOURNEY_BUFFER);
+        let reader = task::spawn_blocking(move || -> Result<()> {
+            let mut journeys = JourneyReader::open(&timetable_path)?;
+            let (mut read, mut skipped) = (0, 0);
+            let mut last_report = Instant::now();
+            while let Some(journey) = journeys.next() {
+                match journey {
+                    Ok(journey) => {
+                        if sender.blocking_send(journey).is_err() {
+                            break;
+                        }
+                        read += 1;
+                    }
+                    Err(e) if e.downcast_ref::<quick_xml::Error>().is_some() => return Err(e),
+                    Err(e) => {
+                        println!("Skipping journey: {:#}", e);
+                        skipped += 1;
+                    }
+                }
+                if last_report.elapsed() >= PROGRESS_INTERVAL {
+                    println!(
+                        "Read {:.0}% of {} ({} journeys).",
+                        journeys.progress().unwrap_or_default() * 100.0,
+                        timetable_path.display(),
+                        read
+                    );
+                    last_report = Instant::now();
+                }
             }
-        };
+            println!("Read {} journeys, skipped {}.", read, skipped);
+            Ok(())
+        });
 
-        let (arrival_time, arrival_tiploc) = match current {
-            // This is wrong and should panic if it happens.
-            StopType::Destination { arrival, tiploc }
-            | StopType::Intermediate {
-                arrival, tiploc, ..
-            } => (Self::timestr_to_time(arrival).unwrap(), tiploc),
-            _ => panic!("Error: Trying to get arrival time from invalid enum variant."),
-        };
+        // Connections are written once they have every journey's departures.
+        let mut connections = HashMap::new();
+        let mut unmatched = UnmatchedTiplocs::default();
+        while let Some(journey) = receiver.recv().await {
+            self.load_journey(&journey, &crosswalk, &mut connections, &mut unmatched)
+                .await?;
+        }
+        reader.await??;
+        for mut con in connections.into_values() {
+            con.merge_insert(self.dc_repo.as_ref()).await?;
+        }
 
-        let mut con = DirectConnection {
-            origin: self.tiploc_to_naptan(depart_tiploc).await?,
-            destination: self.tiploc_to_naptan(arrival_tiploc).await?,
-            duration_minutes: (arrival_time - depart_time).num_minutes() as f64,
-            departure_times: vec![depart_time],
-            ..Default::default()
-        };
-        con.mongo_insert(&self.dc_repo).await?;
+        unmatched.write(unmatched_report)?;
+        println!(
+            "{} TIPLOCs in the timetable are unmatched, see {}.",
+            unmatched.len(),
+            unmatched_report.display()
+        );
         Ok(())
     }
 
-    fn timestr_to_time(timestr: &str) -> Result<NaiveTime> {
-        let time = NaiveTime::parse_from_str(&timestr[..5].to_string(), "%H:%M")?;
-        Ok(time)
+    /// Loads the schedules of an ATOC CIF timetable bundle that run in the
+    /// `days` days from `from`, with the overlays, new schedules and
+    /// cancellations of its short term plans applied.
+    ///
+    /// Also stores the interchange times of the bundle's stations in the
+    /// crosswalk, which the graph uses as transfer penalties.
+    pub async fn load_cif(
+        &self,
+        cif_path: PathBuf,
+        from: NaiveDate,
+        days: u32,
+        unmatched_report: &Path,
+    ) -> Result<()> {
+        if days == 0 {
+            bail!("The CIF calendar must cover at least one day.");
+        }
+        let mut crosswalk = self.crosswalk().await?;
+        let to = from + chrono::Duration::days(days as i64 - 1);
+
+        let matched: HashSet<String> = crosswalk
+            .values()
+            .filter(|mapping| mapping.stop_id.is_some())
+            .map(|mapping| mapping.tiploc.clone())
+            .collect();
+        let cif = task::spawn_blocking(move || {
+            CifTimetable::read(&cif_path, from, to, |tiploc| matched.contains(tiploc))
+        })
+        .await??;
+
+        self.load_interchange_times(&cif.stations, &mut crosswalk)
+            .await?;
+
+        let mut connections: HashMap<(String, String), DirectConnection> = HashMap::new();
+        let mut unmatched = UnmatchedTiplocs::default();
+        let mut runs = vec![];
+        let mut journeys = 0;
+        for date in from.iter_days().take(days as usize) {
+            for schedule in cif.running_on(date) {
+                let journey = schedule.journey(date);
+                let legs = Self::matched_legs(&journey, &crosswalk, &mut unmatched);
+                runs.push(Self::trip(&journey, &journey.uid, &legs));
+                Self::add_legs(&mut connections, &journey, legs);
+                journeys += 1;
+            }
+        }
+        println!(
+            "Read {} journeys between {} and {} into {} connections.",
+            journeys,
+            from,
+            to,
+            connections.len()
+        );
+
+        for mut con in connections.into_values() {
+            con.merge_insert(self.dc_repo.as_ref()).await?;
+        }
+        for trip in Trip::merge_runs(runs) {
+            self.trip_repo.insert_or_replace(&trip).await?;
+        }
+
+        unmatched.write(unmatched_report)?;
+        println!(
+            "{} TIPLOCs in the timetable are unmatched, see {}.",
+            unmatched.len(),
+            unmatched_report.display()
+        );
+        Ok(())
     }
 
-    async fn tiploc_to_naptan(&self, tiploc: &str) -> Result<String> {
-        // Look up stop points with this tiploc.
-        let filter = doc! {"tiploc": tiploc.to_string()};
-        let matches: Vec<_> = self
-            .tfl_stops_repo
-            .collection
-            .find(filter, None)
+    // MSN stations are matched by TIPLOC, or by CRS for the other TIPLOCs of
+    // the same station.
+    async fn load_interchange_times(
+        &self,
+        stations: &[MsnStation],
+        crosswalk: &mut HashMap<String, TiplocMapping>,
+    ) -> Result<()> {
+        let by_tiploc: HashMap<&str, u16> = stations
+            .iter()
+            .map(|s| (s.tiploc.as_str(), s.interchange_minutes))
+            .collect();
+        let by_crs: HashMap<&str, u16> = stations
+            .iter()
+            .map(|s| (s.crs.as_str(), s.interchange_minutes))
+            .collect();
+        let mut updated = 0;
+        for mapping in crosswalk.values_mut() {
+            let minutes = by_tiploc
+                .get(mapping.tiploc.as_str())
+                .or_else(|| mapping.crs.as_deref().and_then(|crs| by_crs.get(crs)));
+            if minutes.copied() != mapping.interchange_minutes {
+                mapping.interchange_minutes = minutes.copied();
+                self.tiploc_repo.insert_or_replace(mapping).await?;
+                updated += 1;
+            }
+        }
+        println!("Updated the interchange times of {} TIPLOCs.", updated);
+        Ok(())
+    }
+
+    async fn crosswalk(&self) -> Result<HashMap<String, TiplocMapping>> {
+        let crosswalk: HashMap<String, TiplocMapping> = self
+            .tiploc_repo
+            .get_all()
             .await?
+            .map_ok(|mapping| (mapping.tiploc.clone(), mapping))
             .try_collect()
             .await?;
+        if crosswalk.is_empty() {
+            bail!("There's no TIPLOC crosswalk. Run `ingest national-rail --crosswalk` first.");
+        }
+        Ok(crosswalk)
+    }
+
+    async fn load_journey(
+        &self,
+        journey: &Journey,
+        crosswalk: &HashMap<String, TiplocMapping>,
+        connections: &mut HashMap<(String, String), DirectConnection>,
+        unmatched: &mut UnmatchedTiplocs,
+    ) -> Result<()> {
+        let legs = Self::matched_legs(journey, crosswalk, unmatched);
+        self.trip_repo
+            .insert_or_replace(&Self::trip(journey, &journey.rid, &legs))
+            .await?;
+        Self::add_legs(connections, journey, legs);
+        Ok(())
+    }
+
+    // Adds the departures of `legs` to the connections they run along.
+    fn add_legs(
+        connections: &mut HashMap<(String, String), DirectConnection>,
+        journey: &Journey,
+        legs: Vec<(Leg, String, String)>,
+    ) {
+        for (leg, origin, destination) in legs {
+            let con = connections
+                .entry((origin.clone(), destination.clone()))
+                .or_insert_with(|| DirectConnection {
+                    origin,
+                    destination,
+                    duration_minutes: (leg.arrival - leg.departure).num_minutes() as f64,
+                    ..Default::default()
+                });
+            con.departure_times.push(leg.departure.time());
+            con.dated_departures.push(leg.departure);
+            con.service_ids.push(journey.uid.clone());
+        }
+    }
+
+    // The run of `journey` on its start date, between matched stop points.
+    fn trip(journey: &Journey, id: &str, legs: &[(Leg, String, String)]) -> Trip {
+        let mut trip = Trip::from_legs(
+            id.to_string(),
+            None,
+            journey.ssd,
+            legs.iter().map(|(leg, origin, destination)| {
+                (
+                    origin.as_str(),
+                    destination.as_str(),
+                    leg.departure,
+                    leg.arrival,
+                )
+            }),
+        );
+        trip.dates = vec![journey.ssd];
+        trip
+    }
 
-        match matches.iter().max_by_key(|s| s.lines.len()) {
-            Some(stop) => Ok(stop.id.clone()),
-            None => bail!("No match found for tiploc {}", tiploc),
+    /// The legs of `journey` with the stop points they go between. Legs to
+    /// or from unmatched TIPLOCs are recorded in `unmatched` instead.
+    fn matched_legs<'a>(
+        journey: &'a Journey,
+        crosswalk: &HashMap<String, TiplocMapping>,
+        unmatched: &mut UnmatchedTiplocs,
+    ) -> Vec<(Leg<'a>, String, String)> {
+        let stop_id = |tiploc: &str| {
+            let mapping = crosswalk.get(tiploc);
+            mapping.and_then(|m| m.stop_id.clone()).ok_or(mapping)
+        };
+        let mut legs = vec![];
+        for leg in journey.legs() {
+            match (
+                stop_id(&leg.origin.tiploc),
+                stop_id(&leg.destination.tiploc),
+            ) {
+                // Between TIPLOCs of the same station.
+                (Ok(origin), Ok(destination)) if origin == destination => {}
+                (Ok(origin), Ok(destination)) => legs.push((leg, origin, destination)),
+                (origin, destination) => {
+                    if let Err(mapping) = origin {
+                        unmatched.add_leg(&leg.origin.tiploc, mapping);
+                    }
+                    if let Err(mapping) = destination {
+                        unmatched.add_leg(&leg.destination.tiploc, mapping);
+                    }
+                }
+            }
+        }
+        legs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveTime;
+
+    use super::*;
+    use crate::national_rail::cif::tests::fixture_zip;
+    use crate::{graph::connection::Connection, util::time::Seconds};
+
+    fn mapping(tiploc: &str, crs: &str, stop_id: Option<&str>) -> TiplocMapping {
+        TiplocMapping {
+            tiploc: tiploc.into(),
+            crs: Some(crs.into()),
+            name: None,
+            atco_code: None,
+            stop_id: stop_id.map(String::from),
+            interchange_minutes: None,
         }
     }
+
+    #[tokio::test]
+    async fn test_load_cif() {
+        let root = tempfile::tempdir().unwrap();
+        let storage = Storage::Files(root.path().to_path_buf());
+        let loader = TimetableLoader::new(&storage);
+        let crosswalk = vec![
+            mapping("PADTON", "PAD", Some("910GPADTON")),
+            // Matched to Paddington's interchange time by CRS.
+            mapping("PADTLL", "PAD", Some("910GPADTON")),
+            mapping("EALINGB", "EAL", Some("910GEALINGB")),
+            mapping("SLOUGH", "SLO", None),
+        ];
+        loader.tiploc_repo.insert_many(&crosswalk).await.unwrap();
+
+        let zip = fixture_zip();
+        let report = root.path().join("unmatched.csv");
+        let from = NaiveDate::from_ymd_opt(2022, 5, 2).unwrap();
+        loader
+            .load_cif(zip.path().to_path_buf(), from, 7, &report)
+            .await
+            .unwrap();
+
+        let con = loader
+            .dc_repo
+            .get_by_id("910GPADTON-910GEALINGB")
+            .await
+            .unwrap()
+            .unwrap();
+        let at = |day, hour, min| {
+            NaiveDate::from_ymd_opt(2022, 5, day)
+                .unwrap()
+                .and_hms_opt(hour, min, 0)
+                .unwrap()
+        };
+        // The overlay runs later on Wednesday, and Thursday is cancelled.
+        assert_eq!(
+            con.dated_departures,
+            vec![at(2, 10, 0), at(3, 10, 0), at(4, 10, 15), at(6, 10, 0)]
+        );
+        assert_eq!(
+            con.departure_times,
+            vec![
+                NaiveTime::from_hms_opt(10, 0, 0).unwrap(),
+                NaiveTime::from_hms_opt(10, 15, 0).unwrap()
+            ]
+        );
+        assert_eq!(con.service_ids, vec!["C10001".to_string()]);
+        // Routing only takes the departures that run on the day.
+        let con = Connection::from_direct_connection(&con);
+        let departure = |day| {
+            con.departure_from(
+                Seconds::from_minutes(9 * 60 + 50),
+                at(day, 0, 0).date(),
+                None,
+            )
+            .map(Seconds::minutes)
+        };
+        assert_eq!(departure(2), Some(10 * 60));
+        assert_eq!(departure(4), Some(10 * 60 + 15));
+        assert_eq!(departure(5), None);
+
+        let crosswalk = loader.crosswalk().await.unwrap();
+        let interchange = |tiploc: &str| crosswalk[tiploc].interchange_minutes;
+        assert_eq!(interchange("PADTON"), Some(15));
+        assert_eq!(interchange("PADTLL"), Some(15));
+        assert_eq!(interchange("EALINGB"), Some(5));
+        assert!(std::fs::read_to_string(&report).unwrap().contains("SLOUGH"));
+    }
 }
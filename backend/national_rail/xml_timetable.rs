@@ -1,53 +1,503 @@
-use serde::{Deserialize, Serialize};
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::Path,
+    rc::Rc,
+};
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct TimetableDoc {
-    #[serde(rename = "Journey")]
-    pub journeys: Vec<Journey>,
-}
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use flate2::bufread::MultiGzDecoder;
+use quick_xml::{events::Event, Reader};
+
+use super::push_port::attributes;
 
-#[derive(Serialize, Deserialize, Debug)]
+/// A train (or replacement bus) in the Darwin timetable.
+///
+/// Darwin timetables list each day's running separately, so a journey runs
+/// only on its schedule start date. Times after midnight are on the next day.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Journey {
-    #[serde(rename = "$value")]
-    pub stops: Vec<StopType>,
+    pub rid: String,
+    pub uid: String,
+    pub train_id: String,
+    /// Schedule start date.
+    pub ssd: NaiveDate,
+    pub toc: String,
+    pub is_passenger_service: bool,
+    pub deleted: bool,
+    pub cancel_reason: Option<String>,
+    pub stops: Vec<Stop>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum StopType {
-    #[serde(rename = "OR")]
-    Origin {
-        #[serde(rename = "tpl")]
-        tiploc: String,
-        #[serde(rename = "wtd")]
-        departure: String,
-    },
-    #[serde(rename = "IP")]
-    Intermediate {
-        #[serde(rename = "tpl")]
-        tiploc: String,
-        #[serde(rename = "wta")]
-        arrival: String,
-        #[serde(rename = "wtd")]
-        departure: String,
-    },
-    #[serde(rename = "DT")]
-    Destination {
-        #[serde(rename = "tpl")]
-        tiploc: String,
-        #[serde(rename = "wta")]
-        arrival: String,
-    },
-    // Don't need these.
-    #[serde(rename = "PP")]
-    PassThrough,
-    #[serde(rename = "OPOR")]
+    Origin,
     OptionalOrigin,
-    #[serde(rename = "OPIP")]
+    Intermediate,
     OptionalIntermediate,
-    #[serde(rename = "OPPP")]
+    PassThrough,
     OptionalPassThrough,
-    #[serde(rename = "OPDT")]
+    Destination,
     OptionalDestination,
-    #[serde(rename = "cancelReason")]
-    CancelReason,
+}
+
+impl StopType {
+    fn from_element(name: &[u8]) -> Option<Self> {
+        match name {
+            b"OR" => Some(StopType::Origin),
+            b"OPOR" => Some(StopType::OptionalOrigin),
+            b"IP" => Some(StopType::Intermediate),
+            b"OPIP" => Some(StopType::OptionalIntermediate),
+            b"PP" => Some(StopType::PassThrough),
+            b"OPPP" => Some(StopType::OptionalPassThrough),
+            b"DT" => Some(StopType::Destination),
+            b"OPDT" => Some(StopType::OptionalDestination),
+            _ => None,
+        }
+    }
+
+    // What a public stop does when the timetable doesn't say.
+    fn default_activities(self) -> &'static str {
+        match self {
+            StopType::Origin => "TB",
+            StopType::Intermediate => "T ",
+            StopType::Destination => "TF",
+            _ => "",
+        }
+    }
+}
+
+/// A location on a journey's route, including those it only passes or
+/// calls at for operational reasons.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stop {
+    pub stop_type: StopType,
+    pub tiploc: String,
+    /// Darwin activity codes, two characters each.
+    pub activities: String,
+    pub public_arrival: Option<NaiveTime>,
+    pub public_departure: Option<NaiveTime>,
+    pub working_arrival: Option<NaiveTime>,
+    pub working_departure: Option<NaiveTime>,
+    pub working_pass: Option<NaiveTime>,
+    pub cancelled: bool,
+}
+
+impl Stop {
+    fn parse(stop_type: StopType, attrs: &HashMap<String, String>) -> Result<Self> {
+        let time = |key: &str| attrs.get(key).and_then(|t| parse_time(t));
+        Ok(Stop {
+            stop_type,
+            tiploc: attrs
+                .get("tpl")
+                .cloned()
+                .ok_or_else(|| anyhow!("Missing tpl on {:?}", stop_type))?,
+            activities: attrs
+                .get("act")
+                .cloned()
+                .unwrap_or_else(|| stop_type.default_activities().to_string()),
+            public_arrival: time("pta"),
+            public_departure: time("ptd"),
+            working_arrival: time("wta"),
+            working_departure: time("wtd"),
+            working_pass: time("wtp"),
+            cancelled: attrs.get("can").map(String::as_str) == Some("true"),
+        })
+    }
+
+    pub fn picks_up(&self) -> bool {
+        !self.cancelled && picks_up(&self.activities)
+    }
+
+    pub fn sets_down(&self) -> bool {
+        !self.cancelled && sets_down(&self.activities)
+    }
+
+    /// The public departure time, or the working one for stops that don't
+    /// have one.
+    pub fn departure(&self) -> Option<NaiveTime> {
+        self.public_departure.or(self.working_departure)
+    }
+
+    pub fn arrival(&self) -> Option<NaiveTime> {
+        self.public_arrival.or(self.working_arrival)
+    }
+}
+
+/// Part of a journey that a passenger can ride without changing.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Leg<'a> {
+    pub origin: &'a Stop,
+    pub destination: &'a Stop,
+    pub departure: NaiveDateTime,
+    pub arrival: NaiveDateTime,
+}
+
+impl Journey {
+    /// The legs between consecutive passenger stops, with the dates they
+    /// run on. Empty for deleted and non-passenger services.
+    pub fn legs(&self) -> Vec<Leg<'_>> {
+        if self.deleted || !self.is_passenger_service {
+            return vec![];
+        }
+        let times = self.times();
+        passenger_legs(&self.stops, Stop::picks_up, Stop::sets_down)
+            .into_iter()
+            .filter_map(|(i, j)| {
+                Some(Leg {
+                    origin: &self.stops[i],
+                    destination: &self.stops[j],
+                    departure: times[i].1?,
+                    arrival: times[j].0?,
+                })
+            })
+            .collect()
+    }
+
+    // The arrival and departure of every stop as date times. Times are only
+    // ever a little out of order (public times can be rounded down), so one
+    // far earlier than the last is after midnight.
+    fn times(&self) -> Vec<(Option<NaiveDateTime>, Option<NaiveDateTime>)> {
+        let mut clock = self.ssd.and_time(NaiveTime::MIN);
+        let mut at = |time: Option<NaiveTime>| {
+            time.map(|time| {
+                let mut date_time = clock.date().and_time(time);
+                if date_time < clock - Duration::hours(6) {
+                    date_time += Duration::days(1);
+                }
+                clock = clock.max(date_time);
+                date_time
+            })
+        };
+        self.stops
+            .iter()
+            .map(|stop| {
+                at(stop.working_arrival);
+                let arrival = at(stop.arrival());
+                at(stop.working_pass);
+                let departure = at(stop.departure());
+                at(stop.working_departure);
+                (arrival, departure)
+            })
+            .collect()
+    }
+}
+
+/// Whether Darwin activity codes let passengers board.
+pub fn picks_up(activities: &str) -> bool {
+    activity_codes(activities).any(|code| matches!(code, "T" | "TB" | "U" | "R"))
+}
+
+/// Whether Darwin activity codes let passengers alight.
+pub fn sets_down(activities: &str) -> bool {
+    activity_codes(activities).any(|code| matches!(code, "T" | "TF" | "D" | "R"))
+}
+
+fn activity_codes(activities: &str) -> impl Iterator<Item = &str> {
+    activities
+        .as_bytes()
+        .chunks(2)
+        .filter_map(|code| std::str::from_utf8(code).ok())
+        .map(str::trim_end)
+}
+
+/// Pairs of indices into `stops`, from every stop where passengers can
+/// board to the next one where they can alight. A passenger can't get off
+/// at a pick up only stop to change trains, so it has no leg towards it.
+/// Nor can they board at a set down only stop, so legs also go past it to
+/// the next stop they can alight at, until one where they could board.
+pub fn passenger_legs<T>(
+    stops: &[T],
+    picks_up: impl Fn(&T) -> bool,
+    sets_down: impl Fn(&T) -> bool,
+) -> Vec<(usize, usize)> {
+    let mut legs = vec![];
+    for i in (0..stops.len()).filter(|&i| picks_up(&stops[i])) {
+        for j in (i + 1..stops.len()).filter(|&j| sets_down(&stops[j])) {
+            legs.push((i, j));
+            if picks_up(&stops[j]) {
+                break;
+            }
+        }
+    }
+    legs
+}
+
+fn parse_time(time: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+        .ok()
+}
+
+/// Reads the journeys of a Darwin XML timetable one at a time, so that the
+/// whole (multi-gigabyte) document never has to be held in memory.
+///
+/// A journey with missing attributes is returned as an error and reading
+/// carries on after it. Malformed XML ends the journeys.
+pub struct JourneyReader<R: BufRead> {
+    reader: Reader<R>,
+    buf: Vec<u8>,
+    progress: Option<ReadProgress>,
+    done: bool,
+}
+
+pub(super) struct ReadProgress {
+    bytes_read: Rc<Cell<u64>>,
+    total_bytes: u64,
+}
+
+/// Opens an XML file, which may be gzipped as downloaded from S3.
+pub(super) fn open_xml(path: &Path) -> Result<(Box<dyn BufRead>, ReadProgress)> {
+    let file = File::open(path)?;
+    let total_bytes = file.metadata()?.len();
+    let bytes_read = Rc::new(Cell::new(0));
+    let mut buffered = BufReader::new(CountingReader {
+        inner: file,
+        bytes_read: bytes_read.clone(),
+    });
+
+    let is_gzip = buffered.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+    let input: Box<dyn BufRead> = if is_gzip {
+        Box::new(BufReader::new(MultiGzDecoder::new(buffered)))
+    } else {
+        Box::new(buffered)
+    };
+    Ok((
+        input,
+        ReadProgress {
+            bytes_read,
+            total_bytes,
+        },
+    ))
+}
+
+impl JourneyReader<Box<dyn BufRead>> {
+    pub fn open(path: &Path) -> Result<Self> {
+        let (input, progress) = open_xml(path)
+            .with_context(|| format!("Couldn't open timetable {}", path.display()))?;
+        let mut reader = Self::from_reader(input);
+        reader.progress = Some(progress);
+        Ok(reader)
+    }
+}
+
+impl<R: BufRead> JourneyReader<R> {
+    pub fn from_reader(input: R) -> Self {
+        let mut reader = Reader::from_reader(input);
+        reader.trim_text(true);
+        Self {
+            reader,
+            buf: Vec::new(),
+            progress: None,
+            done: false,
+        }
+    }
+
+    /// The fraction of the file read so far, if reading from a file.
+    pub fn progress(&self) -> Option<f64> {
+        self.progress
+            .as_ref()
+            .map(|progress| progress.bytes_read.get() as f64 / progress.total_bytes.max(1) as f64)
+    }
+
+    fn next_journey(&mut self) -> Result<Option<Journey>> {
+        let mut journey: Option<Journey> = None;
+        let mut error = None;
+        let mut in_cancel_reason = false;
+        loop {
+            self.buf.clear();
+            match self.reader.read_event(&mut self.buf)? {
+                Event::Start(element) | Event::Empty(element)
+                    if element.local_name() == b"Journey" =>
+                {
+                    let attrs = attributes(&element)?;
+                    match Self::journey(&attrs) {
+                        Ok(started) => journey = Some(started),
+                        Err(e) => error = Some(e),
+                    }
+                }
+                Event::Start(element) if element.local_name() == b"cancelReason" => {
+                    in_cancel_reason = true;
+                }
+                Event::Text(text) if in_cancel_reason => {
+                    if let Some(journey) = journey.as_mut() {
+                        journey.cancel_reason = Some(text.unescape_and_decode(&self.reader)?);
+                    }
+                }
+                Event::End(element) if element.local_name() == b"cancelReason" => {
+                    in_cancel_reason = false;
+                }
+                Event::Start(element) | Event::Empty(element) => {
+                    let stop_type = StopType::from_element(element.local_name());
+                    if let (Some(journey), Some(stop_type)) = (journey.as_mut(), stop_type) {
+                        match Stop::parse(stop_type, &attributes(&element)?) {
+                            Ok(stop) => journey.stops.push(stop),
+                            Err(e) => error = error.or(Some(e)),
+                        }
+                    }
+                }
+                Event::End(element) if element.local_name() == b"Journey" => {
+                    if let Some(e) = error {
+                        return Err(e);
+                    }
+                    return Ok(journey.take());
+                }
+                Event::Eof => {
+                    if journey.is_some() {
+                        bail!("Timetable ended in the middle of a journey");
+                    }
+                    return Ok(None);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn journey(attrs: &HashMap<String, String>) -> Result<Journey> {
+        let attr = |key: &str| attrs.get(key).cloned().unwrap_or_default();
+        let rid = attr("rid");
+        let ssd = NaiveDate::parse_from_str(&attr("ssd"), "%Y-%m-%d")
+            .with_context(|| format!("Journey {} has no valid ssd", rid))?;
+        Ok(Journey {
+            uid: attr("uid"),
+            train_id: attr("trainId"),
+            ssd,
+            toc: attr("toc"),
+            is_passenger_service: attr("isPassengerSvc") != "false",
+            deleted: attr("deleted") == "true",
+            cancel_reason: None,
+            stops: vec![],
+            rid,
+        })
+    }
+}
+
+impl<R: BufRead> Iterator for JourneyReader<R> {
+    type Item = Result<Journey>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.next_journey() {
+            Ok(Some(journey)) => Some(Ok(journey)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                // There's no recovering the position in malformed XML.
+                if e.downcast_ref::<quick_xml::Error>().is_some() {
+                    self.done = true;
+                }
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+// Counts the bytes read from the file, before decompression.
+struct CountingReader<R> {
+    inner: R,
+    bytes_read: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.bytes_read.set(self.bytes_read.get() + read as u64);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+
+    use super::*;
+
+    #[test]
+    fn test_read_gzipped_journeys() {
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<PportTimetable xmlns="http://www.thalesgroup.com/rtti/XmlTimetable/v8">
+  <Journey rid="202205037654321" uid="C12345" trainId="1A23" ssd="2022-05-03" toc="GW">
+    <OR tpl="PADTON" act="TB" ptd="23:45" wtd="23:44:30" />
+    <PP tpl="ROYAOJN" wtp="23:48" />
+    <IP tpl="EALINGB" act="U " pta="23:52" ptd="23:53" wta="23:52" wtd="23:53" />
+    <OPIP tpl="STHALL" act="OP" wta="23:58" wtd="00:01" />
+    <IP tpl="SLOUGH" act="T " pta="00:10" ptd="00:11" wta="00:10" wtd="00:11" />
+    <IP tpl="MDNHEAD" act="D " pta="00:20" wta="00:20" wtd="00:21" />
+    <IP tpl="TWYFORD" act="T " pta="00:28" ptd="00:29" wta="00:28" wtd="00:29" can="true" />
+    <DT tpl="RDNGSTN" act="TF" pta="00:40" wta="00:39:30" />
+  </Journey>
+  <Association tiploc="RDNGSTN" category="NP" main="202205037654321" assoc="202205037654322" />
+  <Journey rid="202205037654322" uid="C12346" trainId="5A24" ssd="2022-05-03" toc="GW" isPassengerSvc="false">
+    <OR tpl="RDNGSTN" act="TB" wtd="11:00" />
+    <DT tpl="PADTON" act="TF" wta="11:30" />
+  </Journey>
+  <Journey rid="202205037654323" uid="C12347" trainId="1A25" toc="GW">
+    <OR tpl="RDNGSTN" act="TB" ptd="12:00" wtd="12:00" />
+    <DT tpl="PADTON" act="TF" pta="12:30" wta="12:30" />
+  </Journey>
+  <Journey rid="202205037654324" uid="C12348" trainId="1A26" ssd="2022-05-03" toc="GW">
+    <OR tpl="RDNGSTN" ptd="13:00" wtd="13:00" can="true" />
+    <DT tpl="PADTON" pta="13:30" wta="13:30" can="true" />
+    <cancelReason>104</cancelReason>
+  </Journey>
+</PportTimetable>"#;
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(xml.as_bytes()).unwrap();
+        file.write_all(&encoder.finish().unwrap()).unwrap();
+
+        let mut journeys = JourneyReader::open(file.path()).unwrap();
+        let first = journeys.next().unwrap().unwrap();
+        assert_eq!(first.stops.len(), 8);
+        assert_eq!(first.stops[3].stop_type, StopType::OptionalIntermediate);
+        let at = |day: u32, hour: u32, minute: u32| {
+            NaiveDate::from_ymd_opt(2022, 5, day)
+                .unwrap()
+                .and_hms_opt(hour, minute, 0)
+                .unwrap()
+        };
+        // Public times, past the pick up only stop and into the next day.
+        // Reading is reached past the set down only stop at Maidenhead, as
+        // Twyford is cancelled.
+        let legs = first
+            .legs()
+            .iter()
+            .map(|leg| {
+                (
+                    leg.origin.tiploc.as_str(),
+                    leg.destination.tiploc.as_str(),
+                    leg.departure,
+                    leg.arrival,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            legs,
+            vec![
+                ("PADTON", "SLOUGH", at(3, 23, 45), at(4, 0, 10)),
+                ("EALINGB", "SLOUGH", at(3, 23, 53), at(4, 0, 10)),
+                ("SLOUGH", "MDNHEAD", at(4, 0, 11), at(4, 0, 20)),
+                ("SLOUGH", "RDNGSTN", at(4, 0, 11), at(4, 0, 40)),
+            ]
+        );
+
+        let empty_stock = journeys.next().unwrap().unwrap();
+        assert!(empty_stock.legs().is_empty());
+        // No start date.
+        assert!(journeys.next().unwrap().is_err());
+        let cancelled = journeys.next().unwrap().unwrap();
+        assert_eq!(cancelled.cancel_reason.as_deref(), Some("104"));
+        assert!(cancelled.legs().is_empty());
+        assert!(journeys.next().is_none());
+        assert_eq!(journeys.progress(), Some(1.0));
+    }
 }
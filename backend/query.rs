@@ -0,0 +1,97 @@
+use std::io;
+
+use anyhow::{anyhow, Result};
+use chrono::{Local, TimeZone};
+
+use crate::{
+    config::Config,
+    graph::{disruptions::Disruptions, graph_source::GraphSource, location::Location},
+    national_rail::push_port::PushPortState,
+    tfl::{client::TFLClient, fixture_client::ReplayClient, live_status::LiveStatusPoller},
+    util::time::parse_start,
+    OutputFormat, QueryArgs,
+};
+
+pub async fn run(options: QueryArgs, config: &Config) -> Result<()> {
+    let start = parse_start(&options.at, options.on.as_deref())?;
+
+    let locs = options
+        .from
+        .iter()
+        .map(|loc_str| {
+            Location::try_parse_loc(loc_str)
+                .ok_or_else(|| anyhow!("Location string could not be parsed: {}", loc_str))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let source = GraphSource::new(options.graph, options.local, config).await?;
+    let mut graph = source.build_graph(&config.routing).await?;
+
+    let mut disruptions = Disruptions::default();
+    if let Some(dir) = &options.push_port_replay {
+        // Departures are only known by the minute of the day they leave at.
+        if start.date() != Local::now().date_naive() {
+            return Err(anyhow!(
+                "Push Port delays are for today, not {}",
+                start.date()
+            ));
+        }
+        let mut state = PushPortState::default();
+        state.replay(dir).await?;
+        disruptions = state.disruptions(&graph);
+        eprintln!(
+            "Applying {} delayed or cancelled departures.",
+            disruptions.len()
+        );
+    }
+
+    if options.tfl_live || options.tfl_live_replay.is_some() {
+        // Recorded line status applies from the requested time.
+        let at = Local
+            .from_local_datetime(&start)
+            .earliest()
+            .ok_or_else(|| anyhow!("{} doesn't exist locally", start))?;
+        let live = match &options.tfl_live_replay {
+            Some(dir) => {
+                LiveStatusPoller::new(ReplayClient::new(dir), config.tfl_live.clone())
+                    .poll(at)
+                    .await?
+            }
+            // The current status only applies from now, whenever the query is.
+            None => {
+                LiveStatusPoller::new(TFLClient::new(&config.tfl)?, config.tfl_live.clone())
+                    .poll(Local::now())
+                    .await?
+            }
+        };
+        eprintln!("Applying {} delayed or closed lines.", live.line_count());
+        disruptions.extend(&live);
+    }
+
+    let mut paths = graph.travel_times_from_locs(locs, start, &disruptions);
+    paths.sort_by_key(|p| p.duration);
+
+    match options.format {
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(io::stdout(), &paths)?;
+            println!();
+        }
+        OutputFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(io::stdout());
+            writer.write_record(["stop_id", "name", "lat", "lon", "minutes"])?;
+            for path in paths {
+                let station = path.destination;
+                writer.write_record([
+                    station.id,
+                    station.name,
+                    station.location.x().to_string(),
+                    station.location.y().to_string(),
+                    path.duration.minutes().to_string(),
+                ])?;
+            }
+            writer.flush()?;
+        }
+    }
+
+    Ok(())
+}
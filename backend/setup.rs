@@ -1,102 +1,325 @@
-use std::{env, time::Instant};
+use std::time::Instant;
 
+use crate::config::Config;
 use crate::{
-    db::{atlas_loader::copy_collections, data_fixer::DataFixer, tfl_loader::Loader},
-    graph::mongo_graph_builder::MongoGraphBuilder,
+    db::{
+        atlas_loader::copy_collections, data_fixer::DataFixer, ingestion_job::TimetableJob,
+        storage::Storage, tfl_loader::Loader,
+    },
+    graph::{
+        diff::diff as diff_graphs, graph_builder::GraphBuilder, graph_source::GraphSource,
+        validation,
+    },
+    naptan::loader::NaptanLoader,
     national_rail::{s3::NationalRailS3, timetable_loader::TimetableLoader},
-    tfl::client::TFLClient,
-    SetupArgs,
+    tfl::{
+        client::{Client, TFLClient},
+        fixture_client::{RecordingClient, ReplayClient},
+        model::{line_response::RouteEndpoints, stops_response::StopPoint},
+    },
+    transxchange::loader::TxcLoader,
+    util::time::parse_start,
+    BuildGraphArgs, DiffArgs, FixArgs, IngestNaptanArgs, IngestNationalRailArgs, IngestTflArgs,
+    IngestTxcArgs, ValidateArgs,
 };
-use anyhow::Result;
-use chrono::NaiveTime;
-use mongodb::options::ClientOptions;
+use anyhow::{bail, Result};
+use chrono::Local;
+use futures::TryStreamExt;
 
-pub async fn load(options: SetupArgs) -> Result<()> {
-    let tfl_uri = env::var("TFL_CLIENT_URI")?;
-    let mut tfl_client = TFLClient::new(&tfl_uri).unwrap();
-    // Parse a connection string into an options struct.
-    let mut client_options = ClientOptions::parse("mongodb://localhost:27017").await?;
-    client_options.app_name = Some("TravelTime".to_string());
+async fn local_storage(config: &Config) -> Result<Storage> {
+    let storage = Storage::local(config).await?;
+    println!("Using {}.", storage);
+    Ok(storage)
+}
+
+pub async fn ingest_tfl(options: IngestTflArgs, config: &Config) -> Result<()> {
+    if !(options.stops
+        || options.routes
+        || options.segments
+        || options.timetables
+        || options.closures)
+    {
+        bail!(
+            "Nothing to ingest. Pass at least one of --stops, --routes, --segments, --timetables, --closures."
+        );
+    }
 
-    // Get a handle to the deployment.
-    let mongo_client = mongodb::Client::with_options(client_options)?;
-    println!("Client OK.");
+    let storage = local_storage(config).await?;
 
-    let mut loader = Loader::new(&mut tfl_client, &mongo_client);
+    if (options.segments || options.timetables)
+        && !options.routes
+        && storage.repo::<RouteEndpoints>().count().await? == 0
+    {
+        bail!("Segments and timetables require routes. Pass --routes or load them first.");
+    }
 
-    if options.stoppoint_load {
+    if let Some(dir) = &options.replay_fixtures {
+        println!("Replaying TfL responses from {}.", dir.display());
+        let mut client = ReplayClient::new(dir);
+        return load_tfl(&options, config, &mut client, &storage).await;
+    }
+
+    let mut tfl_client = TFLClient::new(&config.tfl)?;
+    match &options.record_fixtures {
+        Some(dir) => {
+            println!("Recording TfL responses to {}.", dir.display());
+            let mut client = RecordingClient::new(tfl_client, dir);
+            load_tfl(&options, config, &mut client, &storage).await
+        }
+        None => load_tfl(&options, config, &mut tfl_client, &storage).await,
+    }
+}
+
+async fn load_tfl<C: Client>(
+    options: &IngestTflArgs,
+    config: &Config,
+    client: &mut C,
+    storage: &Storage,
+) -> Result<()> {
+    let mut loader = Loader::new(client, storage);
+
+    if options.stops {
         println!("Loading stops.");
         loader.load_stops().await?;
         println!("Loaded stops.");
     }
 
-    if options.routes_load {
+    if options.routes {
         println!("Loading routes.");
         loader.load_routes().await?;
         println!("Loaded routes.");
     }
 
-    if options.segment_load {
+    if options.segments {
         println!("Loading segments.");
         loader.load_segments().await?;
         println!("Loaded segments.");
     }
 
-    if options.timetable_load {
+    if options.timetables {
+        if options.reload_timetables {
+            storage.repo::<TimetableJob>().clear().await?;
+        }
         println!("Loading timetables.");
-        loader.load_timetables().await?;
+        let skip_modes: &[&str] = if options.skip_bus_timetables {
+            &["bus"]
+        } else {
+            &[]
+        };
+        loader.load_timetables(skip_modes).await?;
         println!("Loaded timetables.");
     }
 
-    if options.fix_timetables {
-        println!("Fixing timetables.");
-        DataFixer::fix_direct_connection_repo(&mongo_client).await?;
-        println!("Done fixing timetables.");
+    if options.closures {
+        println!("Loading planned closures.");
+        let today = Local::now().date_naive();
+        loader
+            .load_closures(
+                config.tfl.closure_modes.clone(),
+                today,
+                today + chrono::Duration::days(config.tfl.closure_days.into()),
+            )
+            .await?;
+        println!("Loaded planned closures.");
     }
 
-    if options.fix_stoppoints {
-        println!("Fixing stop points.");
-        DataFixer::fix_stop_point_repo(&mongo_client).await?;
-        println!("Done fixing stop points.");
+    Ok(())
+}
+
+pub async fn ingest_national_rail(options: IngestNationalRailArgs, config: &Config) -> Result<()> {
+    if !(options.download || options.crosswalk || options.load || options.cif) {
+        bail!("Nothing to ingest. Pass at least one of --download, --crosswalk, --load, --cif.");
     }
 
-    if options.load_national_rail_data {
+    if options.download {
         println!("Loading national rail timetables from S3.");
-        NationalRailS3::get_timetable_data().await.unwrap();
+        NationalRailS3::get_timetable_data(&config.national_rail).await?;
         println!("Done loading national rail timetables from S3.");
     }
 
-    if options.load_national_rail {
+    let storage = local_storage(config).await?;
+    let timetable = TimetableLoader::new(&storage);
+    let national_rail = &config.national_rail;
+
+    if options.crosswalk {
+        // National rail stations are matched to TFL stop points.
+        if storage.repo::<StopPoint>().count().await? == 0 {
+            bail!("The TIPLOC crosswalk requires stop points. Run `ingest tfl --stops` first.");
+        }
+        println!("Loading the TIPLOC crosswalk.");
+        timetable
+            .load_crosswalk(
+                &national_rail.rail_references_path,
+                &national_rail.reference_path,
+                &national_rail.unmatched_report_path,
+            )
+            .await?;
+        println!("Loaded the TIPLOC crosswalk.");
+    }
+
+    if options.load {
         println!("Loading timetables.");
-        let timetable = TimetableLoader::new(&mongo_client);
-        timetable.load_timetable("./data/timetable.xml").await?;
+        let path = options
+            .timetable
+            .clone()
+            .unwrap_or_else(|| national_rail.timetable_path.clone());
+        timetable
+            .load_timetable(path, &national_rail.unmatched_report_path)
+            .await?;
         println!("Loaded timetables.");
     }
 
-    if options.copy_to_atlas {
-        let atlas_uri = env::var("MONGO_URI")?;
-        println!("{}", atlas_uri);
-        let mut atlas_opts = ClientOptions::parse(atlas_uri).await?;
-        atlas_opts.app_name = Some("travel-time".to_string());
-        let atlas_client = mongodb::Client::with_options(atlas_opts)?;
-        println!("Copying collections");
-        copy_collections(&mongo_client, &atlas_client).await?;
-        println!("Pasting collections");
+    if options.cif {
+        println!("Loading the CIF timetable.");
+        let path = options
+            .timetable
+            .unwrap_or_else(|| national_rail.cif_path.clone());
+        timetable
+            .load_cif(
+                path,
+                Local::now().date_naive(),
+                national_rail.calendar_days,
+                &national_rail.unmatched_report_path,
+            )
+            .await?;
+        println!("Loaded the CIF timetable.");
+    }
+
+    Ok(())
+}
+
+pub async fn ingest_naptan(options: IngestNaptanArgs, config: &Config) -> Result<()> {
+    let storage = local_storage(config).await?;
+    let path = options.path.unwrap_or_else(|| config.naptan.path.clone());
+    println!("Loading NaPTAN stops from {}.", path.display());
+    NaptanLoader::new(&storage)
+        .load(path, config.naptan.atco_prefixes.clone())
+        .await?;
+    println!("Loaded NaPTAN stops.");
+    Ok(())
+}
+
+pub async fn ingest_transxchange(options: IngestTxcArgs, config: &Config) -> Result<()> {
+    let storage = local_storage(config).await?;
+    if storage.repo::<StopPoint>().count().await? == 0 {
+        bail!("TransXChange timetables require stop points. Run `ingest tfl --stops` first.");
+    }
+    let txc = &config.transxchange;
+    let path = options.path.unwrap_or_else(|| txc.path.clone());
+    println!("Loading TransXChange timetables from {}.", path.display());
+    TxcLoader::new(&storage)
+        .load(
+            path,
+            txc.modes.clone(),
+            Local::now().date_naive(),
+            txc.calendar_days,
+        )
+        .await?;
+    println!("Loaded TransXChange timetables.");
+    Ok(())
+}
+
+pub async fn fix(options: FixArgs, config: &Config) -> Result<()> {
+    if !options.timetables {
+        bail!("Nothing to fix. Pass --timetables.");
     }
 
-    if options.build_graph {
-        println!("Building graph");
-        let now = Instant::now();
-        let graph_builder = MongoGraphBuilder::from_client(mongo_client).await;
-        let graph = graph_builder.build_graph().await?;
-        println!("Done building graph in {}ms", now.elapsed().as_millis());
+    let storage = local_storage(config).await?;
+
+    println!("Fixing timetables.");
+    DataFixer::fix_direct_connection_repo(&storage).await?;
+    println!("Done fixing timetables.");
+
+    Ok(())
+}
 
-        println!("Computing dijkstra's algorithm.");
-        let now = Instant::now();
-        let _scores = graph.tt_from_stop_id("490004733C".into(), NaiveTime::from_hms(10, 0, 0));
-        println!("Time for dijkstra's: {}ms", now.elapsed().as_millis());
-        //println!("{:#?}", scores);
+pub async fn copy_to_atlas(config: &Config) -> Result<()> {
+    let storage = local_storage(config).await?;
+    let atlas = Storage::atlas(config).await?;
+    println!("Copying collections");
+    copy_collections(&storage, &atlas).await?;
+    println!("Pasting collections");
+    Ok(())
+}
+
+pub async fn build_graph(options: BuildGraphArgs, config: &Config) -> Result<()> {
+    let storage = local_storage(config).await?;
+
+    println!("Building graph");
+    let now = Instant::now();
+    let graph_builder = GraphBuilder::new(&storage);
+    let snapshot = graph_builder.load_snapshot().await?;
+
+    if let Some(out) = &options.out {
+        snapshot.save(out)?;
+        println!("Saved snapshot to {}", out.display());
     }
 
+    snapshot.build_graph(&config.routing)?;
+    println!("Done building graph in {}ms", now.elapsed().as_millis());
+
+    Ok(())
+}
+
+pub async fn validate(options: ValidateArgs, config: &Config) -> Result<()> {
+    let storage = local_storage(config).await?;
+    let connections = storage.repo().get_all().await?.try_collect().await?;
+    let stop_points = storage.repo().get_all().await?.try_collect().await?;
+
+    let report = validation::validate(
+        connections,
+        stop_points,
+        &config.routing,
+        &config.validation,
+    )?;
+    let out = options
+        .out
+        .unwrap_or_else(|| config.validation.report_path.clone());
+    report.write(&out)?;
+
+    for (check, count) in &report.counts {
+        println!("{:?}: {}", check, count);
+    }
+    println!(
+        "Checked {} connections and {} stop points in {} components: {} errors, {} warnings. See {}.",
+        report.connections,
+        report.stop_points,
+        report.components,
+        report.errors,
+        report.warnings,
+        out.display()
+    );
+    if report.fails(options.strict) {
+        bail!("Validation failed.");
+    }
+    Ok(())
+}
+
+pub async fn diff(options: DiffArgs, config: &Config) -> Result<()> {
+    let start = parse_start(&options.at, options.on.as_deref())?;
+    let probes = if options.probe.is_empty() {
+        config.diff.probe_stop_ids.clone()
+    } else {
+        options.probe
+    };
+
+    let before = GraphSource::parse(&options.before, config).await?;
+    let after = GraphSource::parse(&options.after, config).await?;
+    println!("Comparing {} with {}.", before, after);
+    let diff = diff_graphs(
+        before.load_snapshot().await?,
+        after.load_snapshot().await?,
+        &config.routing,
+        &config.diff,
+        &probes,
+        start,
+    )?;
+
+    let out = options
+        .out
+        .unwrap_or_else(|| config.diff.report_path.clone());
+    diff.write(&out)?;
+    diff.print_summary();
+    println!("See {} for the details.", out.display());
     Ok(())
 }
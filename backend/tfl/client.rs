@@ -1,102 +1,229 @@
-use anyhow::{bail, Result};
+use std::time::Duration;
+
+use anyhow::Result;
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use futures::{stream, StreamExt};
-use reqwest::Url;
-use serde::Serialize;
+use rand::Rng;
+use reqwest::{header::RETRY_AFTER, Url};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::config::TflConfig;
 
-use super::{endpoint::Endpoint, errors::TflBadRequest};
+use super::{
+    endpoint::Endpoint,
+    errors::{TflBadRequest, TflError},
+    rate_limiter::RateLimiter,
+};
 
 #[async_trait(?Send)]
 pub trait Client {
-    async fn query<E: Endpoint + Sync + Serialize>(&self, endpoint: &E) -> Result<E::Returns>;
     async fn query_raw<E: Endpoint + Sync + Serialize>(&self, endpoint: &E) -> Result<String>;
 
+    async fn query<E: Endpoint + Sync + Serialize>(&self, endpoint: &E) -> Result<E::Returns> {
+        let response_body = self.query_raw(endpoint).await?;
+        decode_response(&response_body)
+    }
+
     async fn query_concurrently<'a, E, I>(&self, endpoints: I) -> Vec<Result<E::Returns>>
     where
         E: Endpoint + Sync + Serialize + 'a,
-        I: IntoIterator<Item = &'a E> + 'a;
+        I: IntoIterator<Item = &'a E> + 'a,
+    {
+        stream::iter(endpoints)
+            .map(|e| self.query(e))
+            .buffer_unordered(5)
+            .collect()
+            .await
+    }
+}
+
+/// Decodes a response body as `T`, or as the error TFL sent instead.
+pub fn decode_response<T: DeserializeOwned>(response_body: &str) -> Result<T> {
+    // Try to decode as T.
+    match serde_json::from_str(response_body) {
+        Ok(result) => {
+            return Ok(result);
+        }
+        Err(e) => println!("Error decoding response: {}", e),
+    }
+
+    // Try to decode as server error message:
+    match serde_json::from_str::<TflBadRequest>(response_body) {
+        Ok(failure) => Err(TflError::from(failure).into()),
+        Err(e) => Err(e.into()),
+    }
 }
 
 pub struct TFLClient {
     base_url: Url,
     reqwest_client: reqwest::Client,
     api_key: String,
+    rate_limiter: RateLimiter,
+    max_retries: u32,
+    max_retry_wait: Duration,
 }
 
 impl TFLClient {
-    pub fn new(api_key: &str) -> Result<Self, url::ParseError> {
-        let url = Url::parse("https://api.tfl.gov.uk/")?;
+    pub fn new(config: &TflConfig) -> Result<Self> {
         Ok(TFLClient {
-            base_url: url,
-            reqwest_client: reqwest::Client::new(),
-            api_key: api_key.into(),
+            base_url: Url::parse(&config.base_url)?,
+            reqwest_client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(config.timeout_seconds))
+                .build()?,
+            api_key: config.app_key()?.into(),
+            rate_limiter: RateLimiter::per_minute(config.requests_per_minute),
+            max_retries: config.max_retries,
+            max_retry_wait: Duration::from_secs(config.max_retry_wait_seconds),
         })
     }
-}
 
-#[async_trait(?Send)]
-impl Client for TFLClient {
-    async fn query<E: Endpoint + Sync + Serialize>(&self, endpoint: &E) -> Result<E::Returns>
+    async fn send<E>(&self, endpoint: &E, url: Url) -> Result<String, TflError>
     where
         E: Endpoint + Sync + Serialize,
     {
-        let joined_url = self.base_url.join(&endpoint.endpoint())?;
-
-        let request = self
+        self.rate_limiter.acquire().await;
+        let response = self
             .reqwest_client
-            .request(endpoint.method(), joined_url.clone())
+            .request(endpoint.method(), url)
             .query(&[("app_key", &self.api_key)])
-            .query(&endpoint)
-            .build()?;
-
-        let response = self.reqwest_client.execute(request).await?;
-        let response_body = response.text().await?;
+            .query(endpoint)
+            .send()
+            .await?;
 
-        // Try to decode as T.
-        match serde_json::from_str(&response_body) {
-            Ok(result) => {
-                return Ok(result);
-            }
-            Err(e) => println!("Error decoding response: {}", e),
-        }
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| parse_retry_after(value, Utc::now()));
+        let body = response.text().await?;
 
-        // Try to decode as server error message:
-        match serde_json::from_str(&response_body) {
-            Ok::<TflBadRequest, _>(failure) => bail!(
-                "server error: code:\n {}\n message: {}",
-                failure.http_status_code,
-                failure.message
-            ),
-            Err(e) => bail!("{}", e),
+        if status.is_success() {
+            Ok(body)
+        } else {
+            Err(TflError::from_response(status, retry_after, &body))
         }
     }
+}
 
+#[async_trait(?Send)]
+impl Client for TFLClient {
     async fn query_raw<E>(&self, endpoint: &E) -> anyhow::Result<String>
     where
         E: Endpoint + Sync + Serialize,
     {
         let joined_url = self.base_url.join(&endpoint.endpoint())?;
-        let response = self
-            .reqwest_client
-            .request(endpoint.method(), joined_url)
-            .query(&[("app_key", &self.api_key)])
-            .query(endpoint)
-            .send()
-            .await?
-            .text()
-            .await?;
-        Ok(response)
+        let mut attempt = 0;
+        loop {
+            let error = match self.send(endpoint, joined_url.clone()).await {
+                Ok(body) => return Ok(body),
+                Err(error) => error,
+            };
+
+            if !error.is_retryable() || attempt >= self.max_retries {
+                return Err(anyhow::Error::new(error).context(format!(
+                    "{} failed after {} attempt(s)",
+                    endpoint.endpoint(),
+                    attempt + 1
+                )));
+            }
+
+            let delay = match retry_delay(&error, attempt, self.max_retry_wait) {
+                Some(delay) => delay,
+                None => {
+                    return Err(anyhow::Error::new(error).context(format!(
+                        "{} asked to wait longer than {}s before retrying",
+                        endpoint.endpoint(),
+                        self.max_retry_wait.as_secs()
+                    )))
+                }
+            };
+            println!(
+                "{} ({}), retrying in {:.1}s.",
+                error,
+                endpoint.endpoint(),
+                delay.as_secs_f64()
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
     }
+}
 
-    async fn query_concurrently<'a, E, I>(&self, endpoints: I) -> Vec<Result<E::Returns>>
-    where
-        E: Endpoint + Sync + Serialize + 'a,
-        I: IntoIterator<Item = &'a E> + 'a,
-    {
-        stream::iter(endpoints)
-            .map(|e| self.query(e))
-            .buffer_unordered(5)
-            .collect()
-            .await
+const BASE_BACKOFF: Duration = Duration::from_millis(500);
+
+/// How long to wait before retrying after `error`: as long as the server
+/// asks, or backing off. `None` if the server asks for longer than `max`.
+fn retry_delay(error: &TflError, attempt: u32, max: Duration) -> Option<Duration> {
+    match error.retry_after() {
+        Some(delay) if delay > max => None,
+        Some(delay) => Some(delay),
+        None => Some(backoff(attempt, max)),
+    }
+}
+
+/// Exponential backoff with jitter: somewhere between half and all of
+/// `BASE_BACKOFF * 2^attempt`, at most `max`, so that concurrent requests
+/// don't retry in lockstep.
+fn backoff(attempt: u32, max: Duration) -> Duration {
+    let ceiling = BASE_BACKOFF
+        .saturating_mul(2_u32.saturating_pow(attempt))
+        .min(max);
+    ceiling / 2 + ceiling.mul_f64(rand::thread_rng().gen_range(0.0..0.5))
+}
+
+/// `Retry-After` is either a number of seconds or an HTTP date.
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_backoff_grows_with_jitter() {
+        let max = Duration::from_secs(60);
+        for attempt in 0..10 {
+            let ceiling = BASE_BACKOFF.saturating_mul(2_u32.pow(attempt)).min(max);
+            let delay = backoff(attempt, max);
+            assert!(delay >= ceiling / 2 && delay <= ceiling);
+        }
+    }
+
+    #[test]
+    fn test_retry_delay_is_bounded() {
+        let max = Duration::from_secs(60);
+        let asked = |seconds| TflError::RateLimited {
+            retry_after: Some(Duration::from_secs(seconds)),
+        };
+        assert_eq!(
+            retry_delay(&asked(30), 0, max),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(retry_delay(&asked(3600), 0, max), None);
+        assert!(retry_delay(&TflError::RateLimited { retry_after: None }, 20, max).unwrap() <= max);
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = Utc.with_ymd_and_hms(2015, 10, 21, 7, 27, 0).unwrap();
+        assert_eq!(parse_retry_after("12", now), Some(Duration::from_secs(12)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT", now),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
     }
 }
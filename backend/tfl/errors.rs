@@ -1,3 +1,6 @@
+use std::{fmt::Display, time::Duration};
+
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -24,3 +27,101 @@ pub struct TflBadRequest {
     pub http_status_code: i32,
     pub message: String,
 }
+
+/// Why a request to TfL failed, and whether it's worth sending again.
+#[derive(Debug)]
+pub enum TflError {
+    /// 429: over the quota.
+    RateLimited {
+        retry_after: Option<Duration>,
+    },
+    /// 5xx: TfL is having trouble, which is usually temporary.
+    Server {
+        status: u16,
+        message: String,
+    },
+    Timeout,
+    Connection(String),
+    /// Any other error status, e.g. an unknown line. Sending it again won't help.
+    Rejected {
+        status: u16,
+        message: String,
+    },
+}
+
+impl TflError {
+    pub fn from_response(status: StatusCode, retry_after: Option<Duration>, body: &str) -> Self {
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            return TflError::RateLimited { retry_after };
+        }
+
+        // TfL explains most failures with a TflBadRequest body.
+        let message = match serde_json::from_str::<TflBadRequest>(body) {
+            Ok(failure) => format!("{}: {}", failure.exception_type, failure.message),
+            Err(_) => body.chars().take(200).collect(),
+        };
+
+        if status.is_server_error() {
+            TflError::Server {
+                status: status.as_u16(),
+                message,
+            }
+        } else {
+            TflError::Rejected {
+                status: status.as_u16(),
+                message,
+            }
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        !matches!(self, TflError::Rejected { .. })
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            TflError::RateLimited { retry_after } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for TflError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            TflError::Timeout
+        } else {
+            // The URL contains the app key.
+            TflError::Connection(e.without_url().to_string())
+        }
+    }
+}
+
+impl From<TflBadRequest> for TflError {
+    fn from(failure: TflBadRequest) -> Self {
+        TflError::from_response(
+            StatusCode::from_u16(failure.http_status_code as u16)
+                .unwrap_or(StatusCode::BAD_REQUEST),
+            None,
+            &serde_json::to_string(&failure).unwrap_or_default(),
+        )
+    }
+}
+
+impl Display for TflError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TflError::RateLimited { .. } => write!(f, "rate limited by TfL"),
+            TflError::Server { status, message } => {
+                write!(f, "TfL server error {}: {}", status, message)
+            }
+            TflError::Timeout => write!(f, "request to TfL timed out"),
+            TflError::Connection(e) => write!(f, "could not reach TfL: {}", e),
+            TflError::Rejected { status, message } => {
+                write!(f, "TfL rejected the request with {}: {}", status, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TflError {}
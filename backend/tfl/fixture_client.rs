@@ -0,0 +1,93 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::Serialize;
+
+use super::{client::Client, endpoint::Endpoint};
+
+/// Wraps another client and saves every response it receives as a fixture
+/// that a `ReplayClient` can serve later.
+pub struct RecordingClient<C: Client> {
+    inner: C,
+    fixture_dir: PathBuf,
+}
+
+impl<C: Client> RecordingClient<C> {
+    pub fn new<P: Into<PathBuf>>(inner: C, fixture_dir: P) -> Self {
+        Self {
+            inner,
+            fixture_dir: fixture_dir.into(),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<C: Client> Client for RecordingClient<C> {
+    async fn query_raw<E: Endpoint + Sync + Serialize>(&self, endpoint: &E) -> Result<String> {
+        let response = self.inner.query_raw(endpoint).await?;
+        let path = fixture_path(&self.fixture_dir, endpoint)?;
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        tokio::fs::write(&path, &response).await?;
+        Ok(response)
+    }
+}
+
+/// Serves responses recorded by a `RecordingClient` without touching the network.
+pub struct ReplayClient {
+    fixture_dir: PathBuf,
+}
+
+impl ReplayClient {
+    pub fn new<P: Into<PathBuf>>(fixture_dir: P) -> Self {
+        Self {
+            fixture_dir: fixture_dir.into(),
+        }
+    }
+
+    /// Replays the fixtures checked in under `fixtures/tfl`.
+    #[cfg(test)]
+    pub fn checked_in() -> Self {
+        Self::new(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures/tfl"))
+    }
+}
+
+#[async_trait(?Send)]
+impl Client for ReplayClient {
+    async fn query_raw<E: Endpoint + Sync + Serialize>(&self, endpoint: &E) -> Result<String> {
+        let path = fixture_path(&self.fixture_dir, endpoint)?;
+        tokio::fs::read_to_string(&path)
+            .await
+            .with_context(|| format!("No recorded response at {}", path.display()))
+    }
+}
+
+/// Fixtures are keyed by endpoint and parameters, e.g. the response to
+/// `StopPoint/Mode/dlr/?page=2` lives in `StopPoint/Mode/dlr/page=2.json`.
+fn fixture_path<E: Endpoint + Serialize>(fixture_dir: &Path, endpoint: &E) -> Result<PathBuf> {
+    let mut path = fixture_dir.to_path_buf();
+    for segment in endpoint.endpoint().split('/').filter(|s| !s.is_empty()) {
+        path.push(sanitise(segment));
+    }
+
+    let params = serde_urlencoded::to_string(endpoint)?;
+    let file_name = if params.is_empty() {
+        "index".to_string()
+    } else {
+        sanitise(&params)
+    };
+    path.push(format!("{}.json", file_name));
+    Ok(path)
+}
+
+fn sanitise(segment: &str) -> String {
+    segment
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '?' | '*' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect()
+}
@@ -0,0 +1,227 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use chrono::{DateTime, Local, Timelike};
+
+use crate::{
+    config::TflLiveConfig,
+    graph::disruptions::{DisruptionSource, Disruptions, LineChange, LiveDisruptions},
+};
+
+use super::{
+    client::Client,
+    model::{
+        line_request::LineStatusByModeRequest,
+        line_response::{LineWithStatus, CLOSED_SEVERITIES, PART_CLOSED_SEVERITIES},
+        stops_request::ArrivalsRequest,
+    },
+};
+
+// TfL's statusSeverity codes, besides the closures.
+const SEVERE_DELAYS: [i64; 2] = [
+    6, // Severe Delays
+    7, // Reduced Service
+];
+const MINOR_DELAYS: i64 = 9;
+
+/// Polls TfL line status and arrival predictions, so that queries for "now"
+/// avoid closed lines and wait for the trains that are actually coming.
+pub struct LiveStatusPoller<C: Client> {
+    client: C,
+    config: TflLiveConfig,
+}
+
+impl<C: Client> LiveStatusPoller<C> {
+    pub fn new(client: C, config: TflLiveConfig) -> Self {
+        Self { client, config }
+    }
+
+    /// Fetches the current status, to be applied from `now`.
+    pub async fn poll(&self, now: DateTime<Local>) -> Result<Disruptions> {
+        let mut disruptions = Disruptions::default();
+        let start = now
+            .naive_local()
+            .with_second(0)
+            .and_then(|start| start.with_nanosecond(0))
+            .expect("Every minute has a first second");
+        disruptions.set_live_window(start, self.config.horizon_minutes);
+
+        let request = LineStatusByModeRequest::new(self.config.status_modes.clone());
+        for line in self.client.query(&request).await? {
+            if let Some(change) = self.line_change(&line) {
+                disruptions.set_line(&line.id, change);
+            }
+        }
+
+        let requests: Vec<_> = self
+            .config
+            .arrival_stop_ids
+            .iter()
+            .map(|id| ArrivalsRequest::new(id))
+            .collect();
+        for result in self.client.query_concurrently(&requests).await {
+            // Predictions only refine waits, so missing ones aren't fatal.
+            match result {
+                Ok(predictions) => {
+                    for prediction in predictions {
+                        disruptions.add_predicted_departure(
+                            &prediction.naptan_id,
+                            &prediction.line_id,
+                            minute_of_day(prediction.expected_arrival.with_timezone(&Local)),
+                        );
+                    }
+                }
+                Err(e) => println!("Could not fetch arrivals: {:#}", e),
+            }
+        }
+
+        Ok(disruptions)
+    }
+
+    /// Polls forever. Each poll replaces the last, and is dropped after the
+    /// configured TTL if polling stops succeeding.
+    pub async fn run(self, disruptions: Arc<LiveDisruptions>) {
+        let interval = Duration::from_secs(self.config.poll_interval_seconds);
+        let ttl = Duration::from_secs(self.config.ttl_seconds);
+        loop {
+            match self.poll(Local::now()).await {
+                Ok(polled) => {
+                    println!(
+                        "TfL reports {} lines delayed or closed.",
+                        polled.line_count()
+                    );
+                    disruptions
+                        .publish(DisruptionSource::TflStatus, polled, Some(ttl))
+                        .await;
+                }
+                Err(e) => println!("Could not poll TfL line status: {:#}", e),
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    // The worst of the line's statuses that are in effect now.
+    fn line_change(&self, line: &LineWithStatus) -> Option<LineChange> {
+        line.line_statuses
+            .iter()
+            .filter(|status| {
+                status.validity_periods.is_empty()
+                    || status.validity_periods.iter().any(|period| period.is_now)
+            })
+            .filter_map(|status| match status.status_severity {
+                s if CLOSED_SEVERITIES.contains(&s) => Some(LineChange::Closed),
+                // Which part is closed is left to planned closures.
+                s if PART_CLOSED_SEVERITIES.contains(&s) || SEVERE_DELAYS.contains(&s) => {
+                    Some(LineChange::Delayed(self.config.severe_delay_minutes))
+                }
+                MINOR_DELAYS => Some(LineChange::Delayed(self.config.minor_delay_minutes)),
+                _ => None,
+            })
+            .max_by_key(|change| match change {
+                LineChange::Closed => u16::MAX,
+                LineChange::Delayed(minutes) => *minutes,
+            })
+    }
+}
+
+fn minute_of_day(time: DateTime<Local>) -> u16 {
+    (time.hour() * 60 + time.minute()) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use crate::{
+        graph::connection::Connection,
+        tfl::{
+            fixture_client::ReplayClient,
+            model::{direct_connection::DirectConnection, stops_response::TransportMode},
+        },
+        util::time::Seconds,
+    };
+
+    use super::*;
+
+    fn connection(line_id: &str, departures: &[DateTime<Local>]) -> Connection {
+        Connection::from_direct_connection(&DirectConnection {
+            duration_minutes: 2.,
+            departure_times: departures.iter().map(|d| d.time()).collect(),
+            line_ids: vec![line_id.into()],
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_poll_recorded_status() {
+        let config = TflLiveConfig {
+            status_modes: vec![TransportMode::Tube],
+            arrival_stop_ids: vec!["940GZZLUHSD".into()],
+            ..Default::default()
+        };
+        let poller = LiveStatusPoller::new(ReplayClient::checked_in(), config);
+        // The recorded predictions are for 10:04 and 10:09 UTC.
+        let now = Utc
+            .with_ymd_and_hms(2022, 5, 2, 10, 0, 0)
+            .unwrap()
+            .with_timezone(&Local);
+        let minutes = |offset: i64| now + chrono::Duration::minutes(offset);
+        let disruptions = poller.poll(now).await.unwrap();
+
+        assert_eq!(disruptions.line("district"), Some(LineChange::Closed));
+        assert_eq!(
+            disruptions.line("piccadilly"),
+            Some(LineChange::Delayed(15))
+        );
+        // Victoria has a planned closure, but not now.
+        assert_eq!(disruptions.line("victoria"), None);
+
+        let today = now.date_naive();
+        let now = minute_of_day(now);
+        let arrival = |origin, destination, connection, previous, minute: u16| {
+            disruptions
+                .time_to_arrival(
+                    origin,
+                    destination,
+                    connection,
+                    previous,
+                    today,
+                    Seconds::from_minutes(minute.into()),
+                )
+                .map(Seconds::minutes)
+        };
+        let district = connection("district", &[minutes(0), minutes(10)]);
+        assert_eq!(
+            arrival("940GZZLUHSD", "940GZZLURVP", &district, None, now),
+            None
+        );
+        // Line status only applies for the next two hours.
+        assert_eq!(
+            arrival("940GZZLUHSD", "940GZZLURVP", &district, None, now - 5),
+            Some(7)
+        );
+        // Nor to the same time on another day.
+        let tomorrow = disruptions.time_to_arrival(
+            "940GZZLUHSD",
+            "940GZZLURVP",
+            &district,
+            None,
+            today.succ_opt().unwrap(),
+            Seconds::from_minutes(now.into()),
+        );
+        assert_eq!(tomorrow.map(Seconds::minutes), Some(2));
+
+        // Boarding waits for the predicted train at 10:04, then for the
+        // timetabled one at 10:10, which runs 15 minutes late.
+        let piccadilly = connection("piccadilly", &[minutes(0), minutes(10)]);
+        assert_eq!(
+            arrival("940GZZLUHSD", "940GZZLURVP", &piccadilly, None, now),
+            Some(10 + 2 + 15)
+        );
+        // Staying on a late train doesn't add the delay again.
+        assert_eq!(
+            arrival("x", "y", &piccadilly, Some(&piccadilly), now + 15),
+            Some(2)
+        );
+    }
+}
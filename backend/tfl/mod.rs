@@ -1,5 +1,8 @@
 pub mod client;
 pub mod endpoint;
 pub mod errors;
+pub mod fixture_client;
+pub mod live_status;
 pub mod model;
 pub mod param_value;
+pub mod rate_limiter;
@@ -1,8 +1,8 @@
 use anyhow::Result;
-use chrono::NaiveTime;
+use chrono::{NaiveDateTime, NaiveTime};
 use serde::{Deserialize, Serialize};
 
-use crate::db::{mongo_doc::MongoDoc, mongo_repo::MongoRepository};
+use crate::db::{mongo_doc::MongoDoc, repository::Repository};
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct DirectConnection {
@@ -12,6 +12,17 @@ pub struct DirectConnection {
     pub destination: String,
     pub duration_minutes: f64,
     pub departure_times: Vec<NaiveTime>,
+    /// Departures with the dates they run on, where the timetable says
+    /// (national rail). Empty if they run every day.
+    #[serde(default)]
+    pub dated_departures: Vec<NaiveDateTime>,
+    /// The TfL lines running between the stops, empty for other operators.
+    #[serde(default)]
+    pub line_ids: Vec<String>,
+    /// The national rail services (train UIDs) running between the stops,
+    /// to tell staying on a train from changing.
+    #[serde(default)]
+    pub service_ids: Vec<String>,
 }
 
 impl MongoDoc for DirectConnection {
@@ -33,23 +44,28 @@ impl MongoDoc for DirectConnection {
 }
 
 impl DirectConnection {
-    pub async fn mongo_insert(&mut self, repo: &MongoRepository<DirectConnection>) -> Result<()> {
-        let existing = repo.get_by_id(self.id()).await?;
-        match existing {
-            Some(mut existing) => {
-                existing.departure_times.append(&mut self.departure_times);
-                existing.departure_times.sort();
-                existing.departure_times.dedup();
-                repo.insert_or_replace(&existing).await?;
-            }
-            None => {
-                self.set_id();
-                self.departure_times.sort();
-                self.departure_times.dedup();
-                repo.insert(self).await?
-            }
-        };
-
-        Ok(())
+    /// Inserts this connection, or adds its departures, lines and services
+    /// to the existing connection between the same stops. Stored ones can
+    /// end up out of order, so the graph sorts them again.
+    pub async fn merge_insert(&mut self, repo: &dyn Repository<DirectConnection>) -> Result<()> {
+        self.set_id();
+        self.departure_times.sort();
+        self.departure_times.dedup();
+        self.dated_departures.sort();
+        self.dated_departures.dedup();
+        self.line_ids.sort();
+        self.line_ids.dedup();
+        self.service_ids.sort();
+        self.service_ids.dedup();
+        repo.add_to_sets(
+            self,
+            &[
+                "departure_times",
+                "dated_departures",
+                "line_ids",
+                "service_ids",
+            ],
+        )
+        .await
     }
 }
@@ -274,9 +274,33 @@ pub struct LineStatusDisruption {
     pub description: String,
     pub created: Option<String>,
     #[serde(rename = "affectedRoutes")]
-    pub affected_routes: Vec<Option<serde_json::Value>>,
+    pub affected_routes: Vec<AffectedRoute>,
     #[serde(rename = "affectedStops")]
-    pub affected_stops: Vec<Option<serde_json::Value>>,
+    pub affected_stops: Vec<AffectedStop>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AffectedRoute {
+    pub name: Option<String>,
+    pub direction: Option<String>,
+    #[serde(default)]
+    pub route_section_naptan_entry_sequence: Vec<RouteSectionNaptanEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteSectionNaptanEntry {
+    pub ordinal: i64,
+    pub stop_point: AffectedStop,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AffectedStop {
+    pub naptan_id: Option<String>,
+    pub id: Option<String>,
+    pub common_name: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -1,8 +1,12 @@
+use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
 
 use crate::{tfl::endpoint::Endpoint, util::string_util};
 
-use super::{line_response::LinesResult, stops_response::TransportMode};
+use super::{
+    line_response::{LineStatusResult, LinesResult},
+    stops_response::TransportMode,
+};
 
 #[derive(Default, Serialize, Deserialize)]
 pub struct LinesByModeRequest {
@@ -37,17 +41,93 @@ impl LinesByModeRequest {
     }
 }
 
+#[derive(Default, Serialize, Deserialize)]
+pub struct LineStatusByModeRequest {
+    #[serde(skip)]
+    modes: Vec<TransportMode>,
+}
+
+impl Endpoint for LineStatusByModeRequest {
+    fn method(&self) -> reqwest::Method {
+        reqwest::Method::GET
+    }
+
+    fn endpoint(&self) -> String {
+        format!(
+            "Line/Mode/{}/Status",
+            self.modes
+                .iter()
+                .map(|m| string_util::enum_to_string(m).unwrap())
+                .collect::<Vec<String>>()
+                .join(",")
+        )
+    }
+
+    type Returns = LineStatusResult;
+}
+
+impl LineStatusByModeRequest {
+    pub fn new<T: Into<Vec<TransportMode>>>(modes: T) -> Self {
+        Self {
+            modes: modes.into(),
+        }
+    }
+}
+
+/// Line status over a range of days, including planned works.
+#[derive(Serialize, Deserialize)]
+pub struct LineStatusBetweenRequest {
+    #[serde(skip)]
+    modes: Vec<TransportMode>,
+    #[serde(skip)]
+    from: NaiveDate,
+    #[serde(skip)]
+    to: NaiveDate,
+    /// Includes the affected routes and stops of each disruption.
+    detail: bool,
+}
+
+impl Endpoint for LineStatusBetweenRequest {
+    fn method(&self) -> reqwest::Method {
+        reqwest::Method::GET
+    }
+
+    fn endpoint(&self) -> String {
+        format!(
+            "Line/Mode/{}/Status/{}/to/{}",
+            self.modes
+                .iter()
+                .map(|m| string_util::enum_to_string(m).unwrap())
+                .collect::<Vec<String>>()
+                .join(","),
+            self.from.format("%Y-%m-%d"),
+            self.to.format("%Y-%m-%d")
+        )
+    }
+
+    type Returns = LineStatusResult;
+}
+
+impl LineStatusBetweenRequest {
+    pub fn new<T: Into<Vec<TransportMode>>>(modes: T, from: NaiveDate, to: NaiveDate) -> Self {
+        Self {
+            modes: modes.into(),
+            from,
+            to,
+            detail: true,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::env;
-
-    use crate::tfl::client::{Client, TFLClient};
+    use crate::tfl::{client::Client, fixture_client::ReplayClient};
 
     use super::*;
 
     #[tokio::test]
     async fn test_request() {
-        let client = TFLClient::new(&env::var("TFL_CLIENT_URI").unwrap()).unwrap();
+        let client = ReplayClient::checked_in();
 
         let request = LinesByModeRequest::new(vec![TransportMode::Dlr, TransportMode::CableCar]);
 
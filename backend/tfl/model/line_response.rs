@@ -2,9 +2,24 @@ use serde::{Deserialize, Serialize};
 
 use crate::db::mongo_doc::MongoDoc;
 
-use super::stops_response::TransportMode;
+use super::{journey_response::LineStatus, stops_response::TransportMode};
 
 pub type LinesResult = Vec<Line>;
+pub type LineStatusResult = Vec<LineWithStatus>;
+
+// TfL's statusSeverity codes.
+pub const CLOSED_SEVERITIES: [i64; 5] = [
+    1,  // Closed
+    2,  // Suspended
+    4,  // Planned Closure
+    16, // Not Running
+    20, // Service Closed
+];
+pub const PART_CLOSED_SEVERITIES: [i64; 3] = [
+    3,  // Part Suspended
+    5,  // Part Closure
+    11, // Part Closed
+];
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Line {
@@ -17,6 +32,15 @@ pub struct Line {
     pub route_sections: Vec<RouteEndpoints>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LineWithStatus {
+    pub id: String,
+    pub name: String,
+    pub mode_name: TransportMode,
+    pub line_statuses: Vec<LineStatus>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct RouteEndpoints {
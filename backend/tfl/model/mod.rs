@@ -2,7 +2,9 @@ pub mod direct_connection;
 pub mod journey_response;
 pub mod line_request;
 pub mod line_response;
+pub mod planned_closure;
 pub mod stops_request;
 pub mod stops_response;
 pub mod time_table_request;
 pub mod time_table_response;
+pub mod trip;
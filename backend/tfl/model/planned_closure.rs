@@ -0,0 +1,151 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::db::mongo_doc::MongoDoc;
+
+use super::{
+    journey_response::{AffectedStop, LineStatus},
+    line_response::{CLOSED_SEVERITIES, PART_CLOSED_SEVERITIES},
+};
+
+/// A planned closure of (part of) a TfL line, e.g. weekend engineering works.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlannedClosure {
+    #[serde(rename = "_id")]
+    pub id: Option<String>,
+    pub line_id: String,
+    pub description: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// The line doesn't run between any two of these stops. Empty if the
+    /// whole line is closed.
+    pub stop_ids: Vec<String>,
+    /// Stops in route order along which rail replacement buses run.
+    pub replacement_bus_routes: Vec<Vec<String>>,
+}
+
+impl MongoDoc for PlannedClosure {
+    fn database_name() -> &'static str {
+        "tfl"
+    }
+
+    fn collection_name() -> &'static str {
+        "plannedClosures"
+    }
+
+    fn id(&self) -> String {
+        // Different sections of a line can close over the same period.
+        format!(
+            "{}:{}-{}:{}",
+            self.line_id,
+            self.from.format("%Y%m%dT%H%M"),
+            self.to.format("%Y%m%dT%H%M"),
+            self.stop_ids.first().map(String::as_str).unwrap_or("all")
+        )
+    }
+
+    fn set_id(&mut self) {
+        self.id = Some(self.id());
+    }
+}
+
+impl PlannedClosure {
+    /// One closure per validity period of `status`, if it closes anything
+    /// we can locate on the line.
+    pub fn from_line_status(line_id: &str, status: &LineStatus) -> Vec<PlannedClosure> {
+        let severity = status.status_severity;
+        let whole_line = CLOSED_SEVERITIES.contains(&severity);
+        if !whole_line && !PART_CLOSED_SEVERITIES.contains(&severity) {
+            return vec![];
+        }
+        let disruption = match &status.disruption {
+            Some(disruption) => disruption,
+            None => return vec![],
+        };
+
+        let replacement_bus_routes: Vec<Vec<String>> = disruption
+            .affected_routes
+            .iter()
+            .map(|route| {
+                let mut entries: Vec<_> =
+                    route.route_section_naptan_entry_sequence.iter().collect();
+                entries.sort_by_key(|entry| entry.ordinal);
+                entries
+                    .iter()
+                    .filter_map(|entry| stop_id(&entry.stop_point))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|stops| stops.len() > 1)
+            .collect();
+
+        let mut stop_ids: Vec<String> = disruption
+            .affected_stops
+            .iter()
+            .filter_map(stop_id)
+            .chain(replacement_bus_routes.iter().flatten().cloned())
+            .collect();
+        stop_ids.sort();
+        stop_ids.dedup();
+        // Without stops there's no telling which part of the line is closed.
+        if stop_ids.is_empty() && !whole_line {
+            return vec![];
+        }
+
+        let description = status
+            .reason
+            .clone()
+            .unwrap_or_else(|| disruption.description.clone());
+        let has_replacement_buses = [&description, &disruption.description]
+            .iter()
+            .any(|text| text.to_lowercase().contains("replacement bus"));
+
+        status
+            .validity_periods
+            .iter()
+            .filter_map(|period| {
+                let mut closure = PlannedClosure {
+                    id: None,
+                    line_id: line_id.to_string(),
+                    description: description.clone(),
+                    from: parse_tfl_date(&period.from_date)?,
+                    to: parse_tfl_date(&period.to_date)?,
+                    stop_ids: stop_ids.clone(),
+                    replacement_bus_routes: if has_replacement_buses {
+                        replacement_bus_routes.clone()
+                    } else {
+                        vec![]
+                    },
+                };
+                closure.set_id();
+                Some(closure)
+            })
+            .collect()
+    }
+
+    pub fn is_active(&self, at: DateTime<Utc>) -> bool {
+        self.from <= at && at < self.to
+    }
+
+    /// Whether `line_id` doesn't run between `origin` and `destination`.
+    pub fn closes(&self, line_id: &str, origin: &str, destination: &str) -> bool {
+        self.line_id == line_id
+            && (self.stop_ids.is_empty()
+                || (self.stop_ids.iter().any(|s| s == origin)
+                    && self.stop_ids.iter().any(|s| s == destination)))
+    }
+}
+
+fn stop_id(stop: &AffectedStop) -> Option<String> {
+    stop.naptan_id.clone().or_else(|| stop.id.clone())
+}
+
+// TfL dates are UTC, sometimes without the Z.
+fn parse_tfl_date(date: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(date)
+        .map(|date| date.with_timezone(&Utc))
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S").map(|date| date.and_utc())
+        })
+        .ok()
+}
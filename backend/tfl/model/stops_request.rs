@@ -2,7 +2,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::{tfl::endpoint::Endpoint, util::string_util};
 
-use super::stops_response::{StopsResponse, TransportMode};
+use super::stops_response::{ArrivalsResult, StopsResponse, TransportMode};
 
 #[derive(Default, Serialize, Deserialize)]
 pub struct StopsByModeRequest {
@@ -39,17 +39,42 @@ impl StopsByModeRequest {
     }
 }
 
+/// Predicted arrivals at a station, for every line calling there.
+#[derive(Default, Serialize, Deserialize)]
+pub struct ArrivalsRequest {
+    #[serde(skip)]
+    stop_point_id: String,
+}
+
+impl Endpoint for ArrivalsRequest {
+    fn method(&self) -> reqwest::Method {
+        reqwest::Method::GET
+    }
+
+    fn endpoint(&self) -> String {
+        format!("StopPoint/{}/Arrivals", self.stop_point_id)
+    }
+
+    type Returns = ArrivalsResult;
+}
+
+impl ArrivalsRequest {
+    pub fn new(stop_point_id: &str) -> Self {
+        Self {
+            stop_point_id: stop_point_id.to_string(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::env;
-
-    use crate::tfl::client::{Client, TFLClient};
+    use crate::tfl::{client::Client, fixture_client::ReplayClient};
 
     use super::*;
 
     #[tokio::test]
     async fn test_request() {
-        let client = TFLClient::new(&env::var("TFL_CLIENT_URI").unwrap()).unwrap();
+        let client = ReplayClient::checked_in();
 
         let mut request =
             StopsByModeRequest::new(vec![TransportMode::Dlr, TransportMode::CableCar]);
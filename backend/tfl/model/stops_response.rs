@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use geo::Point;
 use serde::{Deserialize, Serialize};
 
@@ -23,17 +24,49 @@ pub struct StopPoint {
     //#[serde(rename = "icsCode")]
     //pub ics_code: Option<String>,
     pub stop_type: Option<StopType>,
-    //#[serde(rename = "stationNaptan")]
-    //pub station_naptan: Option<String>,
+    /// The stop area this stop is part of, e.g. a platform's station.
+    #[serde(default)]
+    pub station_naptan: Option<String>,
     pub lines: Vec<Line>,
     #[serde(alias = "id")]
     #[serde(rename = "_id")]
     pub id: String,
     pub common_name: String,
-    //pub children: Vec<StopPoint>,
+    /// The stops and stop areas that are part of this one, from NaPTAN.
+    #[serde(default)]
+    pub child_ids: Vec<String>,
+    /// Every NaPTAN stop area the stop is in.
+    #[serde(default)]
+    pub stop_areas: Vec<String>,
+    /// Tells stops of the same name apart, e.g. "Stop B" or "Platform 2".
+    #[serde(default)]
+    pub indicator: Option<String>,
+    /// The compass direction buses leave a stop in, e.g. "NE".
+    #[serde(default)]
+    pub bearing: Option<String>,
+    /// Whatever else TfL knows about the stop, such as its facilities.
+    #[serde(default)]
+    pub additional_properties: Vec<AdditionalProperty>,
     pub lat: f64,
     pub lon: f64,
-    pub tiploc: Option<String>,
+}
+
+impl StopPoint {
+    /// The fare zone, e.g. "2", or "2+3" for a station on the boundary.
+    pub fn zone(&self) -> Option<&str> {
+        self.additional_properties
+            .iter()
+            .find(|property| property.key == "Zone")
+            .map(|property| property.value.as_str())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdditionalProperty {
+    pub category: String,
+    pub key: String,
+    pub value: String,
 }
 
 impl MongoDoc for StopPoint {
@@ -60,6 +93,21 @@ impl From<&StopPoint> for Station {
     }
 }
 
+pub type ArrivalsResult = Vec<Prediction>;
+
+/// A predicted arrival of a vehicle at a stop.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Prediction {
+    pub naptan_id: String,
+    pub line_id: String,
+    pub platform_name: Option<String>,
+    pub destination_naptan_id: Option<String>,
+    /// Seconds until arrival, as of when the prediction was made.
+    pub time_to_station: i64,
+    pub expected_arrival: DateTime<Utc>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Line {
     pub id: String,
@@ -67,7 +115,7 @@ pub struct Line {
     pub uri: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum TransportMode {
     Bus,
@@ -93,7 +141,7 @@ pub enum TransportMode {
     ElizabethLine,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum StopType {
     CarPickupSetDownArea,
     NaptanAirAccessArea,
@@ -134,3 +182,68 @@ pub enum StopType {
     NaptanUnmarkedPoint,
     TransportInterchange,
 }
+
+impl StopType {
+    /// The stop type of a NaPTAN stop or stop area type code, e.g. "RPL".
+    pub fn from_naptan_code(code: &str) -> Option<Self> {
+        Some(match code {
+            "AIR" => StopType::NaptanAirAccessArea,
+            "GAT" => StopType::NaptanAirEntrance,
+            "BCE" => StopType::NaptanCoachEntrance,
+            "BST" => StopType::NaptanCoachAccessArea,
+            "BCS" => StopType::NaptanCoachBay,
+            "BCQ" => StopType::NaptanCoachVariableBay,
+            "BCT" => StopType::NaptanPublicBusCoachTram,
+            "FTD" => StopType::NaptanFerryEntrance,
+            "FER" => StopType::NaptanFerryAccessArea,
+            "FBT" => StopType::NaptanFerryBerth,
+            "RSE" => StopType::NaptanRailEntrance,
+            "RLY" => StopType::NaptanRailAccessArea,
+            "RPL" => StopType::NaptanRailPlatform,
+            "TMU" => StopType::NaptanMetroEntrance,
+            "MET" => StopType::NaptanMetroAccessArea,
+            "PLT" => StopType::NaptanMetroPlatform,
+            "LCE" => StopType::NaptanLiftCableCarEntrance,
+            "LCB" => StopType::NaptanLiftCableCarAccessArea,
+            "LPL" => StopType::NaptanLiftCableCarStop,
+            "TXR" => StopType::NaptanTaxiRank,
+            "STR" => StopType::NaptanSharedTaxi,
+            "GAIR" => StopType::NaptanAirportBuilding,
+            "GBCS" => StopType::NaptanBusCoachStation,
+            "GCCH" => StopType::NaptanCoachServiceCoverage,
+            "GCLS" => StopType::NaptanOnstreetBusCoachStopCluster,
+            "GPBS" => StopType::NaptanOnstreetBusCoachStopPair,
+            "GFTD" => StopType::NaptanFerryPort,
+            "GLCB" => StopType::NaptanLiftCableCarStopArea,
+            "GRLS" => StopType::NaptanRailStation,
+            "GTMU" => StopType::NaptanMetroStation,
+            _ => return None,
+        })
+    }
+
+    /// Whether stops of this type are a platform, entrance or concourse of
+    /// the station they belong to, rather than a stop in their own right.
+    pub fn is_part_of_station(self) -> bool {
+        matches!(
+            self,
+            StopType::NaptanAirAccessArea
+                | StopType::NaptanAirEntrance
+                | StopType::NaptanCoachAccessArea
+                | StopType::NaptanCoachBay
+                | StopType::NaptanCoachEntrance
+                | StopType::NaptanCoachVariableBay
+                | StopType::NaptanFerryAccessArea
+                | StopType::NaptanFerryBerth
+                | StopType::NaptanFerryEntrance
+                | StopType::NaptanLiftCableCarAccessArea
+                | StopType::NaptanLiftCableCarEntrance
+                | StopType::NaptanLiftCableCarStop
+                | StopType::NaptanMetroAccessArea
+                | StopType::NaptanMetroEntrance
+                | StopType::NaptanMetroPlatform
+                | StopType::NaptanRailAccessArea
+                | StopType::NaptanRailEntrance
+                | StopType::NaptanRailPlatform
+        )
+    }
+}
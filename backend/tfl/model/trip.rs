@@ -0,0 +1,146 @@
+use std::collections::BTreeMap;
+
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{db::mongo_doc::MongoDoc, national_rail::xml_timetable::passenger_legs};
+
+/// One run of a vehicle along its stops.
+///
+/// Connections only say when something leaves one stop for the next. A trip
+/// says which departure further on is the same vehicle, so that staying on
+/// board can be told apart from changing.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Trip {
+    #[serde(rename = "_id")]
+    pub id: String,
+    /// The TfL line or bus route, if the trip has one.
+    pub line_id: Option<String>,
+    /// The days the trip starts on, sorted. Empty if it runs every day.
+    #[serde(default)]
+    pub dates: Vec<NaiveDate>,
+    pub stop_times: Vec<StopTime>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StopTime {
+    pub stop_id: String,
+    /// Minutes after midnight of the day the trip starts, so past 1440 once
+    /// it runs past midnight.
+    pub arrival_minutes: u32,
+    pub departure_minutes: u32,
+    pub picks_up: bool,
+    pub sets_down: bool,
+}
+
+/// A ride between two stops of a trip.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TripLeg<'a> {
+    pub origin: &'a str,
+    pub destination: &'a str,
+    pub departure_minutes: u32,
+    pub arrival_minutes: u32,
+}
+
+impl MongoDoc for Trip {
+    fn database_name() -> &'static str {
+        "tfl"
+    }
+
+    fn collection_name() -> &'static str {
+        "trips"
+    }
+
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+}
+
+impl Trip {
+    /// The trip a vehicle starting on `date` makes along `legs`, in order,
+    /// each from a stop it picks up at to the next one it sets down at. The
+    /// trip isn't dated.
+    pub fn from_legs<'a>(
+        id: String,
+        line_id: Option<String>,
+        date: NaiveDate,
+        legs: impl IntoIterator<Item = (&'a str, &'a str, NaiveDateTime, NaiveDateTime)>,
+    ) -> Self {
+        let midnight = date.and_time(NaiveTime::MIN);
+        let minutes = |time: NaiveDateTime| (time - midnight).num_minutes().max(0) as u32;
+        let mut stop_times: Vec<StopTime> = vec![];
+        for (origin, destination, departure, arrival) in legs {
+            match stop_times.last_mut() {
+                Some(last) if last.stop_id == origin => {
+                    last.departure_minutes = minutes(departure);
+                    last.picks_up = true;
+                }
+                _ => stop_times.push(StopTime {
+                    stop_id: origin.to_string(),
+                    arrival_minutes: minutes(departure),
+                    departure_minutes: minutes(departure),
+                    picks_up: true,
+                    sets_down: false,
+                }),
+            }
+            stop_times.push(StopTime {
+                stop_id: destination.to_string(),
+                arrival_minutes: minutes(arrival),
+                departure_minutes: minutes(arrival),
+                picks_up: false,
+                sets_down: true,
+            });
+        }
+        Self {
+            id,
+            line_id,
+            dates: vec![],
+            stop_times,
+        }
+    }
+
+    /// Merges the runs of each trip that call at the same stops at the same
+    /// times into one trip running on all their dates. Runs that differ, e.g.
+    /// a short term overlay, become trips of their own with a numbered id.
+    pub fn merge_runs(runs: impl IntoIterator<Item = Trip>) -> Vec<Trip> {
+        let mut by_id: BTreeMap<String, Vec<Trip>> = BTreeMap::new();
+        for run in runs {
+            let variants = by_id.entry(run.id.clone()).or_default();
+            match variants
+                .iter_mut()
+                .find(|v| v.stop_times == run.stop_times && v.line_id == run.line_id)
+            {
+                Some(variant) => variant.dates.extend(run.dates),
+                None => variants.push(run),
+            }
+        }
+        by_id
+            .into_values()
+            .flat_map(|variants| variants.into_iter().enumerate())
+            .map(|(i, mut trip)| {
+                if i > 0 {
+                    trip.id = format!("{}:{}", trip.id, i);
+                }
+                trip.dates.sort();
+                trip.dates.dedup();
+                trip
+            })
+            .collect()
+    }
+
+    /// The legs passengers can ride, from every stop the trip picks up at to
+    /// the next one it sets down at, as in `passenger_legs`.
+    pub fn legs(&self) -> Vec<TripLeg<'_>> {
+        passenger_legs(&self.stop_times, |s| s.picks_up, |s| s.sets_down)
+            .into_iter()
+            .map(|(i, j)| TripLeg {
+                origin: &self.stop_times[i].stop_id,
+                destination: &self.stop_times[j].stop_id,
+                departure_minutes: self.stop_times[i].departure_minutes,
+                arrival_minutes: self.stop_times[j].arrival_minutes,
+            })
+            .collect()
+    }
+}
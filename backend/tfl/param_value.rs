@@ -1,4 +1,6 @@
-use chrono::{Date, DateTime, NaiveDate, NaiveTime, Utc};
+#[allow(deprecated)]
+use chrono::Date;
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
 pub trait ParamValue {
     #[allow(clippy::wrong_self_convention)]
     /// The parameter value as a string.
@@ -57,6 +59,8 @@ impl ParamValue for NaiveDate {
     }
 }
 
+// `Date` is deprecated in chrono, but still accepted for parameters.
+#[allow(deprecated)]
 impl ParamValue for Date<Utc> {
     fn as_value(&self) -> String {
         format!("{}", self.format("%Y%m%d"))
@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use tokio::{sync::Mutex, time::Instant};
+
+/// A token bucket that lets through at most `per_minute` requests a minute,
+/// in bursts of up to `per_minute` requests.
+pub struct RateLimiter {
+    bucket: Mutex<TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn per_minute(per_minute: u32) -> Self {
+        Self {
+            bucket: Mutex::new(TokenBucket::new(per_minute, Instant::now())),
+        }
+    }
+
+    /// Waits until a request may be sent.
+    pub async fn acquire(&self) {
+        loop {
+            let wait = self.bucket.lock().await.try_take(Instant::now());
+            match wait {
+                None => return,
+                Some(wait) => tokio::time::sleep(wait).await,
+            }
+        }
+    }
+}
+
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    tokens_per_second: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(per_minute: u32, now: Instant) -> Self {
+        let capacity = per_minute.max(1) as f64;
+        Self {
+            capacity,
+            tokens: capacity,
+            tokens_per_second: capacity / 60.,
+            last_refill: now,
+        }
+    }
+
+    /// Takes a token, or returns how long until the next one is available.
+    fn try_take(&mut self, now: Instant) -> Option<Duration> {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.tokens_per_second).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1. {
+            self.tokens -= 1.;
+            None
+        } else {
+            Some(Duration::from_secs_f64(
+                (1. - self.tokens) / self.tokens_per_second,
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_refills_at_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(60, start);
+
+        for _ in 0..60 {
+            assert!(bucket.try_take(start).is_none());
+        }
+        let wait = bucket.try_take(start).unwrap();
+        assert!((wait.as_secs_f64() - 1.).abs() < 1e-6);
+
+        // One token a second, and never more than the capacity.
+        assert!(bucket.try_take(start + Duration::from_secs(1)).is_none());
+        assert!(bucket.try_take(start + Duration::from_secs(1)).is_some());
+        let later = start + Duration::from_secs(3600);
+        for _ in 0..60 {
+            assert!(bucket.try_take(later).is_none());
+        }
+        assert!(bucket.try_take(later).is_some());
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use futures::TryStreamExt;
+use tokio::task;
+use zip::ZipArchive;
+
+use crate::{
+    db::{repository::Repository, storage::Storage},
+    tfl::model::{direct_connection::DirectConnection, stops_response::StopPoint, trip::Trip},
+};
+
+use super::txc::{read_vehicle_journeys, VehicleJourney};
+
+pub struct TxcLoader {
+    dc_repo: Box<dyn Repository<DirectConnection>>,
+    stop_repo: Box<dyn Repository<StopPoint>>,
+    trip_repo: Box<dyn Repository<Trip>>,
+}
+
+impl TxcLoader {
+    pub fn new(storage: &Storage) -> Self {
+        Self {
+            dc_repo: storage.repo(),
+            stop_repo: storage.repo(),
+            trip_repo: storage.repo(),
+        }
+    }
+
+    /// Loads the trips of `modes` in the TransXChange files at `path` (a
+    /// directory or a zip of them) that run in the `days` days from `from`.
+    ///
+    /// Legs to or from stops that aren't stored are dropped.
+    pub async fn load(
+        &self,
+        path: PathBuf,
+        modes: Vec<String>,
+        from: NaiveDate,
+        days: u32,
+    ) -> Result<()> {
+        let stop_ids: HashSet<String> = self
+            .stop_repo
+            .get_all()
+            .await?
+            .map_ok(|stop| stop.id)
+            .try_collect()
+            .await?;
+
+        let trips = task::spawn_blocking(move || -> Result<Vec<VehicleJourney>> {
+            let mut trips = vec![];
+            for_each_document(&path, |name, input| {
+                match read_vehicle_journeys(BufReader::new(input)) {
+                    Ok(read) => trips.extend(read.into_iter().filter(|t| modes.contains(&t.mode))),
+                    Err(e) => println!("Skipping {}: {:#}", name, e),
+                }
+                Ok(())
+            })?;
+            Ok(trips)
+        })
+        .await??;
+
+        let mut connections: HashMap<(&str, &str), DirectConnection> = HashMap::new();
+        let mut runs = vec![];
+        let (mut journeys, mut dropped) = (0, 0);
+        for date in from.iter_days().take(days as usize) {
+            for trip in trips.iter().filter(|trip| trip.runs_on(date)) {
+                let legs = trip.legs(date);
+                let (legs, unknown): (Vec<_>, Vec<_>) = legs.into_iter().partition(|leg| {
+                    stop_ids.contains(leg.origin) && stop_ids.contains(leg.destination)
+                });
+                dropped += unknown.len();
+                let line_id = trip.line_name.to_lowercase();
+                let mut run = Trip::from_legs(
+                    trip.id.clone(),
+                    Some(line_id.clone()),
+                    date,
+                    legs.iter()
+                        .map(|leg| (leg.origin, leg.destination, leg.departure, leg.arrival)),
+                );
+                run.dates = vec![date];
+                runs.push(run);
+                for leg in legs {
+                    let con = connections
+                        .entry((leg.origin, leg.destination))
+                        .or_insert_with(|| DirectConnection {
+                            origin: leg.origin.to_string(),
+                            destination: leg.destination.to_string(),
+                            duration_minutes: (leg.arrival - leg.departure).num_minutes() as f64,
+                            ..Default::default()
+                        });
+                    con.departure_times.push(leg.departure.time());
+                    con.dated_departures.push(leg.departure);
+                    con.line_ids.push(line_id.clone());
+                    con.service_ids.push(trip.id.clone());
+                }
+                journeys += 1;
+            }
+        }
+        println!(
+            "Read {} trips, running {} journeys in {} days. Dropped {} legs to unknown stops.",
+            trips.len(),
+            journeys,
+            days,
+            dropped
+        );
+
+        for mut con in connections.into_values() {
+            con.line_ids.sort();
+            con.line_ids.dedup();
+            con.merge_insert(self.dc_repo.as_ref()).await?;
+        }
+        for trip in Trip::merge_runs(runs) {
+            self.trip_repo.insert_or_replace(&trip).await?;
+        }
+        Ok(())
+    }
+}
+
+// Every `.xml` file in a directory (recursively) or a zip.
+fn for_each_document(
+    path: &Path,
+    mut read: impl FnMut(&str, &mut dyn Read) -> Result<()>,
+) -> Result<()> {
+    if path.is_dir() {
+        let mut paths = vec![];
+        let mut dirs = vec![path.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(&dir)? {
+                let entry_path = entry?.path();
+                if entry_path.is_dir() {
+                    dirs.push(entry_path);
+                } else if is_xml(&entry_path.to_string_lossy()) {
+                    paths.push(entry_path);
+                }
+            }
+        }
+        paths.sort();
+        for file_path in paths {
+            let mut file = File::open(&file_path)?;
+            read(&file_path.to_string_lossy(), &mut file)?;
+        }
+        return Ok(());
+    }
+
+    let file = File::open(path).with_context(|| format!("Couldn't open {}", path.display()))?;
+    let mut archive = ZipArchive::new(file)?;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        let name = entry.name().to_string();
+        if is_xml(&name) {
+            read(&name, &mut entry)?;
+        }
+    }
+    Ok(())
+}
+
+fn is_xml(name: &str) -> bool {
+    name.to_lowercase().ends_with(".xml")
+}
@@ -0,0 +1,3 @@
+pub mod loader;
+pub mod operating_profile;
+pub mod txc;
@@ -0,0 +1,213 @@
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+
+/// When a TransXChange service or vehicle journey runs.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OperatingProfile {
+    /// Monday first. A profile without days of the week runs every day.
+    pub days_of_week: [bool; 7],
+    /// Only runs on the bank holidays in `bank_holiday_operation`.
+    pub holidays_only: bool,
+    pub special_operation: Vec<(NaiveDate, NaiveDate)>,
+    pub special_non_operation: Vec<(NaiveDate, NaiveDate)>,
+    /// TransXChange bank holiday names, e.g. `GoodFriday` or `AllBankHolidays`.
+    pub bank_holiday_operation: Vec<String>,
+    pub bank_holiday_non_operation: Vec<String>,
+    /// Dates of `OtherPublicHoliday`s.
+    pub other_holiday_operation: Vec<NaiveDate>,
+    pub other_holiday_non_operation: Vec<NaiveDate>,
+}
+
+impl OperatingProfile {
+    pub fn every_day() -> Self {
+        Self {
+            days_of_week: [true; 7],
+            ..Default::default()
+        }
+    }
+
+    /// Whether the profile runs on `date`. Special days take precedence over
+    /// bank holidays, which take precedence over the days of the week.
+    pub fn runs_on(&self, date: NaiveDate) -> bool {
+        let within = |ranges: &[(NaiveDate, NaiveDate)]| {
+            ranges
+                .iter()
+                .any(|(start, end)| *start <= date && date <= *end)
+        };
+        if within(&self.special_non_operation) {
+            return false;
+        }
+        if within(&self.special_operation) {
+            return true;
+        }
+
+        let holidays = bank_holidays_on(date);
+        let matches = |names: &[String]| names.iter().any(|name| holidays.contains(&name.as_str()));
+        if matches(&self.bank_holiday_non_operation)
+            || self.other_holiday_non_operation.contains(&date)
+        {
+            return false;
+        }
+        if matches(&self.bank_holiday_operation) || self.other_holiday_operation.contains(&date) {
+            return true;
+        }
+
+        !self.holidays_only && self.days_of_week[date.weekday().num_days_from_monday() as usize]
+    }
+}
+
+/// The TransXChange names that `date` goes by if it is a bank holiday in
+/// England and Wales, most specific first.
+///
+/// Holidays are worked out from the usual rules, so one-off ones such as a
+/// jubilee aren't known.
+pub fn bank_holidays_on(date: NaiveDate) -> Vec<&'static str> {
+    let year = date.year();
+    let ymd = |month, day| NaiveDate::from_ymd_opt(year, month, day).unwrap();
+    let easter = easter_sunday(year);
+    let is_weekend = |date: NaiveDate| matches!(date.weekday(), Weekday::Sat | Weekday::Sun);
+
+    let mut names = vec![];
+    let mut holiday = |name, displaced: bool| {
+        names.push(name);
+        names.push("AllBankHolidays");
+        if displaced {
+            names.push("DisplacementHolidays");
+        }
+    };
+    let (christmas, boxing_day) = (ymd(12, 25), ymd(12, 26));
+
+    if date == ymd(1, 1) {
+        holiday("NewYearsDay", false);
+    }
+    if date == next_weekday(ymd(1, 1)) && is_weekend(ymd(1, 1)) {
+        holiday("NewYearsDayHoliday", true);
+    }
+    if date == easter - Duration::days(2) {
+        holiday("GoodFriday", false);
+    }
+    if date == easter + Duration::days(1) {
+        holiday("EasterMonday", false);
+    }
+    if date == nth_monday(year, 5, 1) {
+        holiday("MayDay", false);
+    }
+    if date == last_monday(year, 5) {
+        holiday("SpringBank", false);
+    }
+    if date == last_monday(year, 8) {
+        holiday("LateSummerBankHolidayNotScotland", false);
+    }
+    if date == christmas || date == boxing_day {
+        holiday(
+            if date == christmas {
+                "ChristmasDay"
+            } else {
+                "BoxingDay"
+            },
+            false,
+        );
+    }
+    // Christmas and Boxing Day falling on a weekend move to the next free
+    // weekdays, Christmas first.
+    let christmas_holiday = is_weekend(christmas).then(|| next_weekday(boxing_day));
+    let boxing_day_holiday = is_weekend(boxing_day).then(|| {
+        let after = next_weekday(boxing_day);
+        if Some(after) == christmas_holiday {
+            next_weekday(after)
+        } else {
+            after
+        }
+    });
+    if Some(date) == christmas_holiday {
+        holiday("ChristmasDayHoliday", true);
+    }
+    if Some(date) == boxing_day_holiday {
+        holiday("BoxingDayHoliday", true);
+    }
+
+    if date == christmas || date == boxing_day {
+        names.push("Christmas");
+    } else if !names.is_empty() {
+        names.push("AllHolidaysExceptChristmas");
+    }
+    if date == ymd(12, 24) {
+        names.extend(["ChristmasEve", "EarlyRunOff"]);
+    }
+    if date == ymd(12, 31) {
+        names.extend(["NewYearsEve", "EarlyRunOff"]);
+    }
+    names
+}
+
+fn next_weekday(date: NaiveDate) -> NaiveDate {
+    let mut next = date + Duration::days(1);
+    while matches!(next.weekday(), Weekday::Sat | Weekday::Sun) {
+        next += Duration::days(1);
+    }
+    next
+}
+
+fn nth_monday(year: i32, month: u32, n: u8) -> NaiveDate {
+    NaiveDate::from_weekday_of_month_opt(year, month, Weekday::Mon, n).unwrap()
+}
+
+fn last_monday(year: i32, month: u32) -> NaiveDate {
+    NaiveDate::from_weekday_of_month_opt(year, month, Weekday::Mon, 5)
+        .unwrap_or_else(|| nth_monday(year, month, 4))
+}
+
+// The anonymous Gregorian algorithm.
+fn easter_sunday(year: i32) -> NaiveDate {
+    let a = year % 19;
+    let b = year / 100;
+    let c = year % 100;
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+    NaiveDate::from_ymd_opt(year, month as u32, day as u32).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bank_holidays() {
+        let holidays: Vec<_> = NaiveDate::from_ymd_opt(2021, 1, 1)
+            .unwrap()
+            .iter_days()
+            .take_while(|date| date.year() == 2021)
+            .filter_map(|date| {
+                let names = bank_holidays_on(date);
+                names
+                    .contains(&"AllBankHolidays")
+                    .then(|| (date.format("%m-%d").to_string(), names[0]))
+            })
+            .collect();
+        // Christmas and Boxing Day fell on a weekend.
+        assert_eq!(
+            holidays,
+            [
+                ("01-01", "NewYearsDay"),
+                ("04-02", "GoodFriday"),
+                ("04-05", "EasterMonday"),
+                ("05-03", "MayDay"),
+                ("05-31", "SpringBank"),
+                ("08-30", "LateSummerBankHolidayNotScotland"),
+                ("12-25", "ChristmasDay"),
+                ("12-26", "BoxingDay"),
+                ("12-27", "ChristmasDayHoliday"),
+                ("12-28", "BoxingDayHoliday"),
+            ]
+            .map(|(date, name)| (date.to_string(), name))
+        );
+    }
+}
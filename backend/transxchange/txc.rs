@@ -0,0 +1,570 @@
+use std::{collections::HashMap, io::BufRead};
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use quick_xml::{events::Event, Reader};
+
+use crate::national_rail::xml_timetable::passenger_legs;
+
+use super::operating_profile::OperatingProfile;
+
+// TransXChange documents are a few megabytes per service, so they're read
+// into a tree and then interpreted.
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    attributes: HashMap<String, String>,
+    text: String,
+    children: Vec<Element>,
+}
+
+impl Element {
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+
+    /// The element at the end of `path`, e.g. `["Lines", "Line"]`.
+    fn find(&self, path: &[&str]) -> Option<&Element> {
+        path.iter()
+            .try_fold(self, |element, name| element.child(name))
+    }
+
+    fn text_at(&self, path: &[&str]) -> Option<&str> {
+        self.find(path).map(|e| e.text.as_str())
+    }
+
+    fn required(&self, path: &[&str]) -> Result<&str> {
+        self.text_at(path)
+            .ok_or_else(|| anyhow!("{} has no {}", self.name, path.join("/")))
+    }
+}
+
+fn read_tree(input: impl BufRead) -> Result<Element> {
+    let mut reader = Reader::from_reader(input);
+    reader.trim_text(true);
+    let mut buf = vec![];
+    let mut stack = vec![Element::default()];
+    loop {
+        match reader.read_event(&mut buf)? {
+            Event::Start(e) => stack.push(element(&reader, &e)?),
+            Event::Empty(e) => {
+                let element = element(&reader, &e)?;
+                stack.last_mut().unwrap().children.push(element);
+            }
+            Event::Text(e) => {
+                let text = e.unescape_and_decode(&reader)?;
+                stack.last_mut().unwrap().text.push_str(&text);
+            }
+            Event::End(_) => {
+                let element = stack.pop().unwrap();
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None => bail!("Unbalanced XML"),
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    let mut document = stack.pop().unwrap();
+    document
+        .children
+        .pop()
+        .filter(|_| stack.is_empty())
+        .ok_or_else(|| anyhow!("Not a complete XML document"))
+}
+
+fn element<R: BufRead>(
+    reader: &Reader<R>,
+    start: &quick_xml::events::BytesStart,
+) -> Result<Element> {
+    let mut attributes = HashMap::new();
+    for attr in start.attributes() {
+        let attr = attr?;
+        attributes.insert(
+            String::from_utf8_lossy(attr.key).into_owned(),
+            attr.unescape_and_decode_value(reader)?,
+        );
+    }
+    Ok(Element {
+        name: String::from_utf8_lossy(start.local_name()).into_owned(),
+        attributes,
+        ..Default::default()
+    })
+}
+
+/// Parses ISO 8601 durations as TransXChange uses them, e.g. `PT1H2M30S`.
+fn parse_duration(text: &str) -> Result<Duration> {
+    let mut rest = text
+        .strip_prefix("PT")
+        .ok_or_else(|| anyhow!("Unsupported duration {:?}", text))?;
+    let mut duration = Duration::zero();
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(|| anyhow!("Unsupported duration {:?}", text))?;
+        let value: i64 = rest[..split].parse()?;
+        duration += match &rest[split..split + 1] {
+            "H" => Duration::hours(value),
+            "M" => Duration::minutes(value),
+            "S" => Duration::seconds(value),
+            _ => bail!("Unsupported duration {:?}", text),
+        };
+        rest = &rest[split + 1..];
+    }
+    Ok(duration)
+}
+
+fn parse_date(text: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(text, "%Y-%m-%d").with_context(|| format!("Invalid date {}", text))
+}
+
+fn parse_operating_profile(profile: &Element) -> Result<OperatingProfile> {
+    let mut parsed = OperatingProfile::default();
+    let regular = profile.child("RegularDayType");
+    if let Some(days) = regular.and_then(|r| r.child("DaysOfWeek")) {
+        for day in &days.children {
+            let weekdays: &[usize] = match day.name.as_str() {
+                "Monday" => &[0],
+                "Tuesday" => &[1],
+                "Wednesday" => &[2],
+                "Thursday" => &[3],
+                "Friday" => &[4],
+                "Saturday" => &[5],
+                "Sunday" => &[6],
+                "MondayToFriday" => &[0, 1, 2, 3, 4],
+                "MondayToSaturday" => &[0, 1, 2, 3, 4, 5],
+                "MondayToSunday" => &[0, 1, 2, 3, 4, 5, 6],
+                "NotMonday" => &[1, 2, 3, 4, 5, 6],
+                "NotTuesday" => &[0, 2, 3, 4, 5, 6],
+                "NotWednesday" => &[0, 1, 3, 4, 5, 6],
+                "NotThursday" => &[0, 1, 2, 4, 5, 6],
+                "NotFriday" => &[0, 1, 2, 3, 5, 6],
+                "NotSaturday" => &[0, 1, 2, 3, 4, 6],
+                "NotSunday" => &[0, 1, 2, 3, 4, 5],
+                "Weekend" => &[5, 6],
+                _ => bail!("Unknown day of the week {}", day.name),
+            };
+            for &weekday in weekdays {
+                parsed.days_of_week[weekday] = true;
+            }
+        }
+    }
+    parsed.holidays_only = regular.is_some_and(|r| r.child("HolidaysOnly").is_some());
+
+    let date_ranges = |path: &[&str]| -> Result<Vec<(NaiveDate, NaiveDate)>> {
+        profile
+            .find(path)
+            .map(|days| days.children("DateRange"))
+            .into_iter()
+            .flatten()
+            .map(|range| {
+                let start = parse_date(range.required(&["StartDate"])?)?;
+                let end = match range.text_at(&["EndDate"]) {
+                    Some(end) => parse_date(end)?,
+                    None => start,
+                };
+                Ok((start, end))
+            })
+            .collect()
+    };
+    parsed.special_operation = date_ranges(&["SpecialDaysOperation", "DaysOfOperation"])?;
+    parsed.special_non_operation = date_ranges(&["SpecialDaysOperation", "DaysOfNonOperation"])?;
+
+    let holidays = |path: &[&str]| -> Result<(Vec<String>, Vec<NaiveDate>)> {
+        let (mut names, mut dates) = (vec![], vec![]);
+        for holiday in profile.find(path).iter().flat_map(|e| &e.children) {
+            match holiday.name.as_str() {
+                "OtherPublicHoliday" => dates.push(parse_date(holiday.required(&["Date"])?)?),
+                name => names.push(name.to_string()),
+            }
+        }
+        Ok((names, dates))
+    };
+    (
+        parsed.bank_holiday_operation,
+        parsed.other_holiday_operation,
+    ) = holidays(&["BankHolidayOperation", "DaysOfOperation"])?;
+    (
+        parsed.bank_holiday_non_operation,
+        parsed.other_holiday_non_operation,
+    ) = holidays(&["BankHolidayOperation", "DaysOfNonOperation"])?;
+
+    if regular.is_none() || parsed.days_of_week == [false; 7] && !parsed.holidays_only {
+        parsed.days_of_week = [true; 7];
+    }
+    Ok(parsed)
+}
+
+#[derive(Debug, Clone)]
+struct TimingStop {
+    stop_id: String,
+    activity: String,
+    wait: Duration,
+}
+
+#[derive(Debug, Clone)]
+struct TimingLink {
+    id: Option<String>,
+    from: TimingStop,
+    to: TimingStop,
+    run_time: Duration,
+}
+
+fn parse_timing_stop(stop: &Element) -> Result<TimingStop> {
+    Ok(TimingStop {
+        stop_id: stop.required(&["StopPointRef"])?.to_string(),
+        activity: stop
+            .text_at(&["Activity"])
+            .unwrap_or("pickUpAndSetDown")
+            .to_string(),
+        wait: stop
+            .text_at(&["WaitTime"])
+            .map(parse_duration)
+            .transpose()?
+            .unwrap_or_else(Duration::zero),
+    })
+}
+
+fn parse_timing_link(link: &Element) -> Result<TimingLink> {
+    Ok(TimingLink {
+        id: link.attributes.get("id").cloned(),
+        from: parse_timing_stop(
+            link.child("From")
+                .ok_or_else(|| anyhow!("Timing link without From"))?,
+        )?,
+        to: parse_timing_stop(
+            link.child("To")
+                .ok_or_else(|| anyhow!("Timing link without To"))?,
+        )?,
+        run_time: parse_duration(link.required(&["RunTime"])?)?,
+    })
+}
+
+/// A stop of a trip, with times relative to midnight of the day it starts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TripStop {
+    pub stop_id: String,
+    pub arrival: Duration,
+    pub departure: Duration,
+    pub picks_up: bool,
+    pub sets_down: bool,
+}
+
+/// One vehicle journey of a TransXChange service.
+#[derive(Debug, Clone)]
+pub struct VehicleJourney {
+    /// The service code and vehicle journey code, unique across files.
+    pub id: String,
+    pub line_name: String,
+    pub mode: String,
+    pub start_date: NaiveDate,
+    pub end_date: Option<NaiveDate>,
+    pub operating_profile: OperatingProfile,
+    pub stops: Vec<TripStop>,
+}
+
+/// A ride between two stops of a trip on a given day.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TripLeg<'a> {
+    pub origin: &'a str,
+    pub destination: &'a str,
+    pub departure: NaiveDateTime,
+    pub arrival: NaiveDateTime,
+}
+
+impl VehicleJourney {
+    pub fn runs_on(&self, date: NaiveDate) -> bool {
+        self.start_date <= date
+            && self.end_date.is_none_or(|end| date <= end)
+            && self.operating_profile.runs_on(date)
+    }
+
+    /// The legs passengers can ride if the trip starts on `date`, from every
+    /// stop it picks up at to the next one it sets down at.
+    pub fn legs(&self, date: NaiveDate) -> Vec<TripLeg<'_>> {
+        let midnight = date.and_time(NaiveTime::MIN);
+        passenger_legs(&self.stops, |s| s.picks_up, |s| s.sets_down)
+            .into_iter()
+            .map(|(i, j)| TripLeg {
+                origin: &self.stops[i].stop_id,
+                destination: &self.stops[j].stop_id,
+                departure: midnight + self.stops[i].departure,
+                arrival: midnight + self.stops[j].arrival,
+            })
+            .collect()
+    }
+}
+
+/// The vehicle journeys of a TransXChange document.
+///
+/// Vehicle journeys follow their journey pattern's timing links, with the
+/// run and wait times they override, and run on their own operating
+/// profile or else their service's.
+pub fn read_vehicle_journeys(input: impl BufRead) -> Result<Vec<VehicleJourney>> {
+    let document = read_tree(input)?;
+    if document.name != "TransXChange" {
+        bail!("Not a TransXChange document, found {}", document.name);
+    }
+
+    let mut sections: HashMap<&str, Vec<TimingLink>> = HashMap::new();
+    for section in document
+        .find(&["JourneyPatternSections"])
+        .iter()
+        .flat_map(|s| s.children("JourneyPatternSection"))
+    {
+        let id = section
+            .attributes
+            .get("id")
+            .ok_or_else(|| anyhow!("Journey pattern section without an id"))?;
+        let links = section
+            .children("JourneyPatternTimingLink")
+            .map(parse_timing_link)
+            .collect::<Result<_>>()
+            .with_context(|| format!("In journey pattern section {}", id))?;
+        sections.insert(id, links);
+    }
+
+    struct ServiceInfo<'a> {
+        code: &'a str,
+        mode: &'a str,
+        lines: HashMap<&'a str, &'a str>,
+        start_date: NaiveDate,
+        end_date: Option<NaiveDate>,
+        operating_profile: OperatingProfile,
+        journey_patterns: HashMap<&'a str, Vec<&'a str>>,
+    }
+    let mut services: HashMap<&str, ServiceInfo> = HashMap::new();
+    for service in document
+        .find(&["Services"])
+        .iter()
+        .flat_map(|s| s.children("Service"))
+    {
+        let code = service.required(&["ServiceCode"])?;
+        let lines = service
+            .find(&["Lines"])
+            .iter()
+            .flat_map(|l| l.children("Line"))
+            .filter_map(|line| {
+                Some((
+                    line.attributes.get("id")?.as_str(),
+                    line.text_at(&["LineName"])?,
+                ))
+            })
+            .collect();
+        let journey_patterns = service
+            .find(&["StandardService"])
+            .iter()
+            .flat_map(|s| s.children("JourneyPattern"))
+            .filter_map(|pattern| {
+                Some((
+                    pattern.attributes.get("id")?.as_str(),
+                    pattern
+                        .children("JourneyPatternSectionRefs")
+                        .map(|r| r.text.as_str())
+                        .collect(),
+                ))
+            })
+            .collect();
+        services.insert(
+            code,
+            ServiceInfo {
+                code,
+                mode: service.text_at(&["Mode"]).unwrap_or("bus"),
+                lines,
+                start_date: parse_date(service.required(&["OperatingPeriod", "StartDate"])?)?,
+                end_date: service
+                    .text_at(&["OperatingPeriod", "EndDate"])
+                    .map(parse_date)
+                    .transpose()?,
+                operating_profile: match service.child("OperatingProfile") {
+                    Some(profile) => parse_operating_profile(profile)?,
+                    None => OperatingProfile::every_day(),
+                },
+                journey_patterns,
+            },
+        );
+    }
+
+    let mut trips = vec![];
+    for journey in document
+        .find(&["VehicleJourneys"])
+        .iter()
+        .flat_map(|j| j.children("VehicleJourney"))
+    {
+        let code = journey.required(&["VehicleJourneyCode"])?;
+        let trip = (|| -> Result<VehicleJourney> {
+            let service = services
+                .get(journey.required(&["ServiceRef"])?)
+                .ok_or_else(|| anyhow!("Unknown service"))?;
+            let pattern = journey.required(&["JourneyPatternRef"])?;
+            let section_ids = service
+                .journey_patterns
+                .get(pattern)
+                .ok_or_else(|| anyhow!("Unknown journey pattern {}", pattern))?;
+            let mut links = vec![];
+            for id in section_ids {
+                links.extend(
+                    sections
+                        .get(id)
+                        .ok_or_else(|| anyhow!("Unknown journey pattern section {}", id))?
+                        .iter()
+                        .cloned(),
+                );
+            }
+            apply_overrides(&mut links, journey)?;
+
+            let departure =
+                NaiveTime::parse_from_str(journey.required(&["DepartureTime"])?, "%H:%M:%S")?;
+            let start = Duration::seconds(departure.num_seconds_from_midnight().into())
+                + journey
+                    .text_at(&["DepartureDayShift"])
+                    .map(|shift| shift.parse().map(Duration::days))
+                    .transpose()?
+                    .unwrap_or_else(Duration::zero);
+
+            let line_name = journey
+                .text_at(&["LineRef"])
+                .and_then(|line| service.lines.get(line))
+                .or_else(|| service.lines.values().next())
+                .copied()
+                .unwrap_or(service.code);
+            Ok(VehicleJourney {
+                id: format!("{}:{}", service.code, code),
+                line_name: line_name.to_string(),
+                mode: service.mode.to_string(),
+                start_date: service.start_date,
+                end_date: service.end_date,
+                operating_profile: match journey.child("OperatingProfile") {
+                    Some(profile) => parse_operating_profile(profile)?,
+                    None => service.operating_profile.clone(),
+                },
+                stops: trip_stops(&links, start),
+            })
+        })()
+        .with_context(|| format!("In vehicle journey {}", code))?;
+        trips.push(trip);
+    }
+    Ok(trips)
+}
+
+// A vehicle journey's timing links override the run and wait times of the
+// journey pattern's links they refer to.
+fn apply_overrides(links: &mut [TimingLink], journey: &Element) -> Result<()> {
+    for timing in journey.children("VehicleJourneyTimingLink") {
+        let link_id = timing.required(&["JourneyPatternTimingLinkRef"])?;
+        let link = match links.iter_mut().find(|l| l.id.as_deref() == Some(link_id)) {
+            Some(link) => link,
+            None => continue,
+        };
+        if let Some(run_time) = timing.text_at(&["RunTime"]) {
+            link.run_time = parse_duration(run_time)?;
+        }
+        if let Some(wait) = timing.text_at(&["From", "WaitTime"]) {
+            link.from.wait = parse_duration(wait)?;
+        }
+        if let Some(wait) = timing.text_at(&["To", "WaitTime"]) {
+            link.to.wait = parse_duration(wait)?;
+        }
+    }
+    Ok(())
+}
+
+// A stop waits for the `To` wait time of the link arriving at it and then
+// for the `From` wait time of the link leaving it.
+fn trip_stops(links: &[TimingLink], start: Duration) -> Vec<TripStop> {
+    let mut stops: Vec<TripStop> = vec![];
+    let mut time = start;
+    for (i, link) in links.iter().enumerate() {
+        if i == 0 {
+            stops.push(TripStop {
+                stop_id: link.from.stop_id.clone(),
+                arrival: time,
+                departure: time,
+                picks_up: false,
+                sets_down: false,
+            });
+        }
+        let from = stops.last_mut().unwrap();
+        time += link.from.wait;
+        from.departure = time;
+        (from.picks_up, from.sets_down) = activity(&link.from.activity);
+        time += link.run_time;
+        let (picks_up, sets_down) = activity(&link.to.activity);
+        stops.push(TripStop {
+            stop_id: link.to.stop_id.clone(),
+            arrival: time,
+            departure: time,
+            picks_up,
+            sets_down,
+        });
+        time += link.to.wait;
+        stops.last_mut().unwrap().departure = time;
+    }
+    stops
+}
+
+fn activity(activity: &str) -> (bool, bool) {
+    match activity {
+        "pickUp" => (true, false),
+        "setDown" => (false, true),
+        "pass" => (false, false),
+        _ => (true, true),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, io::BufReader, path::Path};
+
+    use super::*;
+
+    #[test]
+    fn test_read_vehicle_journeys() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/transxchange/sample.xml");
+        let trips = read_vehicle_journeys(BufReader::new(File::open(path).unwrap())).unwrap();
+        assert_eq!(trips.len(), 3);
+        let trip = |id: &str| trips.iter().find(|t| t.id == id).unwrap();
+        let date = |month, day| NaiveDate::from_ymd_opt(2022, month, day).unwrap();
+        let at = |date: NaiveDate, hour, min| date.and_hms_opt(hour, min, 0).unwrap();
+
+        let weekday = trip("9-HAM:VJ1");
+        assert_eq!(weekday.line_name, "9");
+        // Not on the early May bank holiday.
+        assert!(!weekday.runs_on(date(5, 2)));
+        assert!(weekday.runs_on(date(5, 3)));
+        assert!(!weekday.runs_on(date(5, 7)));
+        let legs = weekday.legs(date(5, 3));
+        let times: Vec<_> = legs
+            .iter()
+            .map(|leg| (leg.origin, leg.departure.time(), leg.arrival.time()))
+            .collect();
+        let time = |hour, min| NaiveTime::from_hms_opt(hour, min, 0).unwrap();
+        // The bus waits a minute at Ravenscourt Park.
+        assert_eq!(
+            times,
+            vec![
+                ("490007705S", time(10, 0), time(10, 2)),
+                ("490008866E", time(10, 2), time(10, 5)),
+                ("490011760E", time(10, 6), time(10, 10)),
+            ]
+        );
+
+        let late = trip("9-HAM:VJ2");
+        assert!(!late.runs_on(date(5, 7)));
+        assert!(late.runs_on(date(5, 14)));
+        let legs = late.legs(date(5, 14));
+        // The second link runs for 5 minutes instead of 3, past midnight.
+        assert_eq!(legs[1].departure, at(date(5, 15), 0, 0));
+        assert_eq!(legs[1].arrival, at(date(5, 15), 0, 5));
+
+        let holiday = trip("9-HAM:VJ3");
+        assert!(holiday.runs_on(date(8, 29)));
+        assert!(!holiday.runs_on(date(8, 30)));
+    }
+}
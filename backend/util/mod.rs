@@ -1,2 +1,3 @@
 pub mod min_scored;
 pub mod string_util;
+pub mod time;
@@ -0,0 +1,127 @@
+use std::ops::{Add, AddAssign, Sub};
+
+use anyhow::{bail, Context, Result};
+use chrono::{Local, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use serde::{Deserialize, Serialize};
+
+/// A duration, or a time of day as the seconds since a midnight, which is
+/// what routing is done in. Timetables are to the minute and travel times
+/// are given in minutes, but rounding every hop of a journey to the minute
+/// adds up.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Seconds(pub u32);
+
+impl Seconds {
+    pub const DAY: Seconds = Seconds(24 * 60 * 60);
+
+    pub fn from_minutes(minutes: u32) -> Self {
+        Seconds(minutes * 60)
+    }
+
+    /// Rounded to the nearest second.
+    pub fn from_fractional_minutes(minutes: f64) -> Self {
+        Seconds((minutes * 60.).round().max(0.) as u32)
+    }
+
+    pub fn of_day(time: NaiveTime) -> Self {
+        Seconds(time.num_seconds_from_midnight())
+    }
+
+    /// Rounded to the nearest minute.
+    pub fn minutes(self) -> u16 {
+        ((self.0 + 30) / 60).min(u16::MAX.into()) as u16
+    }
+
+    /// The minutes since midnight of the first whole minute from now.
+    pub fn next_minute(self) -> u32 {
+        self.0.div_ceil(60)
+    }
+
+    /// The minute of the day this time falls in.
+    pub fn minute_of_day(self) -> u16 {
+        (self.0 % Self::DAY.0 / 60) as u16
+    }
+}
+
+impl Add for Seconds {
+    type Output = Seconds;
+
+    fn add(self, other: Seconds) -> Seconds {
+        Seconds(self.0 + other.0)
+    }
+}
+
+impl AddAssign for Seconds {
+    fn add_assign(&mut self, other: Seconds) {
+        self.0 += other.0;
+    }
+}
+
+impl Sub for Seconds {
+    type Output = Seconds;
+
+    fn sub(self, other: Seconds) -> Seconds {
+        Seconds(self.0 - other.0)
+    }
+}
+
+/// Parses a departure time given as HH:MM, or "now" for the current local
+/// time, on `date_str` (YYYY-MM-DD) or today.
+pub fn parse_start(time_str: &str, date_str: Option<&str>) -> Result<NaiveDateTime> {
+    if time_str == "now" {
+        if date_str.is_some() {
+            bail!("A date can't be given for now");
+        }
+        let now = Local::now().naive_local();
+        return Ok(now
+            .date()
+            .and_time(NaiveTime::from_hms_opt(now.hour(), now.minute(), 0).unwrap()));
+    }
+    let time = NaiveTime::parse_from_str(time_str, "%H:%M")
+        .with_context(|| format!("Invalid time {}, expected HH:MM or now", time_str))?;
+    let date = match date_str {
+        Some(date_str) => NaiveDate::parse_from_str(date_str, "%Y-%m-%d")
+            .with_context(|| format!("Invalid date {}, expected YYYY-MM-DD", date_str))?,
+        None => Local::now().date_naive(),
+    };
+    Ok(date.and_time(time))
+}
+
+/// Serializes `Seconds` as whole minutes, for clients that count in them.
+pub mod as_minutes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::Seconds;
+
+    pub fn serialize<S: Serializer>(duration: &Seconds, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u16(duration.minutes())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Seconds, D::Error> {
+        Ok(Seconds::from_minutes(
+            u16::deserialize(deserializer)?.into(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seconds() {
+        let hop = Seconds::from_fractional_minutes(1.39);
+        assert_eq!(hop, Seconds(83));
+        assert_eq!(hop.minutes(), 1);
+        assert_eq!(Seconds(90).minutes(), 2);
+        assert_eq!(Seconds(0).next_minute(), 0);
+        assert_eq!(Seconds(61).next_minute(), 2);
+        assert_eq!((Seconds::DAY + Seconds(61)).minute_of_day(), 1);
+        assert_eq!(
+            Seconds::of_day(NaiveTime::from_hms_opt(10, 1, 30).unwrap()),
+            Seconds(36090)
+        );
+    }
+}
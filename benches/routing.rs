@@ -0,0 +1,141 @@
+//! Routing benchmarks on a synthetic London-sized network.
+//!
+//! Compare against the committed baseline with
+//! `cp -r benches/baseline/. target/criterion/ && cargo bench --bench routing -- --baseline committed`.
+//!
+//! The `departures` group compares the ways departures can be stored, and
+//! prints how much memory each takes.
+
+use std::time::Duration;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use geo::Point;
+use travel_time::{
+    config::{DepartureStorage, RoutingConfig},
+    graph::{
+        connection::DepartureTime, disruptions::Disruptions, location::Location,
+        synthetic::SyntheticNetwork,
+    },
+};
+
+fn start() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(2022, 5, 2)
+        .unwrap()
+        .and_hms_opt(8, 30, 0)
+        .unwrap()
+}
+
+fn routing(c: &mut Criterion) {
+    let network = SyntheticNetwork::london();
+    let routing = RoutingConfig::default();
+    let start = start();
+    let disruptions = Disruptions::default();
+
+    let mut group = c.benchmark_group("routing");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(20));
+
+    group.bench_function("build_graph", |b| {
+        b.iter_batched(
+            || network.generate(),
+            |snapshot| snapshot.build_graph(&routing).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+
+    let snapshot = network.generate();
+    let origins: Vec<String> = snapshot
+        .connections
+        .iter()
+        .step_by(snapshot.connections.len() / 20)
+        .map(|c| c.origin.clone())
+        .take(20)
+        .collect();
+    let mut graph = snapshot.build_graph(&routing).unwrap();
+
+    group.bench_function("single_origin", |b| {
+        b.iter(|| {
+            graph
+                .tt_from_stop_id(origins[0].clone(), start, &disruptions)
+                .unwrap()
+        })
+    });
+
+    // Three friends meeting up, from the middle and either side of London.
+    let locations = [(51.515, -0.141), (51.462, -0.301), (51.541, 0.002)];
+    group.bench_function("multi_origin", |b| {
+        b.iter(|| {
+            let locations = locations
+                .iter()
+                .map(|(lat, lon)| Location(Point::new(*lat, *lon)))
+                .collect();
+            graph.travel_times_from_locs(locations, start, &disruptions)
+        })
+    });
+
+    group.bench_function("matrix_20_origins", |b| {
+        b.iter(|| {
+            origins
+                .iter()
+                .map(|origin| {
+                    graph
+                        .tt_from_stop_id(origin.clone(), start, &disruptions)
+                        .unwrap()
+                        .len()
+                })
+                .sum::<usize>()
+        })
+    });
+
+    group.finish();
+}
+
+fn departures(c: &mut Criterion) {
+    let network = SyntheticNetwork::london();
+    let snapshot = network.generate();
+    let origin = snapshot.connections[0].origin.clone();
+    let disruptions = Disruptions::default();
+
+    let mut group = c.benchmark_group("departures");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(20));
+
+    for (name, storage) in [
+        ("table", DepartureStorage::Table),
+        ("sorted", DepartureStorage::Sorted),
+    ] {
+        group.bench_function(format!("build/{}", name), |b| {
+            b.iter(|| {
+                snapshot
+                    .connections
+                    .iter()
+                    .map(|con| DepartureTime::new(&con.departure_times, storage))
+                    .collect::<Vec<_>>()
+            })
+        });
+
+        let routing = RoutingConfig {
+            departure_storage: storage,
+            ..Default::default()
+        };
+        let graph = network.generate().build_graph(&routing).unwrap();
+        println!(
+            "departures/{}: {:.1} MB of departures",
+            name,
+            graph.departure_bytes() as f64 / 1e6
+        );
+        group.bench_function(format!("single_origin/{}", name), |b| {
+            b.iter(|| {
+                graph
+                    .tt_from_stop_id(origin.clone(), start(), &disruptions)
+                    .unwrap()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, routing, departures);
+criterion_main!(benches);